@@ -48,6 +48,12 @@ fn wayland_scan_local(scanner: &str, proto: &str) {
     wayland_scan(scanner, in_path, proto)
 }
 
+// Protocols from outside wayland-protocols and weston, kept in protocol/
+fn wayland_scan_vendored(scanner: &str, proto: &str) {
+    let in_path = Path::new("protocol").join(format!("{}.xml", proto));
+    wayland_scan(scanner, in_path, proto)
+}
+
 fn main() {
     let libdrm = Config::new().atleast_version("2.4.30").probe("libdrm").unwrap();
     let libudev = Config::new().atleast_version("136").probe("libudev").unwrap();
@@ -74,6 +80,11 @@ fn main() {
     wayland_scan_pkg(&wayland_scanner, &wayland_protos, "input-timestamps-unstable-v1");
     wayland_scan_pkg(&wayland_scanner, &wayland_protos, "fullscreen-shell-unstable-v1");
     wayland_scan_pkg(&wayland_scanner, &wayland_protos, "xdg-shell-unstable-v6");
+    wayland_scan_pkg(&wayland_scanner, &wayland_protos, "xdg-shell");
+    wayland_scan_pkg(&wayland_scanner, &wayland_protos, "xdg-decoration-unstable-v1");
+    wayland_scan_vendored(&wayland_scanner, "wlr-layer-shell-unstable-v1");
+    wayland_scan_vendored(&wayland_scanner, "wlr-foreign-toplevel-management-unstable-v1");
+    wayland_scan_vendored(&wayland_scanner, "ext-workspace-v1");
     wayland_scan_pkg(&wayland_scanner, &wayland_protos, "viewporter");
     wayland_scan_pkg(&wayland_scanner, &wayland_protos, "presentation-time");
     wayland_scan_local(&wayland_scanner, "text-cursor-position");
@@ -125,6 +136,8 @@ fn main() {
                           "protos/viewporter-protocol.c",
                           "protos/presentation-time-protocol.c",
                           "protos/text-cursor-position-protocol.c",
+                          "protos/wlr-foreign-toplevel-management-unstable-v1-protocol.c",
+                          "protos/ext-workspace-v1-protocol.c",
     ]);
     let build_logind = cfg!(target_os="linux") && env::var("CARGO_FEATURE_LOGIND").ok().is_some();
     if build_logind {
@@ -173,9 +186,13 @@ fn main() {
                                   "weston/libweston-desktop/seat.c",
                                   "weston/libweston-desktop/surface.c",
                                   "weston/libweston-desktop/wl-shell.c",
+                                  "weston/libweston-desktop/xdg-shell.c",
                                   "weston/libweston-desktop/xdg-shell-v6.c",
                                   "weston/libweston-desktop/xwayland.c",
+                                  "protos/xdg-shell-protocol.c",
                                   "protos/xdg-shell-unstable-v6-protocol.c",
+                                  "protos/xdg-decoration-unstable-v1-protocol.c",
+                                  "protos/wlr-layer-shell-unstable-v1-protocol.c",
     ]);
     libweston_desktop_build.include("config").include("protos").include("weston/shared").include("weston/libweston").include("weston")
         .flag_if_supported("-Wno-unused-parameter")
@@ -189,6 +206,41 @@ fn main() {
     include_pkg!(libweston_desktop_build << wayland_server);
     libweston_desktop_build.compile("libweston-desktop.a");
 
+    if env::var("CARGO_FEATURE_XWAYLAND").ok().is_some() {
+        let xcb = Config::new().probe("xcb").unwrap();
+        let xcb_xfixes = Config::new().probe("xcb-xfixes").unwrap();
+        let xcb_composite = Config::new().probe("xcb-composite").unwrap();
+        let xcursor = Config::new().probe("xcursor").unwrap();
+        let cairo_xcb = Config::new().probe("cairo-xcb").unwrap();
+        let mut xwayland_build = cc::Build::new();
+        xwayland_build.files(vec![
+                             "weston/xwayland/launcher.c",
+                             "weston/xwayland/window-manager.c",
+                             "weston/xwayland/selection.c",
+                             "weston/xwayland/dnd.c",
+                             "weston/xwayland/hash.c",
+        ]);
+        // Upstream this is a loadable module, its entry point would clash with other modules
+        xwayland_build.define("weston_module_init", "weston_xwayland_module_init");
+        xwayland_build.include("config").include("protos").include("weston/shared").include("weston/libweston").include("weston")
+            .flag_if_supported("-Wno-unused-parameter")
+            .flag_if_supported("-Wno-shift-negative-value")
+            .flag_if_supported("-Wno-missing-field-initializers")
+            .flag_if_supported("-fstack-protector-strong")
+            .flag_if_supported("-fvisibility=hidden")
+            .flag_if_supported("-flto=thin")
+            .flag_if_supported("-fsanitize=cfi,safe-stack");
+        include_pkg!(xwayland_build << pixman);
+        include_pkg!(xwayland_build << cairo);
+        include_pkg!(xwayland_build << wayland_server);
+        include_pkg!(xwayland_build << xcb);
+        include_pkg!(xwayland_build << xcb_xfixes);
+        include_pkg!(xwayland_build << xcb_composite);
+        include_pkg!(xwayland_build << xcursor);
+        include_pkg!(xwayland_build << cairo_xcb);
+        xwayland_build.compile("libweston-xwayland.a");
+    }
+
     bindgen::Builder::default()
         .impl_debug(true)
         .header("wrapper.h")
@@ -198,10 +250,16 @@ fn main() {
         .blacklist_type(r"^wl_.*$")
         .whitelist_type(r"^weston_.*$")
         .whitelist_function(r"^weston_.*$")
+        // shared/cairo-util.h, for server-side decorations
+        .whitelist_function(r"^(frame|theme)_.*$")
+        .whitelist_function(r"^cairo_(create|destroy|image_surface_create_for_data|surface_flush|surface_destroy)$")
+        // and for the window menu
+        .whitelist_function(r"^cairo_(set_source_rgba|rectangle|fill|paint|move_to|show_text|select_font_face|set_font_size|text_extents|font_extents)$")
+        .whitelist_var(r"^FRAME_BUTTON_.*$")
         .opaque_type("__va_list_tag")
         .ctypes_prefix("libc")
         .clang_args(&["-Iconfig", "-Iprotos", "-Iweston/shared", "-Iweston/libweston", "-Iweston"])
-        .clang_args(&[libdrm, libudev, gbm, libinput, pixman, xkbcommon, wayland_server, wayland_client, wayland_cursor, wayland_egl]
+        .clang_args(&[libdrm, libudev, gbm, libinput, pixman, xkbcommon, cairo, wayland_server, wayland_client, wayland_cursor, wayland_egl]
                     .iter().flat_map(|p| &p.include_paths).map(|p| format!("-I{}", p.to_str().unwrap())).collect::<Vec<_>>())
         .generate()
         .expect("bindgen")
@@ -31,3 +31,46 @@ include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 extern "C" {
     pub static launcher_logind_iface: launcher_interface;
 }
+
+// weston/xwayland, compiled with its module entry point renamed
+#[cfg(feature = "xwayland")]
+extern "C" {
+    pub fn weston_xwayland_module_init(compositor: *mut weston_compositor) -> libc::c_int;
+}
+
+// shared/os-compatibility.c, compiled into libweston
+extern "C" {
+    pub fn os_create_anonymous_file(size: libc::off_t) -> libc::c_int;
+}
+
+// protos/xdg-decoration-unstable-v1-protocol.c, compiled into libweston-desktop
+extern "C" {
+    pub static zxdg_decoration_manager_v1_interface: wl_interface;
+    pub static zxdg_toplevel_decoration_v1_interface: wl_interface;
+}
+
+// protos/wlr-layer-shell-unstable-v1-protocol.c, compiled into libweston-desktop (it refers to xdg_popup)
+extern "C" {
+    pub static zwlr_layer_shell_v1_interface: wl_interface;
+    pub static zwlr_layer_surface_v1_interface: wl_interface;
+}
+
+// protos/wlr-foreign-toplevel-management-unstable-v1-protocol.c, compiled into libweston
+extern "C" {
+    pub static zwlr_foreign_toplevel_manager_v1_interface: wl_interface;
+    pub static zwlr_foreign_toplevel_handle_v1_interface: wl_interface;
+}
+
+// protos/ext-workspace-v1-protocol.c, compiled into libweston
+extern "C" {
+    pub static ext_workspace_manager_v1_interface: wl_interface;
+    pub static ext_workspace_group_handle_v1_interface: wl_interface;
+    pub static ext_workspace_handle_v1_interface: wl_interface;
+}
+
+// libweston-desktop/seat.c (declared in its internal.h), for telling popup grabs apart.
+// The weston_desktop_seat is opaque.
+extern "C" {
+    pub fn weston_desktop_seat_from_seat(wseat: *mut weston_seat) -> *mut libc::c_void;
+    pub fn weston_desktop_seat_popup_grab_get_topmost_surface(seat: *mut libc::c_void) -> *mut weston_desktop_surface;
+}
@@ -14,3 +14,5 @@ extern const struct launcher_interface launcher_logind_iface;
 #include "weston/libweston/timeline-object.h"
 #include "weston/libweston/windowed-output-api.h"
 #include "weston/libweston-desktop/libweston-desktop.h"
+#include "weston/xwayland/xwayland-api.h"
+#include "weston/shared/cairo-util.h"
@@ -4,24 +4,20 @@
 
 #![feature(nll)]
 
-extern crate libc;
 extern crate loginw;
 #[macro_use]
 extern crate weston_rs;
-#[macro_use]
-extern crate lazy_static;
-extern crate mut_static;
 
-use std::{env, ffi, process, cell, any, cmp};
-use mut_static::MutStatic;
+use std::{env, ffi, process};
 use weston_rs::*;
+use weston_rs::shell::floating::{FloatingShell, FloatingSurface, FloatingConfigBuilder};
+use weston_rs::shell::keyboard::{self as keyboard_move, StepConfigBuilder};
+use weston_rs::shell::menu::{self as window_menu, WindowMenu, WindowMenuApi, MenuAction};
+use weston_rs::shell::minimize::{MinimizeManager, MinimizeApi};
+use weston_rs::shell::snap::SnapConfigBuilder;
+use weston_rs::shell::switcher;
 use loginw::priority;
 
-lazy_static! {
-    static ref COMPOSITOR: MutStatic<Compositor> = MutStatic::new();
-    static ref DESKTOP: MutStatic<Desktop<SurfaceContext>> = MutStatic::new();
-}
-
 weston_logger!{fn wlog(msg: &str) {
     eprint!("WESTON: {}", msg);
 }}
@@ -30,222 +26,22 @@ weston_logger!{fn wlog_continue(msg: &str) {
     eprint!("{}", msg);
 }}
 
-/// Mouse handler for moving windows
-struct MoveGrab<'a> {
-    dsurf: &'a mut DesktopSurfaceRef<SurfaceContext>,
-    dx: f64,
-    dy: f64,
-}
-
-impl<'a> PointerGrab for MoveGrab<'a> {
-    fn motion(&mut self, pointer: &mut PointerRef, _time: &libc::timespec, event: PointerMotionEvent) {
-        pointer.moove(event);
-        let sctx = self.dsurf.borrow_user_data().expect("user_data");
-        sctx.view.set_position((wl_fixed_to_double(pointer.x()) + self.dx) as f32, (wl_fixed_to_double(pointer.y()) + self.dy) as f32);
-        self.dsurf.surface().compositor_mut().schedule_repaint();
-    }
-
-    fn button(&mut self, pointer: &mut PointerRef, _time: &libc::timespec, _button: u32, state: ButtonState) {
-        if pointer.button_count() == 0 && state == ButtonState::Released {
-            pointer.end_grab();
-        }
-    }
-
-    fn cancel(&mut self, pointer: &mut PointerRef) {
-        pointer.end_grab();
-    }
-}
-
-/// Mouse handler for resizing windows
-struct ResizeGrab<'a> {
-    dsurf: &'a mut DesktopSurfaceRef<SurfaceContext>,
-    edges: Resize,
-    width: i32,
-    height: i32,
-}
-
-impl<'a> PointerGrab for ResizeGrab<'a> {
-    fn motion(&mut self, pointer: &mut PointerRef, _time: &libc::timespec, event: PointerMotionEvent) {
-        pointer.moove(event);
-        let sctx = self.dsurf.borrow_user_data().expect("user_data");
-        let (from_x, from_y) = sctx.view.from_global_fixed(pointer.grab_x(), pointer.grab_y());
-        let (to_x, to_y) = sctx.view.from_global_fixed(pointer.x(), pointer.y());
-        let mut width = self.width;
-        if self.edges.contains(Resize::Left) {
-            width += wl_fixed_to_int(from_x - to_x);
-        } else if self.edges.contains(Resize::Right) {
-            width += wl_fixed_to_int(to_x - from_x);
-        }
-        let mut height = self.height;
-        if self.edges.contains(Resize::Top) {
-            height += wl_fixed_to_int(from_y - to_y);
-        } else if self.edges.contains(Resize::Bottom) {
-            height += wl_fixed_to_int(to_y - from_y);
-        }
-        let mut min_size = self.dsurf.get_min_size();
-        min_size.width = cmp::max(1, min_size.width);
-        min_size.height = cmp::max(1, min_size.height);
-        let max_size = self.dsurf.get_max_size();
-        if width < min_size.width {
-            width = min_size.width;
-        } else if max_size.width > 0 && width > max_size.width {
-            width = max_size.width;
-        }
-        if height < min_size.height {
-            height = min_size.height;
-        } else if max_size.width > 0 && width > max_size.width {
-            // is it right that we're doing the width thing again, not height? (copied from weston desktop shell)
-            width = max_size.width;
-        }
-        self.dsurf.set_size(width, height);
-    }
-
-    fn button(&mut self, pointer: &mut PointerRef, _time: &libc::timespec, _button: u32, state: ButtonState) {
-        if pointer.button_count() == 0 && state == ButtonState::Released {
-            self.cancel(pointer);
-        }
-    }
-
-    fn cancel(&mut self, pointer: &mut PointerRef) {
-        let sctx = self.dsurf.borrow_user_data().expect("user_data");
-        self.dsurf.set_resizing(false);
-        sctx.resize_edges = Resize::None;
-        pointer.end_grab();
-    }
-}
-
-
 /// Per-surface user data for Desktop Surfaces (libweston-desktop's wrapper around surfaces)
-struct SurfaceContext {
-    view: View,
-    focus_count: i16,
-    /// Commit handling must check if a resize with top/left edges is happening
-    /// and move the surface accordingly
-    resize_edges: Resize,
-    last_width: f32,
-    last_height: f32,
-}
-
-/// User data for the Desktop API implementation
-struct DesktopImpl {
-    windows_layer: Layer,
-}
+type SurfaceContext = FloatingSurface;
 
-impl DesktopApi<SurfaceContext> for DesktopImpl {
-    fn as_any(&mut self) -> &mut any::Any { self }
+/// The default window menu actions (move, resize and close) are enough here
+struct MenuActions;
 
-    fn surface_added(&mut self, dsurf: &mut DesktopSurfaceRef<SurfaceContext>) {
-        let mut view = dsurf.create_view();
-        self.windows_layer.view_list_entry_insert(&mut view);
-        let mut compositor = COMPOSITOR.write().expect("compositor MutStatic");
-        dsurf.surface_mut().damage();
-        compositor.schedule_repaint();
-        dsurf.set_activated(true);
-        view.activate(&compositor.first_seat().expect("first_seat"), ActivateFlag::CONFIGURE);
-        let _ = dsurf.set_user_data(Box::new(SurfaceContext {
-            view,
-            resize_edges: Resize::None,
-            last_width: 0.0,
-            last_height: 0.0,
-            focus_count: 1,
-        }));
-    }
-
-    fn surface_removed(&mut self, dsurf: &mut DesktopSurfaceRef<SurfaceContext>) {
-        let mut sctx = dsurf.get_user_data().expect("user_data");
-        dsurf.unlink_view(&mut sctx.view);
-        // sctx dropped here, destroying the view
-    }
-
-    fn committed(&mut self, dsurf: &mut DesktopSurfaceRef<SurfaceContext>, _sx: i32, _sy: i32) {
-        let sctx = dsurf.borrow_user_data().expect("user_data");
-        let surface = dsurf.surface();
-        let (from_x, from_y) = sctx.view.from_global_float(0.0, 0.0);
-        let (to_x, to_y) = sctx.view.from_global_float(
-            if sctx.resize_edges.contains(Resize::Left) { sctx.last_width - surface.width() as f32 } else { 0.0 },
-            if sctx.resize_edges.contains(Resize::Top) { sctx.last_height - surface.height() as f32 } else { 0.0 },
-        );
-        let (orig_x, orig_y) = sctx.view.get_position();
-        sctx.view.set_position(orig_x + to_x - from_x, orig_y + to_y - from_y);
-        sctx.last_width = surface.width() as f32;
-        sctx.last_height = surface.height() as f32;
-    }
-
-    fn moove(&mut self, dsurf: &mut DesktopSurfaceRef<SurfaceContext>, seat: &mut SeatRef, serial: u32) {
-        let sctx = dsurf.borrow_user_data().expect("user_data");
-        if let Some(pointer) = seat.pointer_mut() {
-            if let Some(focus) = pointer.focus() {
-                if pointer.button_count() > 0 && serial == pointer.grab_serial() &&
-                    focus.surface().main_surface().as_ptr() == dsurf.surface().as_ptr() {
-                    let (view_x, view_y) = sctx.view.get_position();
-                    let grab = MoveGrab {
-                        dsurf: unsafe { DesktopSurfaceRef::from_ptr_mut(dsurf.as_ptr()) },
-                        dx: f64::from(view_x) - wl_fixed_to_double(pointer.grab_x()),
-                        dy: f64::from(view_y) - wl_fixed_to_double(pointer.grab_y()),
-                    };
-                    pointer.start_grab(grab);
-                }
-            }
-        }
-    }
-
-    fn resize(&mut self, dsurf: &mut DesktopSurfaceRef<SurfaceContext>, seat: &mut SeatRef, serial: u32, edges: Resize) {
-        if edges == Resize::None || edges.contains(Resize::Left | Resize::Right) || edges.contains(Resize::Top | Resize::Bottom) {
-            return
-        }
-        let sctx = dsurf.borrow_user_data().expect("user_data");
-        if let Some(pointer) = seat.pointer_mut() {
-            if let Some(focus) = pointer.focus() {
-                if pointer.button_count() > 0 && serial == pointer.grab_serial() &&
-                    focus.surface().main_surface().as_ptr() == dsurf.surface().as_ptr() {
-                    let geom = dsurf.get_geometry();
-                    let grab = ResizeGrab {
-                        dsurf: unsafe { DesktopSurfaceRef::from_ptr_mut(dsurf.as_ptr()) },
-                        edges,
-                        width: geom.width,
-                        height: geom.height,
-                    };
-                    dsurf.set_resizing(true);
-                    sctx.resize_edges = edges;
-                    pointer.start_grab(grab);
-                }
-            }
-        }
+impl WindowMenuApi<SurfaceContext> for MenuActions {
+    fn menu_action(&mut self, surface: &mut DesktopSurfaceRef<SurfaceContext>, seat: &mut SeatRef, action: MenuAction) {
+        window_menu::default_action(surface, seat, action);
     }
 }
 
-fn activate(view: &mut ViewRef, seat: &SeatRef, flags: ActivateFlag) {
-    // "cannot borrow *view as mutable" even with nll
-    let main_surf = unsafe { SurfaceRef::from_ptr(view.surface().main_surface().as_ptr()) };
-    if let Some(dsurf) = DesktopSurfaceRef::<SurfaceContext>::from_surface(&main_surf) {
-        let mut desktop = DESKTOP.write().expect("desktop MutStatic");
-        let desktop_impl = desktop.api().as_any().downcast_mut::<DesktopImpl>().expect("DesktopImpl downcast");
+/// Nothing else (like a taskbar) needs to know about minimized windows
+struct MinimizeActions;
 
-        view.activate(&seat, flags);
-
-        // Re-insert into the layer to put on top visually
-        if view.layer_link().layer.is_null() {
-            // Except for newly created surfaces (?)
-            // e.g. w/o this, clicking a GTK menu action that spawns a new window would freeze
-            return
-        }
-        view.geometry_dirty();
-        view.layer_entry_remove();
-        desktop_impl.windows_layer.view_list_entry_insert(view);
-        dsurf.propagate_layer();
-        view.geometry_dirty();
-        dsurf.surface_mut().damage();
-    }
-}
-
-fn click_activate(p: &mut PointerRef) {
-    if !p.is_default_grab() {
-        return;
-    }
-    if let Some(focus_view) = p.focus_mut() {
-        activate(focus_view, p.seat(), ActivateFlag::CONFIGURE | ActivateFlag::CLICKED);
-    }
-}
+impl MinimizeApi<SurfaceContext> for MinimizeActions {}
 
 enum SelectedBackend {
     Drm(DrmOutputImpl),
@@ -329,49 +125,24 @@ fn main() {
     let mut bg_view = View::new(&bg_surf);
     bg_layer.view_list_entry_insert(&mut bg_view);
 
-    // Layer for user applications
-    let mut windows_layer = Layer::new(&compositor);
-    windows_layer.set_position(POSITION_NORMAL);
-
-    // Our data for libweston-desktop stuff
-    let desktop_impl = Box::new(DesktopImpl {
-        windows_layer,
-    });
+    // Window management: moving and resizing (with snapping), click to focus, Alt-Tab,
+    // Alt+F7/Alt+F8 to move/resize with the keyboard, a window menu, minimizing
+    let floating_config = FloatingConfigBuilder::default()
+        .snap(Some(SnapConfigBuilder::default().build().unwrap()))
+        .build().unwrap();
+    let mut shell = FloatingShell::<SurfaceContext>::new(&mut compositor, floating_config);
+    let mut menu = WindowMenu::new(&mut compositor, Box::new(MenuActions)).expect("window menu");
+    menu.set_items(vec![MenuAction::Move, MenuAction::Resize, MenuAction::Close]);
+    shell.set_window_menu(Some(menu));
+    let minimize = MinimizeManager::new(&mut compositor, shell.focus(), Box::new(MinimizeActions));
+    shell.set_minimize_manager(Some(minimize));
+    switcher::bind(&mut compositor, shell.focus(), ev::KEY_TAB, KeyboardModifier::ALT);
+    keyboard_move::bind::<SurfaceContext>(&mut compositor, ev::KEY_F7, ev::KEY_F8, KeyboardModifier::ALT,
+                                          &StepConfigBuilder::default().build().unwrap());
 
     // The libweston-desktop object
     // NOTE: Important to keep around (do not do 'let _')
-    let desktop = Desktop::new(unsafe { CompositorRef::from_ptr(compositor.as_ptr()) }, desktop_impl);
-
-    // Left click to focus window
-    let _ = compositor.add_button_binding(ev::BTN_LEFT, KeyboardModifier::empty(), &|p, _, _| click_activate(p));
-    // Right click to focus window
-    let _ = compositor.add_button_binding(ev::BTN_RIGHT, KeyboardModifier::empty(), &|p, _, _| click_activate(p));
-
-    let focused_surface = cell::RefCell::new(None); // in desktop-shell this is part of seat state
-    WlListener::new(Box::new(move |p: &mut KeyboardRef| {
-
-        if let Some(old_focus) = focused_surface.replace(p.focus().map(|f| unsafe { SurfaceRef::from_ptr(f.as_ptr()) })) {
-            if let Some(dsurf) = DesktopSurfaceRef::<SurfaceContext>::from_surface(&old_focus) {
-                if let Some(sctx) = dsurf.borrow_user_data() {
-                    sctx.focus_count -= 1;
-                    if sctx.focus_count == 0 {
-                        dsurf.set_activated(false);
-                    }
-                }
-            }
-        }
-
-        if let Some(focus) = *focused_surface.borrow() {
-            if let Some(dsurf) = DesktopSurfaceRef::<SurfaceContext>::from_surface(&focus) {
-                if let Some(sctx) = dsurf.borrow_user_data() {
-                    if sctx.focus_count == 0 {
-                        dsurf.set_activated(true);
-                    }
-                    sctx.focus_count += 1;
-                }
-            }
-        }
-    })).signal_add(compositor.first_seat().expect("first_seat").keyboard().expect("first_seat keyboard").focus_signal());
+    let _desktop = Desktop::new(unsafe { CompositorRef::from_ptr_mut(compositor.as_ptr()) }, Box::new(shell));
 
     // Ctrl+Enter to spawn a terminal
     compositor.add_key_binding(ev::KEY_ENTER, KeyboardModifier::CTRL, &|_, _, _| {
@@ -392,7 +163,5 @@ fn main() {
 
     // Go!
     compositor.wake();
-    COMPOSITOR.set(compositor).expect("compositor MutStatic set");
-    DESKTOP.set(desktop).expect("desktop MutStatic set");
     event_loop.run();
 }
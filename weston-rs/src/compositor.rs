@@ -6,7 +6,7 @@ use libweston_sys::{
     weston_compositor_set_xkb_rule_names,
     weston_compositor_wake, weston_compositor_schedule_repaint,
     weston_compositor_flush_heads_changed, weston_compositor_add_heads_changed_listener,
-    weston_seat,
+    weston_seat, weston_output,
     weston_binding, weston_compositor_add_key_binding,
     weston_compositor_add_modifier_binding, weston_compositor_add_button_binding,
     weston_compositor_add_touch_binding, weston_compositor_add_axis_binding,
@@ -22,6 +22,7 @@ use libweston_sys::{
 };
 use xkbcommon::xkb;
 use xkbcommon::xkb::ffi::{xkb_rule_names, xkb_context_ref};
+use wayland_sys::common::wl_list;
 use wayland_sys::server::wl_signal;
 use foreign_types::{ForeignType, ForeignTypeRef};
 use wayland_server::{Display, EventLoop};
@@ -32,7 +33,7 @@ use ::seat::SeatRef;
 use ::pointer::{PointerRef, PointerAxisEvent, Axis};
 use ::keyboard::{KeyboardRef, KeyboardModifier};
 use ::touch::TouchRef;
-use ::output::Output;
+use ::output::{Output, OutputRef};
 use ::head::HeadRef;
 
 /// Opaque reference to a key/modifier/button/touch/axis/debug binding.
@@ -64,6 +65,24 @@ unsafe extern "C" fn run_axis_binding<F: FnMut(&mut PointerRef, &libc::timespec,
     cb(PointerRef::from_ptr_mut(pointer), &*time, (&*event).into());
 }
 
+pub struct SeatIterator<'a> {
+    compositor: &'a CompositorRef,
+    link: *mut wl_list,
+}
+
+impl<'a> Iterator for SeatIterator<'a> {
+    type Item = &'a mut SeatRef;
+
+    fn next(&mut self) -> Option<&'a mut SeatRef> {
+        self.link = unsafe { (*self.link).next };
+        if self.link == unsafe { &mut (*self.compositor.as_ptr()).seat_list as *mut _ } {
+            None
+        } else {
+            Some(unsafe { SeatRef::from_ptr_mut(wl_container_of!(self.link, weston_seat, link)) })
+        }
+    }
+}
+
 pub struct HeadIterator<'a> {
     compositor: &'a CompositorRef,
     head: *mut weston_head,
@@ -82,6 +101,24 @@ impl<'a> Iterator for HeadIterator<'a> {
     }
 }
 
+pub struct OutputIterator<'a> {
+    compositor: &'a CompositorRef,
+    link: *mut wl_list,
+}
+
+impl<'a> Iterator for OutputIterator<'a> {
+    type Item = &'a mut OutputRef;
+
+    fn next(&mut self) -> Option<&'a mut OutputRef> {
+        self.link = unsafe { (*self.link).next };
+        if self.link == unsafe { &mut (*self.compositor.as_ptr()).output_list as *mut _ } {
+            None
+        } else {
+            Some(unsafe { OutputRef::from_ptr_mut(wl_container_of!(self.link, weston_output, link)) })
+        }
+    }
+}
+
 foreign_type! {
     type CType = weston_compositor;
     fn drop = weston_compositor_destroy;
@@ -149,6 +186,21 @@ impl CompositorRef {
         unsafe { weston_compositor_add_heads_changed_listener(self.as_ptr(), &mut listener.wll); }
     }
 
+    pub fn iterate_seats(&self) -> SeatIterator {
+        SeatIterator {
+            compositor: self,
+            link: unsafe { &mut (*self.as_ptr()).seat_list },
+        }
+    }
+
+    /// Enabled outputs only
+    pub fn iterate_outputs(&self) -> OutputIterator {
+        OutputIterator {
+            compositor: self,
+            link: unsafe { &mut (*self.as_ptr()).output_list },
+        }
+    }
+
     pub fn iterate_heads(&mut self) -> HeadIterator {
         HeadIterator {
             compositor: self,
@@ -0,0 +1,245 @@
+//! Hiding the cursor while the mouse isn't being used.
+//!
+//! The cursor views are taken out of the compositor's cursor layer, clients are not told
+//! anything (their cursor surfaces stay as they are and come back on the next motion).
+
+use libc;
+use std::ptr;
+use std::rc::Rc;
+use std::cell::Cell;
+use libweston_sys::{weston_seat, weston_pointer, weston_keyboard_grab_interface};
+use foreign_types::ForeignTypeRef;
+use ::ev;
+use ::keyboard::{KeyboardGrab, KeyboardModifier, KeyboardRef, KeyState};
+use ::listener::{WlListener, ListenerList, SeatListeners};
+use ::object_map::ObjectMap;
+use ::pointer::PointerRef;
+use ::seat::SeatRef;
+use ::timer::Timer;
+
+#[derive(Builder)]
+pub struct CursorHideConfig {
+    /// Hide after this many milliseconds without pointer motion
+    #[builder(default)]
+    timeout_ms: Option<u32>,
+    /// Hide as soon as a (non-modifier) key is pressed
+    #[builder(default)]
+    hide_while_typing: bool,
+}
+
+thread_local! {
+    static HIDDEN: ObjectMap<weston_pointer, ()> = ObjectMap::new();
+}
+
+/// Whether a `CursorHider` currently keeps this pointer's cursor hidden.
+pub fn is_hidden(pointer: &PointerRef) -> bool {
+    HIDDEN.with(|h| h.contains(pointer.as_ptr()))
+}
+
+fn set_hidden(pointer: &mut PointerRef, hidden: bool) {
+    HIDDEN.with(|h| if hidden {
+        h.insert(pointer.as_ptr(), ());
+    } else {
+        h.remove(pointer.as_ptr());
+    });
+    super::refresh(pointer);
+}
+
+fn is_modifier_key(key: u32) -> bool {
+    match key {
+        ev::KEY_LEFTCTRL | ev::KEY_RIGHTCTRL | ev::KEY_LEFTSHIFT | ev::KEY_RIGHTSHIFT |
+        ev::KEY_LEFTALT | ev::KEY_RIGHTALT | ev::KEY_LEFTMETA | ev::KEY_RIGHTMETA |
+        ev::KEY_CAPSLOCK | ev::KEY_NUMLOCK => true,
+        _ => false,
+    }
+}
+
+struct HiderState {
+    config: CursorHideConfig,
+    /// Null once the seat is gone
+    seat: *mut weston_seat,
+    timer: Option<Timer>,
+    pointer_watched: bool,
+    /// The `TypingGrab`, the default grab it replaced and its way to the state
+    typing_grab: Option<(*const weston_keyboard_grab_interface, *const weston_keyboard_grab_interface, Rc<Cell<*mut HiderState>>)>,
+    listeners: Option<SeatListeners>,
+    // Listeners can't be freed while they run, these go with the state
+    dead: Vec<ListenerList>,
+}
+
+impl HiderState {
+    fn seat(&self) -> &mut SeatRef {
+        unsafe { SeatRef::from_ptr_mut(self.seat) }
+    }
+
+    fn hide(&mut self) {
+        if let Some(timer) = self.timer.as_mut() {
+            timer.disarm();
+        }
+        if self.seat.is_null() {
+            return
+        }
+        if let Some(pointer) = self.seat().pointer_mut() {
+            if !is_hidden(pointer) {
+                set_hidden(pointer, true);
+            }
+        }
+    }
+
+    fn show(&mut self) {
+        if self.seat.is_null() {
+            return
+        }
+        if let Some(pointer) = self.seat().pointer_mut() {
+            if is_hidden(pointer) {
+                set_hidden(pointer, false);
+            }
+        }
+        if let (Some(timer), Some(ms)) = (self.timer.as_mut(), self.config.timeout_ms) {
+            timer.arm(ms);
+        }
+    }
+
+    fn seat_destroyed(&mut self) {
+        // The devices are gone, and so is the keyboard's default grab
+        if let Some(listeners) = self.listeners.take() {
+            self.dead.push(listeners.destroyed());
+        }
+        if let Some(timer) = self.timer.as_mut() {
+            timer.disarm();
+        }
+        self.typing_grab = None;
+        self.seat = ptr::null_mut();
+    }
+}
+
+/// Passes everything to the keyboard's previous default grab, noticing key presses on the way.
+struct TypingGrab {
+    prev: *const weston_keyboard_grab_interface,
+    /// Null once the hider is dropped
+    state: Rc<Cell<*mut HiderState>>,
+}
+
+impl KeyboardGrab for TypingGrab {
+    fn key(&mut self, keyboard: &mut KeyboardRef, time: &libc::timespec, key: u32, state: KeyState) {
+        let hider = self.state.get();
+        if state == KeyState::Pressed && !is_modifier_key(key) && !hider.is_null() {
+            unsafe { (*hider).hide(); }
+        }
+        if let Some(key_fn) = unsafe { (*self.prev).key } {
+            unsafe { key_fn(keyboard.default_grab_ptr(), time, key, state.to_raw()); }
+        }
+    }
+
+    fn modifiers(&mut self, keyboard: &mut KeyboardRef, serial: u32,
+                 mods_depressed: KeyboardModifier, mods_latched: KeyboardModifier,
+                 mods_locked: KeyboardModifier, group: u32) {
+        if let Some(modifiers_fn) = unsafe { (*self.prev).modifiers } {
+            unsafe {
+                modifiers_fn(keyboard.default_grab_ptr(), serial,
+                             mods_depressed.bits(), mods_latched.bits(), mods_locked.bits(), group);
+            }
+        }
+    }
+
+    fn cancel(&mut self, keyboard: &mut KeyboardRef) {
+        if let Some(cancel_fn) = unsafe { (*self.prev).cancel } {
+            unsafe { cancel_fn(keyboard.default_grab_ptr()); }
+        }
+    }
+}
+
+fn watch_devices(state: *mut HiderState) {
+    let st = unsafe { &mut *state };
+    let seat = unsafe { SeatRef::from_ptr_mut(st.seat) };
+    if !st.pointer_watched {
+        if let (Some(pointer), Some(listeners)) = (seat.pointer_mut(), st.listeners.as_mut()) {
+            st.pointer_watched = true;
+            listeners.devices.add(WlListener::new(Box::new(move |_: &mut PointerRef| unsafe { (*state).show() })),
+                                  pointer.motion_signal());
+            // A new client under the pointer sets its cursor on enter
+            listeners.devices.add(WlListener::new(Box::new(|pointer: &mut PointerRef| if is_hidden(pointer) { super::refresh(pointer) })),
+                                  pointer.focus_signal());
+            listeners.devices.add(WlListener::new(Box::new(move |pointer: &mut PointerRef| {
+                HIDDEN.with(|h| h.remove(pointer.as_ptr()));
+                unsafe { (*state).pointer_watched = false; }
+            })), pointer.destroy_signal());
+        }
+        if st.pointer_watched {
+            st.show();
+        }
+    }
+    if st.typing_grab.is_none() && st.config.hide_while_typing {
+        if let Some(keyboard) = seat.keyboard_mut() {
+            // libweston keeps the keyboard struct around when the last device is unplugged
+            let prev = unsafe { (*keyboard.default_grab_ptr()).interface };
+            let shared = Rc::new(Cell::new(state));
+            keyboard.set_default_grab(TypingGrab { prev, state: Rc::clone(&shared) });
+            let grab = unsafe { (*keyboard.default_grab_ptr()).interface };
+            st.typing_grab = Some((grab, prev, shared));
+        }
+    }
+}
+
+/// Seat-level policy that hides the cursor after a period without pointer motion
+/// and/or when typing. The next motion brings it back.
+pub struct CursorHider {
+    state: Box<HiderState>,
+}
+
+impl CursorHider {
+    pub fn new(seat: &mut SeatRef, config: CursorHideConfig) -> CursorHider {
+        let mut state = Box::new(HiderState {
+            config,
+            seat: seat.as_ptr(),
+            timer: None,
+            pointer_watched: false,
+            typing_grab: None,
+            listeners: Some(SeatListeners::new(seat.as_ptr())),
+            dead: Vec::new(),
+        });
+        let state_ptr = &mut *state as *mut HiderState;
+        if state.config.timeout_ms.is_some() {
+            state.timer = Some(Timer::new(seat.compositor(), Box::new(move || unsafe { (*state_ptr).hide() })));
+        }
+        if let Some(listeners) = state.listeners.as_mut() {
+            // Devices can be plugged in later
+            listeners.own.add(WlListener::new(Box::new(move |_: &mut SeatRef| watch_devices(state_ptr))),
+                              seat.updated_caps_signal());
+            listeners.own.add(WlListener::new(Box::new(move |_: &mut SeatRef| unsafe { (*state_ptr).seat_destroyed() })),
+                              seat.destroy_signal());
+        }
+        watch_devices(state_ptr);
+        CursorHider { state }
+    }
+
+    pub fn hide(&mut self) {
+        self.state.hide();
+    }
+
+    pub fn show(&mut self) {
+        self.state.show();
+    }
+}
+
+impl Drop for CursorHider {
+    fn drop(&mut self) {
+        // Shows the cursor again and puts back the keyboard's previous default grab
+        if let Some((_, _, ref shared)) = self.state.typing_grab {
+            shared.set(ptr::null_mut());
+        }
+        if self.state.seat.is_null() {
+            return
+        }
+        let seat = unsafe { SeatRef::from_ptr_mut(self.state.seat) };
+        if let (Some(keyboard), Some((grab, prev, _))) = (seat.keyboard_mut(), self.state.typing_grab.take()) {
+            // Otherwise something else wraps the typing grab, which keeps passing keys on
+            unsafe { keyboard.restore_default_grab::<TypingGrab>(grab, prev); }
+        }
+        if let Some(pointer) = seat.pointer_mut() {
+            if is_hidden(pointer) {
+                set_hidden(pointer, false);
+            }
+        }
+    }
+}
@@ -0,0 +1,355 @@
+//! Compositor-drawn cursor images.
+//!
+//! Clients set their own cursors while the pointer is over them, but the compositor needs
+//! to show something on the desktop and during grabs (e.g. "move" while dragging a window).
+//! Call `init` once per compositor, then use `set_default` and `PointerRef::set_cursor`.
+//! `CursorHider` hides the cursor while the mouse isn't used (this works without `init` too).
+
+use std::{slice, mem};
+use std::mem::ManuallyDrop;
+use std::collections::HashMap;
+use libweston_sys::{weston_compositor, weston_pointer, weston_seat};
+use wayland_server::protocol::wl_shell_surface::Resize;
+use wayland_sys::common::wl_fixed_to_double;
+use wayland_sys::server::{wl_client, wl_resource_get_client};
+use foreign_types::ForeignTypeRef;
+use ::compositor::CompositorRef;
+use ::internal_client::{InternalClient, InternalSurface, ShmBuffer};
+use ::listener::{WlListener, ListenerList, SeatListeners};
+use ::object_map::ObjectMap;
+use ::pointer::PointerRef;
+use ::seat::SeatRef;
+use ::surface::SurfaceRef;
+use ::view::{View, ViewRef};
+
+pub mod xcursor;
+pub mod hide;
+
+pub use self::xcursor::*;
+pub use self::hide::*;
+
+/// Default size when neither the caller nor `XCURSOR_SIZE` says otherwise
+const DEFAULT_SIZE: u32 = 24;
+
+/// Names of the same cursor in the freedesktop (CSS) and legacy X11 naming schemes
+const ALIASES: &[&[&str]] = &[
+    &["default", "left_ptr", "arrow"],
+    &["move", "fleur", "grabbing", "closedhand", "all-scroll"],
+    &["grab", "openhand", "hand1"],
+    &["pointer", "hand2", "hand"],
+    &["text", "xterm", "ibeam"],
+    &["wait", "watch"],
+    &["progress", "left_ptr_watch"],
+    &["crosshair", "cross", "tcross"],
+    &["not-allowed", "crossed_circle", "forbidden"],
+    &["n-resize", "top_side"],
+    &["s-resize", "bottom_side"],
+    &["e-resize", "right_side"],
+    &["w-resize", "left_side"],
+    &["ne-resize", "top_right_corner"],
+    &["nw-resize", "top_left_corner"],
+    &["se-resize", "bottom_right_corner"],
+    &["sw-resize", "bottom_left_corner"],
+];
+
+/// The cursor to show while resizing from the given edges.
+pub fn name_for_resize_edges(edges: Resize) -> &'static str {
+    match edges {
+        Resize::Top => "n-resize",
+        Resize::Bottom => "s-resize",
+        Resize::Left => "w-resize",
+        Resize::Right => "e-resize",
+        Resize::TopLeft => "nw-resize",
+        Resize::TopRight => "ne-resize",
+        Resize::BottomLeft => "sw-resize",
+        Resize::BottomRight => "se-resize",
+        _ => "default",
+    }
+}
+
+struct CursorImage {
+    buffer: ShmBuffer,
+    hotspot_x: i32,
+    hotspot_y: i32,
+}
+
+struct PointerCursor {
+    // view first: it must be destroyed before the surface
+    view: Option<View>,
+    surface: InternalSurface,
+    shown: Option<String>,
+    hotspot_x: i32,
+    hotspot_y: i32,
+    override_name: Option<String>,
+}
+
+struct CursorState {
+    client: InternalClient,
+    theme: XcursorTheme,
+    size: u32,
+    images: HashMap<String, Option<CursorImage>>,
+    default_name: Option<String>,
+    pointers: HashMap<*mut weston_pointer, PointerCursor>,
+    /// The pointer's signals are in `devices`
+    seats: Vec<SeatListeners>,
+    /// On the compositor's seat_created_signal
+    listeners: ListenerList,
+    /// Listeners of destroyed seats, one of them was running when they were removed
+    dead: Vec<ListenerList>,
+    /// Runs when the state goes away, so it's never freed (once per compositor)
+    _destroy_listener: ManuallyDrop<Box<WlListener<CompositorRef>>>,
+}
+
+thread_local! {
+    static CURSORS: ObjectMap<weston_compositor, CursorState> = ObjectMap::new();
+}
+
+impl CursorState {
+    fn load_image(&mut self, name: &str) -> bool {
+        // No buffers yet, try again later
+        if !self.client.is_ready() {
+            return false
+        }
+        if !self.images.contains_key(name) {
+            let names = ALIASES.iter().find(|a| a.contains(&name)).map(|a| a.to_vec()).unwrap_or_else(|| vec![name]);
+            let frames = Some(name).into_iter().chain(names.into_iter())
+                .filter_map(|n| self.theme.load(n, self.size)).next();
+            // Animated cursors are shown as their first frame
+            let image = frames.and_then(|f| f.into_iter().next()).and_then(|frame| {
+                let pixels = unsafe { slice::from_raw_parts(frame.pixels.as_ptr() as *const u8, frame.pixels.len() * mem::size_of::<u32>()) };
+                self.client.create_buffer(frame.width as i32, frame.height as i32, pixels).map(|buffer| CursorImage {
+                    buffer,
+                    hotspot_x: frame.xhot as i32,
+                    hotspot_y: frame.yhot as i32,
+                })
+            });
+            self.images.insert(name.to_owned(), image);
+        }
+        self.images[name].is_some()
+    }
+
+    fn update(&mut self, pointer: &mut PointerRef) {
+        if hide::is_hidden(pointer) {
+            if let Some(pc) = self.pointers.get_mut(&pointer.as_ptr()) {
+                if let Some(ref mut view) = pc.view {
+                    unmap(view);
+                }
+            }
+            set_sprite_mapped(pointer, false);
+            return
+        }
+        let internal = self.client.client();
+        let over_client = pointer.focus().map(|f| has_client(f.surface(), internal)).unwrap_or(false);
+        let pc_override = self.pointers.get(&pointer.as_ptr()).and_then(|pc| pc.override_name.clone());
+        let wanted = if pc_override.is_some() {
+            pc_override
+        } else if !over_client {
+            self.default_name.clone()
+        } else {
+            None
+        };
+        let wanted = wanted.filter(|name| self.load_image(name));
+
+        let compositor = pointer.seat_mut().compositor_mut();
+        if let Some(name) = wanted {
+            if !self.pointers.contains_key(&pointer.as_ptr()) {
+                let surface = self.client.create_surface();
+                self.pointers.insert(pointer.as_ptr(), PointerCursor {
+                    view: None, surface, shown: None, hotspot_x: 0, hotspot_y: 0, override_name: None,
+                });
+            }
+            let pc = self.pointers.get_mut(&pointer.as_ptr()).expect("pointer cursor");
+            if pc.shown.as_ref() != Some(&name) {
+                let image = self.images[&name].as_ref().expect("loaded image");
+                pc.surface.attach(&image.buffer);
+                pc.hotspot_x = image.hotspot_x;
+                pc.hotspot_y = image.hotspot_y;
+                pc.shown = Some(name);
+            }
+            if pc.view.is_none() {
+                pc.view = pc.surface.surface().map(|s| View::new(s));
+            }
+            if let Some(ref mut view) = pc.view {
+                if view.layer_link().layer.is_null() {
+                    compositor.cursor_layer_mut().view_list_entry_insert(view);
+                }
+                view.set_position(
+                    (wl_fixed_to_double(pointer.x()) as i32 - pc.hotspot_x) as f32,
+                    (wl_fixed_to_double(pointer.y()) as i32 - pc.hotspot_y) as f32);
+                view.schedule_repaint();
+            }
+            // The client's cursor would be drawn on top of ours
+            set_sprite_mapped(pointer, false);
+        } else {
+            if let Some(pc) = self.pointers.get_mut(&pointer.as_ptr()) {
+                if let Some(ref mut view) = pc.view {
+                    unmap(view);
+                }
+            }
+            set_sprite_mapped(pointer, true);
+        }
+        compositor.schedule_repaint();
+    }
+
+    fn set_override(&mut self, pointer: &mut PointerRef, name: Option<&str>) -> bool {
+        if let Some(name) = name {
+            if !self.load_image(name) {
+                return false
+            }
+        }
+        if let Some(pc) = self.pointers.get_mut(&pointer.as_ptr()) {
+            pc.override_name = name.map(|n| n.to_owned());
+        } else if name.is_some() {
+            let surface = self.client.create_surface();
+            self.pointers.insert(pointer.as_ptr(), PointerCursor {
+                view: None, surface, shown: None, hotspot_x: 0, hotspot_y: 0,
+                override_name: name.map(|n| n.to_owned()),
+            });
+        }
+        self.update(pointer);
+        true
+    }
+}
+
+fn compositor_of(pointer: &PointerRef) -> *mut weston_compositor {
+    pointer.seat().compositor().as_ptr()
+}
+
+fn unmap(view: &mut ViewRef) {
+    if !view.layer_link().layer.is_null() {
+        view.damage_below();
+        view.layer_entry_remove();
+        view.schedule_repaint();
+    }
+}
+
+/// Puts the client's cursor view into the cursor layer or takes it out.
+fn set_sprite_mapped(pointer: &mut PointerRef, mapped: bool) {
+    let compositor = pointer.seat_mut().compositor_mut();
+    if let Some(sprite) = pointer.sprite_mut() {
+        if !mapped {
+            unmap(sprite);
+        } else if sprite.layer_link().layer.is_null() {
+            compositor.cursor_layer_mut().view_list_entry_insert(sprite);
+            sprite.geometry_dirty();
+            sprite.schedule_repaint();
+        }
+    }
+}
+
+/// Re-evaluates which cursor view should be in the cursor layer for the pointer.
+pub(crate) fn refresh(pointer: &mut PointerRef) {
+    let compositor = compositor_of(pointer);
+    if CURSORS.with(|c| c.contains(compositor)) {
+        update_pointer(pointer);
+        return
+    }
+    // Without compositor-drawn cursors, only the client's one can be shown
+    let hidden = hide::is_hidden(pointer);
+    set_sprite_mapped(pointer, !hidden);
+}
+
+/// Whether the surface belongs to an actual (external) client that manages its own cursor.
+fn has_client(surface: &SurfaceRef, internal: *mut wl_client) -> bool {
+    let resource = unsafe { (*surface.as_ptr()).resource };
+    !resource.is_null() && unsafe { wl_resource_get_client(resource) } != internal
+}
+
+fn watch_seat(state: &mut CursorState, seat: &mut SeatRef) {
+    let mut listeners = SeatListeners::new(seat.as_ptr());
+    // Real backends add the pointer after the seat
+    listeners.own.add(WlListener::new(Box::new(|seat: &mut SeatRef| {
+        let compositor = seat.compositor().as_ptr();
+        CURSORS.with(|c| c.with(compositor, |state| track_pointer(state, seat)));
+    })), seat.updated_caps_signal());
+    listeners.own.add(WlListener::new(Box::new(|seat: &mut SeatRef| {
+        let compositor = seat.compositor().as_ptr();
+        CURSORS.with(|c| c.with(compositor, |state| seat_destroyed(state, seat.as_ptr())));
+    })), seat.destroy_signal());
+    state.seats.push(listeners);
+    track_pointer(state, seat);
+}
+
+fn track_pointer(state: &mut CursorState, seat: &mut SeatRef) {
+    let seat_ptr = seat.as_ptr();
+    let listeners = match state.seats.iter_mut().find(|s| s.seat == seat_ptr) {
+        Some(listeners) => listeners,
+        None => return,
+    };
+    if let (true, Some(pointer)) = (listeners.devices.is_empty(), seat.pointer_mut()) {
+        listeners.devices.add(WlListener::new(Box::new(|pointer: &mut PointerRef| update_pointer(pointer))),
+                              pointer.motion_signal());
+        listeners.devices.add(WlListener::new(Box::new(|pointer: &mut PointerRef| update_pointer(pointer))),
+                              pointer.focus_signal());
+        listeners.devices.add(WlListener::new(Box::new(|pointer: &mut PointerRef| {
+            let compositor = compositor_of(pointer);
+            CURSORS.with(|c| c.with(compositor, |state| state.pointers.remove(&pointer.as_ptr())));
+        })), pointer.destroy_signal());
+    }
+}
+
+fn seat_destroyed(state: &mut CursorState, seat: *mut weston_seat) {
+    state.dead.clear();
+    if let Some(i) = state.seats.iter().position(|s| s.seat == seat) {
+        let listeners = state.seats.remove(i).destroyed();
+        state.dead.push(listeners);
+    }
+}
+
+fn update_pointer(pointer: &mut PointerRef) {
+    let compositor = compositor_of(pointer);
+    CURSORS.with(|c| c.with(compositor, |state| state.update(pointer)));
+}
+
+/// Sets up compositor-drawn cursors using an XCursor theme.
+///
+/// `theme` and `size` default to `XCURSOR_THEME`/`XCURSOR_SIZE`.
+/// The cursors show up once the `InternalClient` is ready.
+pub fn init(compositor: &mut CompositorRef, theme: Option<&str>, size: Option<u32>) -> bool {
+    if CURSORS.with(|c| c.contains(compositor.as_ptr())) {
+        return false
+    }
+    let client = match InternalClient::new(compositor) {
+        Some(client) => client,
+        None => return false,
+    };
+    let size = size
+        .or_else(|| ::std::env::var("XCURSOR_SIZE").ok().and_then(|s| s.parse().ok()))
+        .unwrap_or(DEFAULT_SIZE);
+    // The seats and their pointers outlive the state, it unlinks the listeners on them
+    let mut destroy_listener = WlListener::new(Box::new(|compositor: &mut CompositorRef| {
+        CURSORS.with(|c| c.remove(compositor.as_ptr()));
+    }));
+    destroy_listener.signal_add(compositor.destroy_signal());
+    let mut state = CursorState {
+        client,
+        theme: XcursorTheme::new(theme),
+        size,
+        images: HashMap::new(),
+        default_name: None,
+        pointers: HashMap::new(),
+        seats: Vec::new(),
+        listeners: ListenerList::new(),
+        dead: Vec::new(),
+        _destroy_listener: destroy_listener,
+    };
+    for seat in compositor.iterate_seats() {
+        watch_seat(&mut state, seat);
+    }
+    state.listeners.add(WlListener::new(Box::new(|seat: &mut SeatRef| {
+        let compositor = seat.compositor().as_ptr();
+        CURSORS.with(|c| c.with(compositor, |state| watch_seat(state, seat)));
+    })), compositor.seat_created_signal());
+    CURSORS.with(|c| c.insert(compositor.as_ptr(), state));
+    true
+}
+
+/// Sets the cursor shown when the pointer is not over a client (e.g. on the desktop background).
+pub fn set_default(compositor: &CompositorRef, name: Option<&str>) {
+    CURSORS.with(|c| c.with(compositor.as_ptr(), |state| state.default_name = name.map(|n| n.to_owned())));
+}
+
+pub(crate) fn set_override(pointer: &mut PointerRef, name: Option<&str>) -> bool {
+    let compositor = compositor_of(pointer);
+    CURSORS.with(|c| c.with(compositor, |state| state.set_override(pointer, name))).unwrap_or(false)
+}
@@ -0,0 +1,241 @@
+//! Loading of XCursor themes (the format used by basically every cursor theme on the desktop)
+//!
+//! Follows the lookup rules of libXcursor: themes are searched for in `XCURSOR_PATH`
+//! (or the usual icon directories), and `Inherits` from `index.theme` is honored.
+
+use std::{env, fs};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+const XCURSOR_MAGIC: u32 = 0x7275_6358; // "Xcur"
+const XCURSOR_IMAGE_TYPE: u32 = 0xfffd_0002;
+const XCURSOR_IMAGE_HEADER_LEN: usize = 36;
+
+/// One frame of a cursor.
+pub struct XcursorImage {
+    /// Nominal size this image was made for
+    pub size: u32,
+    pub width: u32,
+    pub height: u32,
+    pub xhot: u32,
+    pub yhot: u32,
+    /// Animation delay in milliseconds
+    pub delay: u32,
+    /// ARGB8888 premultiplied (what `wl_shm` wants)
+    pub pixels: Vec<u32>,
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    data.get(pos..pos + 4).map(|b| u32::from(b[0]) | u32::from(b[1]) << 8 | u32::from(b[2]) << 16 | u32::from(b[3]) << 24)
+}
+
+fn parse_image(data: &[u8], pos: usize) -> Option<XcursorImage> {
+    if read_u32(data, pos)? as usize != XCURSOR_IMAGE_HEADER_LEN || read_u32(data, pos + 4)? != XCURSOR_IMAGE_TYPE {
+        return None
+    }
+    let size = read_u32(data, pos + 8)?;
+    let width = read_u32(data, pos + 16)?;
+    let height = read_u32(data, pos + 20)?;
+    let xhot = read_u32(data, pos + 24)?;
+    let yhot = read_u32(data, pos + 28)?;
+    let delay = read_u32(data, pos + 32)?;
+    if width > 0x7fff || height > 0x7fff || xhot > width || yhot > height {
+        return None
+    }
+    let start = pos + XCURSOR_IMAGE_HEADER_LEN;
+    let len = width as usize * height as usize * 4;
+    let pixels = data.get(start..start + len)?.chunks(4)
+        .map(|b| u32::from(b[0]) | u32::from(b[1]) << 8 | u32::from(b[2]) << 16 | u32::from(b[3]) << 24)
+        .collect();
+    Some(XcursorImage { size, width, height, xhot, yhot, delay, pixels })
+}
+
+/// Parses all images from the contents of a cursor file.
+pub fn parse_xcursor(data: &[u8]) -> Option<Vec<XcursorImage>> {
+    if read_u32(data, 0)? != XCURSOR_MAGIC {
+        return None
+    }
+    let header_len = read_u32(data, 4)? as usize;
+    let ntoc = read_u32(data, 12)? as usize;
+    let mut images = Vec::new();
+    for i in 0..ntoc {
+        let entry = header_len + i * 12;
+        if read_u32(data, entry)? != XCURSOR_IMAGE_TYPE {
+            continue
+        }
+        if let Some(image) = parse_image(data, read_u32(data, entry + 8)? as usize) {
+            images.push(image);
+        }
+    }
+    Some(images)
+}
+
+/// Loads the frames closest to the wanted nominal size from a cursor file.
+pub fn load_xcursor(path: &Path, size: u32) -> Option<Vec<XcursorImage>> {
+    let images = parse_xcursor(&fs::read(path).ok()?)?;
+    let best = images.iter()
+        .map(|i| i.size)
+        .min_by_key(|s| (i64::from(*s) - i64::from(size)).abs())?;
+    Some(images.into_iter().filter(|i| i.size == best).collect())
+}
+
+fn default_search_path() -> Vec<PathBuf> {
+    let home = env::var("HOME").ok().map(PathBuf::from);
+    let xdg_data_home = env::var("XDG_DATA_HOME").ok().map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|h| h.join(".local/share")));
+    if let Ok(path) = env::var("XCURSOR_PATH") {
+        return path.split(':').filter(|p| !p.is_empty()).map(|p| {
+            if p.starts_with("~/") {
+                home.as_ref().map(|h| h.join(&p[2..])).unwrap_or_else(|| PathBuf::from(p))
+            } else {
+                PathBuf::from(p)
+            }
+        }).collect()
+    }
+    let mut result = Vec::new();
+    if let Some(d) = xdg_data_home {
+        result.push(d.join("icons"));
+    }
+    if let Some(h) = home {
+        result.push(h.join(".icons"));
+    }
+    result.push(PathBuf::from("/usr/share/icons"));
+    result.push(PathBuf::from("/usr/share/pixmaps"));
+    result.push(PathBuf::from("/usr/local/share/icons"));
+    result
+}
+
+fn theme_inherits(index: &Path) -> Vec<String> {
+    let file = match fs::File::open(index) {
+        Ok(f) => f,
+        Err(_) => return Vec::new(),
+    };
+    for line in BufReader::new(file).lines().filter_map(|l| l.ok()) {
+        let line = line.trim();
+        if !line.starts_with("Inherits") {
+            continue
+        }
+        if let Some(value) = line.splitn(2, '=').nth(1) {
+            return value.split(|c| c == ',' || c == ';')
+                .map(|t| t.trim().to_owned())
+                .filter(|t| !t.is_empty())
+                .collect()
+        }
+    }
+    Vec::new()
+}
+
+/// A named cursor theme, resolved lazily on lookup.
+pub struct XcursorTheme {
+    name: String,
+    search_path: Vec<PathBuf>,
+}
+
+impl XcursorTheme {
+    /// The theme from `XCURSOR_THEME` if no name is given, falling back to "default".
+    pub fn new(name: Option<&str>) -> XcursorTheme {
+        XcursorTheme {
+            name: name.map(|n| n.to_owned())
+                .or_else(|| env::var("XCURSOR_THEME").ok())
+                .unwrap_or_else(|| "default".to_owned()),
+            search_path: default_search_path(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Finds the file for a cursor, following theme inheritance.
+    pub fn find(&self, cursor: &str) -> Option<PathBuf> {
+        let mut visited = Vec::new();
+        self.find_in(&self.name, cursor, &mut visited)
+            .or_else(|| self.find_in("default", cursor, &mut visited))
+    }
+
+    fn find_in(&self, theme: &str, cursor: &str, visited: &mut Vec<String>) -> Option<PathBuf> {
+        if visited.iter().any(|t| t == theme) {
+            return None
+        }
+        visited.push(theme.to_owned());
+        for dir in &self.search_path {
+            let path = dir.join(theme).join("cursors").join(cursor);
+            if path.is_file() {
+                return Some(path)
+            }
+        }
+        for dir in &self.search_path {
+            for parent in theme_inherits(&dir.join(theme).join("index.theme")) {
+                if let Some(path) = self.find_in(&parent, cursor, visited) {
+                    return Some(path)
+                }
+            }
+        }
+        None
+    }
+
+    /// Loads the frames of a cursor at the nominal size closest to the given one.
+    pub fn load(&self, cursor: &str, size: u32) -> Option<Vec<XcursorImage>> {
+        load_xcursor(&self.find(cursor)?, size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x2 image at size 24, a comment and a 1x1 image at size 32
+    const CURSOR: &[u8] = &[
+        // header: magic, header length, version, number of TOC entries
+        0x58, 0x63, 0x75, 0x72, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x03, 0x00, 0x00, 0x00,
+        // TOC: type, subtype (nominal size), position
+        0x02, 0x00, 0xfd, 0xff, 0x18, 0x00, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00,
+        0x01, 0x00, 0xfe, 0xff, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x02, 0x00, 0xfd, 0xff, 0x20, 0x00, 0x00, 0x00, 0x68, 0x00, 0x00, 0x00,
+        // image at 52: header length, type, size, version, width, height, xhot, yhot, delay
+        0x24, 0x00, 0x00, 0x00, 0x02, 0x00, 0xfd, 0xff, 0x18, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+        0x02, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x32, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x80, 0x80, 0x80, 0x80,
+        // image at 104
+        0x24, 0x00, 0x00, 0x00, 0x02, 0x00, 0xfd, 0xff, 0x20, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+        0x33, 0x22, 0x11, 0xff,
+    ];
+
+    #[test]
+    fn parses_images() {
+        let images = parse_xcursor(CURSOR).expect("valid file");
+        assert_eq!(images.len(), 2);
+        let first = &images[0];
+        assert_eq!((first.size, first.width, first.height), (24, 2, 2));
+        assert_eq!((first.xhot, first.yhot, first.delay), (1, 0, 50));
+        assert_eq!(first.pixels, vec![0xff00_0000, 0xffff_ffff, 0, 0x8080_8080]);
+        let second = &images[1];
+        assert_eq!((second.size, second.width, second.height), (32, 1, 1));
+        assert_eq!(second.pixels, vec![0xff11_2233]);
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut data = CURSOR.to_vec();
+        data[0] = 0;
+        assert!(parse_xcursor(&data).is_none());
+        assert!(parse_xcursor(&[]).is_none());
+    }
+
+    #[test]
+    fn skips_broken_images() {
+        // Pixels cut off
+        let images = parse_xcursor(&CURSOR[..CURSOR.len() - 2]).expect("valid header");
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].size, 24);
+        // Hotspot outside of the image
+        let mut data = CURSOR.to_vec();
+        data[52 + 24] = 3;
+        let images = parse_xcursor(&data).expect("valid header");
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].size, 32);
+    }
+}
@@ -0,0 +1,504 @@
+//! Server-side window decorations, drawn by weston's own frame code (`shared/frame.c`).
+//!
+//! The frames are surfaces of an `InternalClient` placed right below the windows (so they
+//! work with the pixman renderer too). Clicks on them are turned into `FrameAction`s
+//! for the shell, which already knows how to move/resize/maximize its windows.
+
+use libc;
+use std::{ffi, ptr};
+use std::collections::HashMap;
+use libweston_sys::{
+    weston_compositor, weston_pointer, weston_view, weston_desktop_surface, weston_binding,
+    weston_compositor_add_button_binding, weston_binding_destroy,
+    frame, theme, theme_create, theme_destroy,
+    frame_create, frame_destroy, frame_set_title, frame_set_flag, frame_unset_flag,
+    frame_resize_inside, frame_width, frame_height, frame_interior, frame_input_rect,
+    frame_get_shadow_margin, frame_status, frame_status_clear, frame_repaint,
+    frame_pointer_motion, frame_pointer_leave, frame_pointer_button,
+    frame_flag_FRAME_FLAG_ACTIVE, frame_flag_FRAME_FLAG_MAXIMIZED,
+    frame_status_FRAME_STATUS_REPAINT, frame_status_FRAME_STATUS_MINIMIZE,
+    frame_status_FRAME_STATUS_MAXIMIZE, frame_status_FRAME_STATUS_CLOSE,
+    frame_status_FRAME_STATUS_MENU, frame_status_FRAME_STATUS_RESIZE,
+    frame_status_FRAME_STATUS_MOVE,
+    frame_button_state_FRAME_BUTTON_PRESSED, frame_button_state_FRAME_BUTTON_RELEASED,
+    theme_location_THEME_LOCATION_RESIZING_MASK,
+    FRAME_BUTTON_ALL,
+    cairo_format_t, cairo_image_surface_create_for_data, cairo_surface_flush, cairo_surface_destroy,
+    cairo_create, cairo_destroy,
+};
+use wayland_sys::common::wl_fixed_to_int;
+use wayland_server::protocol::wl_shell_surface::Resize;
+use foreign_types::ForeignTypeRef;
+use ::Geometry;
+use ::compositor::CompositorRef;
+use ::cursor;
+use ::desktop::DesktopSurfaceRef;
+use ::ev;
+use ::internal_client::{InternalClient, InternalSurface};
+use ::listener::{WlListener, ListenerList, SeatListeners};
+use ::pointer::{PointerGrab, PointerRef, PointerMotionEvent, ButtonState};
+use ::seat::SeatRef;
+use ::surface::SurfaceRef;
+use ::view::{View, ViewRef};
+
+const CAIRO_FORMAT_ARGB32: cairo_format_t = 0;
+
+/// What a click on the decorations asks the shell to do.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FrameAction {
+    Move,
+    Resize(Resize),
+    Close,
+    /// Toggle
+    Maximize,
+    Minimize,
+    /// Right click on the titlebar, at the given global position
+    Menu(i32, i32),
+}
+
+pub trait DecorationsApi<SC> {
+    fn frame_action(&mut self, surface: &mut DesktopSurfaceRef<SC>, seat: &mut SeatRef, action: FrameAction);
+}
+
+struct Decoration {
+    frame: *mut frame,
+    // view first: it must be destroyed before the surface
+    view: Option<View>,
+    internal: InternalSurface,
+    /// Undecorated when it's destroyed
+    window_view: *mut weston_view,
+    title: ffi::CString,
+    activated: bool,
+    maximized: bool,
+    width: i32,
+    height: i32,
+    /// On the window's commit and destroy signals and on its view's destroy signal
+    listeners: ListenerList,
+}
+
+impl Drop for Decoration {
+    fn drop(&mut self) {
+        unsafe { frame_destroy(self.frame); }
+    }
+}
+
+struct DecorationsState<SC> {
+    api: Box<dyn DecorationsApi<SC>>,
+    client: InternalClient,
+    theme: *mut theme,
+    compositor: *mut weston_compositor,
+    decorations: HashMap<*mut weston_desktop_surface, Decoration>,
+    /// Which decoration each pointer is over, and the cursor shown there
+    hovered: HashMap<*mut weston_pointer, (*mut weston_desktop_surface, Option<&'static str>)>,
+    bindings: Vec<*mut weston_binding>,
+    listeners: ListenerList,
+    seats: Vec<SeatListeners>,
+    // Listeners can't be freed while they run, these go on the next `decorate`
+    dead: Vec<ListenerList>,
+    dead_seats: Vec<ListenerList>,
+}
+
+impl<SC> Drop for DecorationsState<SC> {
+    fn drop(&mut self) {
+        for binding in self.bindings.drain(..) {
+            unsafe { weston_binding_destroy(binding); }
+        }
+        let dsurfs = self.decorations.keys().cloned().collect::<Vec<_>>();
+        for dsurf in dsurfs {
+            self.remove(dsurf);
+        }
+        self.dead.clear();
+        unsafe { theme_destroy(self.theme); }
+    }
+}
+
+impl<SC: 'static> DecorationsState<SC> {
+    fn watch_seat(&mut self, seat: &mut SeatRef) {
+        let state = self as *mut DecorationsState<SC>;
+        let mut listeners = SeatListeners::new(seat.as_ptr());
+        if let Some(pointer) = seat.pointer_mut() {
+            listeners.devices.add(WlListener::new(Box::new(move |pointer: &mut PointerRef| unsafe { (*state).pointer_motion(pointer) })),
+                                  pointer.motion_signal());
+            listeners.devices.add(WlListener::new(Box::new(move |pointer: &mut PointerRef| unsafe { (*state).pointer_motion(pointer) })),
+                                  pointer.focus_signal());
+        }
+        listeners.own.add(WlListener::new(Box::new(move |seat: &mut SeatRef| unsafe { (*state).seat_destroyed(seat) })),
+                          seat.destroy_signal());
+        self.seats.push(listeners);
+    }
+
+    fn seat_destroyed(&mut self, seat: &mut SeatRef) {
+        self.dead_seats.clear();
+        // The pointer is already freed, only its address is left
+        self.hovered.remove(&unsafe { (*seat.as_ptr()).pointer_state });
+        let index = self.seats.iter().position(|s| s.seat == seat.as_ptr());
+        if let Some(i) = index {
+            let listeners = self.seats.remove(i).destroyed();
+            self.dead_seats.push(listeners);
+        }
+    }
+}
+
+impl<SC> DecorationsState<SC> {
+    fn find_by_view(&self, view: &ViewRef) -> Option<*mut weston_desktop_surface> {
+        self.decorations.iter()
+            .find(|&(_, deco)| deco.view.as_ref().map(|v| v.as_ptr()) == Some(view.as_ptr()))
+            .map(|(dsurf, _)| *dsurf)
+    }
+
+    fn refresh(&mut self, dsurf_ptr: *mut weston_desktop_surface) {
+        let dsurf = unsafe { DesktopSurfaceRef::<SC>::from_ptr_mut(dsurf_ptr) };
+        let deco = match self.decorations.get_mut(&dsurf_ptr) {
+            Some(deco) => deco,
+            None => return,
+        };
+        let geom = dsurf.get_geometry();
+        let mut changed = false;
+        // Empty until the client sets one
+        let title = dsurf.title().unwrap_or_default();
+        if deco.title.as_c_str() != title {
+            deco.title = title.to_owned();
+            unsafe { frame_set_title(deco.frame, deco.title.as_ptr()); }
+            changed = true;
+        }
+        if deco.activated != dsurf.get_activated() {
+            deco.activated = dsurf.get_activated();
+            unsafe {
+                if deco.activated {
+                    frame_set_flag(deco.frame, frame_flag_FRAME_FLAG_ACTIVE);
+                } else {
+                    frame_unset_flag(deco.frame, frame_flag_FRAME_FLAG_ACTIVE);
+                }
+            }
+            changed = true;
+        }
+        if deco.maximized != dsurf.get_maximized() {
+            deco.maximized = dsurf.get_maximized();
+            unsafe {
+                if deco.maximized {
+                    frame_set_flag(deco.frame, frame_flag_FRAME_FLAG_MAXIMIZED);
+                } else {
+                    frame_unset_flag(deco.frame, frame_flag_FRAME_FLAG_MAXIMIZED);
+                }
+            }
+            changed = true;
+        }
+        if geom.width > 0 && geom.height > 0 && (deco.width != geom.width || deco.height != geom.height) {
+            deco.width = geom.width;
+            deco.height = geom.height;
+            unsafe { frame_resize_inside(deco.frame, geom.width, geom.height); }
+            changed = true;
+        }
+        if changed || unsafe { frame_status(deco.frame) } & frame_status_FRAME_STATUS_REPAINT != 0 {
+            draw(deco);
+        }
+
+        if deco.view.is_none() {
+            deco.view = deco.internal.surface().map(|s| View::new(s));
+        }
+        let window_view = unsafe { ViewRef::from_ptr_mut(deco.window_view) };
+        if let Some(ref mut view) = deco.view {
+            if window_view.layer_link().layer.is_null() {
+                if !view.layer_link().layer.is_null() {
+                    view.damage_below();
+                    view.layer_entry_remove();
+                }
+                return
+            }
+            let (mut ix, mut iy, mut iw, mut ih) = (0, 0, 0, 0);
+            unsafe { frame_interior(deco.frame, &mut ix, &mut iy, &mut iw, &mut ih); }
+            // Windows get restacked by the shell, so the frame follows on every commit
+            view.place_below(window_view);
+            if view.transform_parent().map(|p| p.as_ptr()) != Some(window_view.as_ptr()) {
+                view.set_transform_parent(window_view);
+            }
+            view.set_position((geom.x - ix) as f32, (geom.y - iy) as f32);
+            view.geometry_dirty();
+            view.schedule_repaint();
+        }
+    }
+
+    fn pointer_motion(&mut self, pointer: &mut PointerRef) {
+        let hovered = pointer.focus().and_then(|view| self.find_by_view(view));
+        let previous = self.hovered.get(&pointer.as_ptr()).cloned();
+        if let Some((prev, _)) = previous {
+            if Some(prev) != hovered {
+                if let Some(deco) = self.decorations.get(&prev) {
+                    unsafe { frame_pointer_leave(deco.frame, pointer.as_ptr() as *mut libc::c_void); }
+                }
+                self.hovered.remove(&pointer.as_ptr());
+                pointer.set_cursor(None);
+                self.refresh(prev);
+            }
+        }
+        let dsurf = match hovered {
+            Some(dsurf) => dsurf,
+            None => return,
+        };
+        let location = match self.decorations.get(&dsurf) {
+            Some(deco) => unsafe {
+                frame_pointer_motion(deco.frame, pointer.as_ptr() as *mut libc::c_void,
+                                     wl_fixed_to_int(pointer.sx()), wl_fixed_to_int(pointer.sy()))
+            },
+            None => return,
+        };
+        let edges = Resize::from_bits_truncate(location & theme_location_THEME_LOCATION_RESIZING_MASK);
+        let wanted = if edges.is_empty() { None } else { Some(cursor::name_for_resize_edges(edges)) };
+        let shown = self.hovered.get(&pointer.as_ptr()).and_then(|&(_, c)| c);
+        if shown != wanted || !self.hovered.contains_key(&pointer.as_ptr()) {
+            pointer.set_cursor(wanted);
+        }
+        self.hovered.insert(pointer.as_ptr(), (dsurf, wanted));
+        self.refresh(dsurf);
+    }
+
+    /// Turns the frame's status into actions for the shell.
+    fn run_actions(&mut self, dsurf_ptr: *mut weston_desktop_surface, pointer: &mut PointerRef, location: u32) -> bool {
+        let status = match self.decorations.get(&dsurf_ptr) {
+            Some(deco) => unsafe { frame_status(deco.frame) },
+            None => return false,
+        };
+        let action = if status & frame_status_FRAME_STATUS_CLOSE != 0 {
+            Some(FrameAction::Close)
+        } else if status & frame_status_FRAME_STATUS_MAXIMIZE != 0 {
+            Some(FrameAction::Maximize)
+        } else if status & frame_status_FRAME_STATUS_MINIMIZE != 0 {
+            Some(FrameAction::Minimize)
+        } else if status & frame_status_FRAME_STATUS_MENU != 0 {
+            Some(FrameAction::Menu(wl_fixed_to_int(pointer.x()), wl_fixed_to_int(pointer.y())))
+        } else if status & frame_status_FRAME_STATUS_RESIZE != 0 {
+            Some(FrameAction::Resize(Resize::from_bits_truncate(location & theme_location_THEME_LOCATION_RESIZING_MASK)))
+        } else if status & frame_status_FRAME_STATUS_MOVE != 0 {
+            Some(FrameAction::Move)
+        } else {
+            None
+        };
+        if let Some(deco) = self.decorations.get(&dsurf_ptr) {
+            unsafe {
+                frame_status_clear(deco.frame, frame_status_FRAME_STATUS_CLOSE | frame_status_FRAME_STATUS_MAXIMIZE |
+                                   frame_status_FRAME_STATUS_MINIMIZE | frame_status_FRAME_STATUS_MENU |
+                                   frame_status_FRAME_STATUS_RESIZE | frame_status_FRAME_STATUS_MOVE);
+            }
+        }
+        self.refresh(dsurf_ptr);
+        if let Some(action) = action {
+            let dsurf = unsafe { DesktopSurfaceRef::<SC>::from_ptr_mut(dsurf_ptr) };
+            self.api.frame_action(dsurf, pointer.seat_mut(), action);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn remove(&mut self, dsurf: *mut weston_desktop_surface) {
+        if let Some(mut deco) = self.decorations.remove(&dsurf) {
+            self.dead.push(deco.listeners.detach());
+            if let Some(ref mut view) = deco.view {
+                if !view.layer_link().layer.is_null() {
+                    view.damage_below();
+                    view.layer_entry_remove();
+                }
+            }
+        }
+        let pointers: Vec<_> = self.hovered.iter().filter(|&(_, &(d, _))| d == dsurf).map(|(p, _)| *p).collect();
+        for pointer in pointers {
+            self.hovered.remove(&pointer);
+            unsafe { PointerRef::from_ptr_mut(pointer).set_cursor(None); }
+        }
+    }
+}
+
+fn draw(deco: &mut Decoration) {
+    let width = unsafe { frame_width(deco.frame) };
+    let height = unsafe { frame_height(deco.frame) };
+    let stride = width * 4;
+    let mut pixels = vec![0u8; stride as usize * height as usize];
+    unsafe {
+        let cs = cairo_image_surface_create_for_data(pixels.as_mut_ptr(), CAIRO_FORMAT_ARGB32, width, height, stride);
+        let cr = cairo_create(cs);
+        frame_repaint(deco.frame, cr);
+        cairo_destroy(cr);
+        cairo_surface_flush(cs);
+        cairo_surface_destroy(cs);
+        frame_status_clear(deco.frame, frame_status_FRAME_STATUS_REPAINT);
+    }
+    // Shadows shouldn't eat clicks meant for the windows below
+    let mut input = Geometry { x: 0, y: 0, width: 0, height: 0 };
+    unsafe { frame_input_rect(deco.frame, &mut input.x, &mut input.y, &mut input.width, &mut input.height); }
+    deco.internal.set_input_region(Some(input));
+    deco.internal.attach_pixels(width, height, &pixels);
+}
+
+/// Tracks a click on one of the frame buttons, which only fire on release.
+struct FrameButtonGrab<SC: 'static> {
+    state: *mut DecorationsState<SC>,
+    dsurf: *mut weston_desktop_surface,
+}
+
+impl<SC: 'static> PointerGrab for FrameButtonGrab<SC> {
+    fn motion(&mut self, pointer: &mut PointerRef, _time: &libc::timespec, event: PointerMotionEvent) {
+        pointer.moove(event);
+        let state = unsafe { &mut *self.state };
+        let pos = state.decorations.get(&self.dsurf).and_then(|deco| deco.view.as_ref())
+            .map(|view| view.from_global_fixed(pointer.x(), pointer.y()));
+        if let (Some(deco), Some((sx, sy))) = (state.decorations.get(&self.dsurf), pos) {
+            unsafe { frame_pointer_motion(deco.frame, pointer.as_ptr() as *mut libc::c_void, wl_fixed_to_int(sx), wl_fixed_to_int(sy)); }
+        }
+        state.refresh(self.dsurf);
+    }
+
+    fn button(&mut self, pointer: &mut PointerRef, _time: &libc::timespec, button: u32, state: ButtonState) {
+        if state != ButtonState::Released || pointer.button_count() > 0 {
+            return
+        }
+        let dstate = unsafe { &mut *self.state };
+        let location = match dstate.decorations.get(&self.dsurf) {
+            Some(deco) => unsafe {
+                frame_pointer_button(deco.frame, pointer.as_ptr() as *mut libc::c_void, button, frame_button_state_FRAME_BUTTON_RELEASED)
+            },
+            None => 0,
+        };
+        pointer.end_grab();
+        dstate.run_actions(self.dsurf, pointer, location);
+    }
+
+    fn cancel(&mut self, pointer: &mut PointerRef) {
+        pointer.end_grab();
+    }
+}
+
+unsafe extern "C" fn run_button<SC: 'static>(pointer: *mut weston_pointer, _time: *const libc::timespec, button: u32, data: *mut libc::c_void) {
+    let state = &mut *(data as *mut DecorationsState<SC>);
+    let pointer = PointerRef::from_ptr_mut(pointer);
+    if !pointer.is_default_grab() {
+        return
+    }
+    let dsurf = match pointer.focus().and_then(|view| state.find_by_view(view)) {
+        Some(dsurf) => dsurf,
+        None => return,
+    };
+    let location = match state.decorations.get(&dsurf) {
+        Some(deco) => frame_pointer_button(deco.frame, pointer.as_ptr() as *mut libc::c_void, button, frame_button_state_FRAME_BUTTON_PRESSED),
+        None => return,
+    };
+    // Move/resize/menu happen right away, frame buttons wait for the release
+    if !state.run_actions(dsurf, pointer, location) {
+        pointer.start_grab(FrameButtonGrab { state: state as *mut _, dsurf });
+    }
+}
+
+/// Draws and handles frames around desktop surfaces.
+///
+/// The shell calls `decorate` for windows that should have a frame (e.g. after
+/// `xdg-decoration` negotiation) and `undecorate` when removing them.
+pub struct Decorations<SC: 'static> {
+    state: Box<DecorationsState<SC>>,
+}
+
+impl<SC: 'static> Decorations<SC> {
+    pub fn new(compositor: &mut CompositorRef, api: Box<dyn DecorationsApi<SC>>) -> Option<Decorations<SC>> {
+        let client = InternalClient::new(compositor)?;
+        let mut state = Box::new(DecorationsState {
+            api,
+            client,
+            theme: unsafe { theme_create() },
+            compositor: compositor.as_ptr(),
+            decorations: HashMap::new(),
+            hovered: HashMap::new(),
+            bindings: Vec::new(),
+            listeners: ListenerList::new(),
+            seats: Vec::new(),
+            dead: Vec::new(),
+            dead_seats: Vec::new(),
+        });
+        let state_ptr = &mut *state as *mut DecorationsState<SC>;
+        for seat in compositor.iterate_seats() {
+            state.watch_seat(seat);
+        }
+        state.listeners.add(WlListener::new(Box::new(move |seat: &mut SeatRef| unsafe { (*state_ptr).watch_seat(seat) })),
+                            compositor.seat_created_signal());
+        for &button in &[ev::BTN_LEFT, ev::BTN_RIGHT, ev::BTN_MIDDLE] {
+            let binding = unsafe {
+                weston_compositor_add_button_binding(state.compositor, button, 0,
+                                                     Some(run_button::<SC>), state_ptr as *mut libc::c_void)
+            };
+            state.bindings.push(binding);
+        }
+        Some(Decorations { state })
+    }
+
+    pub fn api(&mut self) -> &mut dyn DecorationsApi<SC> {
+        &mut *self.state.api
+    }
+
+    /// Adds a frame around the window shown by `view` (one view per window is decorated).
+    pub fn decorate(&mut self, surface: &mut DesktopSurfaceRef<SC>, view: &mut ViewRef) {
+        self.state.dead.clear();
+        if self.state.decorations.contains_key(&surface.as_ptr()) {
+            return
+        }
+        let title = surface.title().unwrap_or_default().to_owned();
+        let frame = unsafe { frame_create(self.state.theme, 0, 0, FRAME_BUTTON_ALL as u32, title.as_ptr(), ptr::null_mut()) };
+        if frame.is_null() {
+            return
+        }
+        let state_ptr = &mut *self.state as *mut DecorationsState<SC>;
+        let dsurf = surface.as_ptr();
+        let mut listeners = ListenerList::new();
+        listeners.add(WlListener::new(Box::new(move |_: &mut SurfaceRef| unsafe { (*state_ptr).refresh(dsurf) })),
+                      surface.surface().commit_signal());
+        listeners.add(WlListener::new(Box::new(move |_: &mut SurfaceRef| unsafe { (*state_ptr).remove(dsurf) })),
+                      surface.surface().destroy_signal());
+        // The shell replaced or dropped the view, the frame has nothing to follow
+        listeners.add(WlListener::new(Box::new(move |_: &mut ViewRef| unsafe { (*state_ptr).remove(dsurf) })),
+                      view.destroy_signal());
+        let internal = self.state.client.create_surface();
+        self.state.decorations.insert(dsurf, Decoration {
+            frame,
+            view: None,
+            internal,
+            window_view: view.as_ptr(),
+            title,
+            activated: false,
+            maximized: false,
+            width: 0,
+            height: 0,
+            listeners,
+        });
+        self.state.refresh(dsurf);
+    }
+
+    pub fn undecorate(&mut self, surface: &mut DesktopSurfaceRef<SC>) {
+        self.state.remove(surface.as_ptr());
+    }
+
+    pub fn is_decorated(&self, surface: &DesktopSurfaceRef<SC>) -> bool {
+        self.state.decorations.contains_key(&surface.as_ptr())
+    }
+
+    /// Redraws after changes that don't come with a commit (e.g. the title).
+    pub fn refresh(&mut self, surface: &mut DesktopSurfaceRef<SC>) {
+        self.state.refresh(surface.as_ptr());
+    }
+
+    /// The window a frame surface belongs to, e.g. for click-to-activate on the titlebar.
+    pub fn window_for(&self, surface: &SurfaceRef) -> Option<&mut DesktopSurfaceRef<SC>> {
+        self.state.decorations.iter()
+            .find(|&(_, deco)| deco.internal.surface().map(|s| s.as_ptr()) == Some(surface.as_ptr()))
+            .map(|(dsurf, _)| unsafe { DesktopSurfaceRef::from_ptr_mut(*dsurf) })
+    }
+
+    /// How far the frame extends (without the shadow) beyond the window geometry:
+    /// (top, right, bottom, left).
+    pub fn extents(&self, surface: &DesktopSurfaceRef<SC>) -> Option<(i32, i32, i32, i32)> {
+        let deco = self.state.decorations.get(&surface.as_ptr())?;
+        let (mut ix, mut iy, mut iw, mut ih) = (0, 0, 0, 0);
+        unsafe {
+            frame_interior(deco.frame, &mut ix, &mut iy, &mut iw, &mut ih);
+            let margin = frame_get_shadow_margin(deco.frame);
+            let width = frame_width(deco.frame);
+            let height = frame_height(deco.frame);
+            Some((iy - margin, width - ix - iw - margin, height - iy - ih - margin, ix - margin))
+        }
+    }
+}
@@ -6,10 +6,15 @@ use libweston_sys::{
 };
 use wayland_server::protocol::wl_shell_surface::Resize;
 use foreign_types::ForeignTypeRef;
+use std::ffi;
+use ::{Geometry, Size};
 use ::output::OutputRef;
 use ::seat::SeatRef;
+use ::surface::SurfaceRef;
 use super::surface::DesktopSurfaceRef;
 use super::client::DesktopClientRef;
+use super::decoration::DecorationMode;
+use super::changes;
 
 pub trait DesktopApi<SC> {
     fn as_any(&mut self) -> &mut any::Any;
@@ -24,17 +29,66 @@ pub trait DesktopApi<SC> {
 
     fn committed(&mut self, _surface: &mut DesktopSurfaceRef<SC>, _sx: i32, _sy: i32) {}
 
+    // The *_changed callbacks compare with the previous commit and run right before `committed`.
+    // On the first commit, the old values are empty strings and zero sizes.
+
+    fn title_changed(&mut self, _surface: &mut DesktopSurfaceRef<SC>, _old: &ffi::CStr, _new: &ffi::CStr) {}
+
+    fn app_id_changed(&mut self, _surface: &mut DesktopSurfaceRef<SC>, _old: &ffi::CStr, _new: &ffi::CStr) {}
+
+    /// 0 means no limit
+    fn min_size_changed(&mut self, _surface: &mut DesktopSurfaceRef<SC>, _old: Size, _new: Size) {}
+
+    /// 0 means no limit
+    fn max_size_changed(&mut self, _surface: &mut DesktopSurfaceRef<SC>, _old: Size, _new: Size) {}
+
+    /// The window geometry (the part of the surface without client-side shadows) changed
+    fn geometry_changed(&mut self, _surface: &mut DesktopSurfaceRef<SC>, _old: Geometry, _new: Geometry) {}
+
     fn show_window_menu(&mut self, _surface: &mut DesktopSurfaceRef<SC>, _seat: &mut SeatRef, _x: i32, _y: i32) {}
 
     fn set_parent(&mut self, _surface: &mut DesktopSurfaceRef<SC>, _parent: &mut DesktopSurfaceRef<SC>) {}
 
+    /// A popup (menu, tooltip...) was shown, libweston-desktop has already placed it.
+    fn popup_mapped(&mut self, _popup: &mut DesktopSurfaceRef<SC>, _parent: &mut DesktopSurfaceRef<SC>) {}
+
+    /// A popup went away (the desktop surface might be gone already).
+    fn popup_unmapped(&mut self, _surface: &mut SurfaceRef) {}
+
+    /// The seat's input goes to the popup's client until the popup is dismissed.
+    fn popup_grab_started(&mut self, _popup: &mut DesktopSurfaceRef<SC>, _seat: &mut SeatRef) {}
+
+    fn popup_grab_ended(&mut self, _seat: &mut SeatRef) {}
+
+    /// The area popups are kept in (they're slid back in when they stick out).
+    ///
+    /// Defaults to the output of the parent surface. Return `None` to leave popups alone.
+    fn popup_bounds(&mut self, popup: &mut DesktopSurfaceRef<SC>) -> Option<Geometry> {
+        Some(popup.parent()?.surface().iterate_views().next()?.output()?.geometry())
+    }
+
+    /// Who draws the decorations of an `xdg_toplevel` using `zxdg_toplevel_decoration_v1`.
+    ///
+    /// `requested` is `None` when the client has no preference. Called again whenever it changes
+    /// its mind; the client has to go along with the answer. Defaults to client-side, shells that
+    /// draw decorations (`::decorations::Decorations`) can grant server-side.
+    fn decoration_mode(&mut self, _surface: &mut DesktopSurfaceRef<SC>, _requested: Option<DecorationMode>) -> DecorationMode {
+        DecorationMode::ClientSide
+    }
+
+    /// The client was told to use another decoration mode, e.g. time to `Decorations::decorate`
+    /// or `undecorate` the window.
+    fn decoration_mode_changed(&mut self, _surface: &mut DesktopSurfaceRef<SC>, _mode: DecorationMode) {}
+
     /// Named like that because `move` is a Rust keyword
     fn moove(&mut self, _surface: &mut DesktopSurfaceRef<SC>, _seat: &mut SeatRef, _serial: u32) {}
 
     fn resize(&mut self, _surface: &mut DesktopSurfaceRef<SC>, _seat: &mut SeatRef, _serial: u32, _edges: Resize) {}
 
+    /// Fullscreen windows cover the whole output (`OutputRef::geometry`), panels included.
     fn fullscreen_requested(&mut self, _surface: &mut DesktopSurfaceRef<SC>, _fullscreen: bool, _output: &mut OutputRef) {}
 
+    /// Maximized windows should fill `OutputRef::work_area`, not cover panels.
     fn maximized_requested(&mut self, _surface: &mut DesktopSurfaceRef<SC>, _maximized: bool) {}
 
     fn minimized_requested(&mut self, _surface: &mut DesktopSurfaceRef<SC>) {}
@@ -85,11 +139,13 @@ pub unsafe extern "C" fn run_surface_removed<SC>(surface: *mut weston_desktop_su
     let surface = DesktopSurfaceRef::from_ptr_mut(surface);
     let api = &mut *(user_data as *mut Box<DesktopApi<SC>>);
     api.surface_removed(surface);
+    changes::forget(surface.as_ptr());
 }
 
 pub unsafe extern "C" fn run_committed<SC>(surface: *mut weston_desktop_surface, sx: i32, sy: i32, user_data: *mut libc::c_void) {
     let surface = DesktopSurfaceRef::from_ptr_mut(surface);
     let api = &mut *(user_data as *mut Box<DesktopApi<SC>>);
+    changes::notify(&mut **api, surface);
     api.committed(surface, sx, sy);
 }
 
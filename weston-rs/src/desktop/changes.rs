@@ -0,0 +1,77 @@
+//! Diffing desktop surface properties at commit time for the `DesktopApi::*_changed` callbacks.
+//!
+//! libweston-desktop doesn't tell when a client sets its title etc., but clients commit afterwards.
+
+use std::ffi;
+use libweston_sys::weston_desktop_surface;
+use foreign_types::ForeignTypeRef;
+use ::{Geometry, Size};
+use ::object_map::ObjectMap;
+use super::api::DesktopApi;
+use super::surface::DesktopSurfaceRef;
+
+#[derive(Clone)]
+struct Props {
+    title: ffi::CString,
+    app_id: ffi::CString,
+    min_size: Size,
+    max_size: Size,
+    geometry: Geometry,
+}
+
+impl Default for Props {
+    fn default() -> Props {
+        Props {
+            title: ffi::CString::default(),
+            app_id: ffi::CString::default(),
+            min_size: Size { width: 0, height: 0 },
+            max_size: Size { width: 0, height: 0 },
+            geometry: Geometry { x: 0, y: 0, width: 0, height: 0 },
+        }
+    }
+}
+
+thread_local! {
+    static KNOWN: ObjectMap<weston_desktop_surface, Props> = ObjectMap::new();
+}
+
+fn same_size(a: &Size, b: &Size) -> bool {
+    a.width == b.width && a.height == b.height
+}
+
+fn same_geometry(a: &Geometry, b: &Geometry) -> bool {
+    a.x == b.x && a.y == b.y && a.width == b.width && a.height == b.height
+}
+
+/// Calls the callbacks for whatever changed since the previous commit
+pub(crate) fn notify<SC>(api: &mut dyn DesktopApi<SC>, surface: &mut DesktopSurfaceRef<SC>) {
+    let new = Props {
+        // Empty like the defaults until the client sets them
+        title: surface.title().map(|t| t.to_owned()).unwrap_or_default(),
+        app_id: surface.app_id().map(|a| a.to_owned()).unwrap_or_default(),
+        min_size: surface.get_min_size(),
+        max_size: surface.get_max_size(),
+        geometry: surface.get_geometry(),
+    };
+    // Not inside the map's borrow, callbacks can do anything
+    let old = KNOWN.with(|known| known.insert(surface.as_ptr(), new.clone())).unwrap_or_default();
+    if old.title != new.title {
+        api.title_changed(surface, &old.title, &new.title);
+    }
+    if old.app_id != new.app_id {
+        api.app_id_changed(surface, &old.app_id, &new.app_id);
+    }
+    if !same_size(&old.min_size, &new.min_size) {
+        api.min_size_changed(surface, old.min_size, new.min_size);
+    }
+    if !same_size(&old.max_size, &new.max_size) {
+        api.max_size_changed(surface, old.max_size, new.max_size);
+    }
+    if !same_geometry(&old.geometry, &new.geometry) {
+        api.geometry_changed(surface, old.geometry, new.geometry);
+    }
+}
+
+pub(crate) fn forget(surface: *mut weston_desktop_surface) {
+    KNOWN.with(|known| known.remove(surface));
+}
@@ -0,0 +1,299 @@
+//! `zxdg_decoration_manager_v1`: telling `xdg_toplevel` clients whether to draw their own decorations.
+//!
+//! libweston-desktop doesn't know about this protocol, so the global lives here and the mode
+//! comes from `DesktopApi::decoration_mode`. Pair `ServerSide` with `::decorations::Decorations`.
+
+use libc;
+use std::{mem, ptr};
+use libweston_sys::{
+    weston_desktop_surface,
+    zxdg_decoration_manager_v1_interface, zxdg_toplevel_decoration_v1_interface,
+};
+use wayland_sys::server::{
+    wl_client, wl_resource, wl_global, wl_listener,
+    wl_global_create, wl_global_destroy, wl_client_post_no_memory,
+    wl_resource_create, wl_resource_destroy, wl_resource_set_implementation,
+    wl_resource_get_user_data, wl_resource_get_version, wl_resource_add_destroy_listener,
+    wl_resource_post_event, wl_resource_post_error, wl_list_remove,
+};
+use foreign_types::ForeignTypeRef;
+use ::compositor::CompositorRef;
+use ::object_map::ObjectMap;
+use super::api::DesktopApi;
+use super::surface::DesktopSurfaceRef;
+
+/// Values of `zxdg_toplevel_decoration_v1.mode`
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Primitive)]
+pub enum DecorationMode {
+    /// The client draws its own decorations
+    ClientSide = 1,
+    /// The compositor draws them, the client draws none
+    ServerSide = 2,
+}
+
+const ZXDG_TOPLEVEL_DECORATION_V1_CONFIGURE: u32 = 0;
+const ZXDG_TOPLEVEL_DECORATION_V1_ERROR_ALREADY_CONSTRUCTED: u32 = 1;
+const ZXDG_TOPLEVEL_DECORATION_V1_ERROR_ORPHANED: u32 = 2;
+
+const_cstr!{
+    ALREADY_CONSTRUCTED_MSG = "this xdg_toplevel already has a decoration object";
+    ORPHANED_MSG = "the xdg_toplevel of this decoration object was destroyed";
+}
+
+thread_local! {
+    // Only surfaces that currently have a decoration object are in here
+    static MODES: ObjectMap<weston_desktop_surface, DecorationMode> = ObjectMap::new();
+}
+
+pub(crate) fn mode_of(surface: *mut weston_desktop_surface) -> Option<DecorationMode> {
+    MODES.with(|m| m.get(surface))
+}
+
+fn set_mode_of(surface: *mut weston_desktop_surface, mode: Option<DecorationMode>) {
+    MODES.with(|m| match mode {
+        Some(mode) => { m.insert(surface, mode); },
+        None => { m.remove(surface); },
+    });
+}
+
+#[repr(C)]
+struct ManagerInterface {
+    destroy: unsafe extern "C" fn(*mut wl_client, *mut wl_resource),
+    get_toplevel_decoration: unsafe extern "C" fn(*mut wl_client, *mut wl_resource, u32, *mut wl_resource),
+}
+
+#[repr(C)]
+struct ToplevelDecorationInterface {
+    destroy: unsafe extern "C" fn(*mut wl_client, *mut wl_resource),
+    set_mode: unsafe extern "C" fn(*mut wl_client, *mut wl_resource, u32),
+    unset_mode: unsafe extern "C" fn(*mut wl_client, *mut wl_resource),
+}
+
+// For the resources that outlive their manager: everything but destroy is ignored
+static INERT_MANAGER_IMPL: ManagerInterface = ManagerInterface {
+    destroy: run_destroy,
+    get_toplevel_decoration: run_inert_get_toplevel_decoration,
+};
+
+static INERT_DECORATION_IMPL: ToplevelDecorationInterface = ToplevelDecorationInterface {
+    destroy: run_destroy,
+    set_mode: run_inert_set_mode,
+    unset_mode: run_inert_unset_mode,
+};
+
+pub(crate) struct DecorationManager<SC> {
+    global: *mut wl_global,
+    api: *mut Box<dyn DesktopApi<SC>>,
+    /// Bound `zxdg_decoration_manager_v1`s
+    resources: Vec<*mut wl_resource>,
+    decorations: Vec<*mut ToplevelDecoration<SC>>,
+    // instantiated for this SC, so they can't be statics like the inert ones
+    manager_impl: ManagerInterface,
+    decoration_impl: ToplevelDecorationInterface,
+}
+
+impl<SC> DecorationManager<SC> {
+    pub(crate) fn new(compositor: &CompositorRef, api: *mut Box<dyn DesktopApi<SC>>) -> Box<DecorationManager<SC>> {
+        let mut manager = Box::new(DecorationManager {
+            global: ptr::null_mut(),
+            api,
+            resources: Vec::new(),
+            decorations: Vec::new(),
+            manager_impl: ManagerInterface {
+                destroy: run_destroy,
+                get_toplevel_decoration: run_get_toplevel_decoration::<SC>,
+            },
+            decoration_impl: ToplevelDecorationInterface {
+                destroy: run_destroy,
+                set_mode: run_set_mode::<SC>,
+                unset_mode: run_unset_mode::<SC>,
+            },
+        });
+        manager.global = unsafe {
+            wl_global_create((*compositor.as_ptr()).wl_display, &zxdg_decoration_manager_v1_interface, 1,
+                             &mut *manager as *mut _ as *mut libc::c_void, bind_manager::<SC>)
+        };
+        manager
+    }
+}
+
+impl<SC> Drop for DecorationManager<SC> {
+    fn drop(&mut self) {
+        unsafe {
+            wl_global_destroy(self.global);
+            // The clients keep their resources, which mustn't reach the request tables or the api anymore
+            for &resource in &self.resources {
+                wl_resource_set_implementation(resource, &INERT_MANAGER_IMPL as *const _ as *const libc::c_void,
+                                               ptr::null_mut(), None);
+            }
+            for decoration in self.decorations.drain(..) {
+                let mut decoration = Box::from_raw(decoration);
+                wl_resource_set_implementation(decoration.resource, &INERT_DECORATION_IMPL as *const _ as *const libc::c_void,
+                                               ptr::null_mut(), None);
+                if !decoration.surface.is_null() {
+                    wl_list_remove(&mut decoration.toplevel_destroy.link);
+                    set_mode_of(decoration.surface, None);
+                }
+            }
+        }
+    }
+}
+
+struct ToplevelDecoration<SC> {
+    manager: *mut DecorationManager<SC>,
+    resource: *mut wl_resource,
+    /// Null once the xdg_toplevel is gone
+    surface: *mut weston_desktop_surface,
+    requested: Option<DecorationMode>,
+    toplevel_destroy: wl_listener,
+}
+
+impl<SC> ToplevelDecoration<SC> {
+    fn api(&self) -> &mut dyn DesktopApi<SC> {
+        unsafe { &mut **(*self.manager).api }
+    }
+
+    /// Asks the shell for the mode and tells the client.
+    fn negotiate(&mut self) {
+        let surface = unsafe { DesktopSurfaceRef::from_ptr_mut(self.surface) };
+        let prev = mode_of(self.surface);
+        let mode = self.api().decoration_mode(surface, self.requested);
+        set_mode_of(self.surface, Some(mode));
+        unsafe { wl_resource_post_event(self.resource, ZXDG_TOPLEVEL_DECORATION_V1_CONFIGURE, mode as u32); }
+        if prev != Some(mode) {
+            self.api().decoration_mode_changed(surface, mode);
+        }
+        // The client applies the mode on the next xdg_surface.configure. Unmapped surfaces
+        // get one from libweston-desktop anyway. Mapped ones are asked for their current size:
+        // libweston-desktop sends that unless it's exactly what it configured last, and then the
+        // mode applies with the next configure, whatever it's for.
+        if surface.surface().is_mapped() {
+            let geometry = surface.get_geometry();
+            surface.set_size(geometry.width, geometry.height);
+        }
+    }
+}
+
+unsafe extern "C" fn bind_manager<SC>(client: *mut wl_client, data: *mut libc::c_void, version: u32, id: u32) {
+    let manager = &mut *(data as *mut DecorationManager<SC>);
+    let resource = wl_resource_create(client, &zxdg_decoration_manager_v1_interface, version as libc::c_int, id);
+    if resource.is_null() {
+        wl_client_post_no_memory(client);
+        return;
+    }
+    wl_resource_set_implementation(resource, &manager.manager_impl as *const _ as *const libc::c_void, data,
+                                   Some(run_manager_destroyed::<SC>));
+    manager.resources.push(resource);
+}
+
+unsafe extern "C" fn run_manager_destroyed<SC>(resource: *mut wl_resource) {
+    let manager = wl_resource_get_user_data(resource) as *mut DecorationManager<SC>;
+    if manager.is_null() {
+        return;
+    }
+    (*manager).resources.retain(|&r| r != resource);
+}
+
+unsafe extern "C" fn run_destroy(_client: *mut wl_client, resource: *mut wl_resource) {
+    wl_resource_destroy(resource);
+}
+
+unsafe extern "C" fn run_get_toplevel_decoration<SC>(client: *mut wl_client, resource: *mut wl_resource, id: u32, toplevel: *mut wl_resource) {
+    let manager = wl_resource_get_user_data(resource) as *mut DecorationManager<SC>;
+    if manager.is_null() {
+        run_inert_get_toplevel_decoration(client, resource, id, toplevel);
+        return;
+    }
+    // libweston-desktop's xdg_toplevel resources carry the desktop surface
+    let surface = wl_resource_get_user_data(toplevel) as *mut weston_desktop_surface;
+    if mode_of(surface).is_some() {
+        wl_resource_post_error(resource, ZXDG_TOPLEVEL_DECORATION_V1_ERROR_ALREADY_CONSTRUCTED, ALREADY_CONSTRUCTED_MSG.as_ptr());
+        return;
+    }
+    let decoration_resource = wl_resource_create(client, &zxdg_toplevel_decoration_v1_interface, wl_resource_get_version(resource), id);
+    if decoration_resource.is_null() {
+        wl_client_post_no_memory(client);
+        return;
+    }
+    let mut decoration = Box::new(ToplevelDecoration {
+        manager,
+        resource: decoration_resource,
+        surface,
+        requested: None,
+        toplevel_destroy: wl_listener {
+            link: mem::zeroed(),
+            notify: run_toplevel_destroyed::<SC>,
+        },
+    });
+    wl_resource_add_destroy_listener(toplevel, &mut decoration.toplevel_destroy);
+    let decoration = Box::into_raw(decoration);
+    wl_resource_set_implementation(decoration_resource, &(*manager).decoration_impl as *const _ as *const libc::c_void,
+                                   decoration as *mut libc::c_void, Some(run_decoration_destroyed::<SC>));
+    (*manager).decorations.push(decoration);
+    (*decoration).negotiate();
+}
+
+// Decoration objects of a manager whose global is gone do nothing, the client keeps its own decorations
+unsafe extern "C" fn run_inert_get_toplevel_decoration(client: *mut wl_client, resource: *mut wl_resource, id: u32, _toplevel: *mut wl_resource) {
+    let decoration_resource = wl_resource_create(client, &zxdg_toplevel_decoration_v1_interface, wl_resource_get_version(resource), id);
+    if decoration_resource.is_null() {
+        wl_client_post_no_memory(client);
+        return;
+    }
+    wl_resource_set_implementation(decoration_resource, &INERT_DECORATION_IMPL as *const _ as *const libc::c_void,
+                                   ptr::null_mut(), None);
+}
+
+unsafe extern "C" fn run_inert_set_mode(_client: *mut wl_client, _resource: *mut wl_resource, _mode: u32) {
+}
+
+unsafe extern "C" fn run_inert_unset_mode(_client: *mut wl_client, _resource: *mut wl_resource) {
+}
+
+unsafe extern "C" fn run_set_mode<SC>(_client: *mut wl_client, resource: *mut wl_resource, mode: u32) {
+    use num_traits::FromPrimitive;
+    let decoration = &mut *(wl_resource_get_user_data(resource) as *mut ToplevelDecoration<SC>);
+    if decoration.surface.is_null() {
+        wl_resource_post_error(resource, ZXDG_TOPLEVEL_DECORATION_V1_ERROR_ORPHANED, ORPHANED_MSG.as_ptr());
+        return;
+    }
+    // Unknown modes count as no preference
+    decoration.requested = DecorationMode::from_u32(mode);
+    decoration.negotiate();
+}
+
+unsafe extern "C" fn run_unset_mode<SC>(_client: *mut wl_client, resource: *mut wl_resource) {
+    let decoration = &mut *(wl_resource_get_user_data(resource) as *mut ToplevelDecoration<SC>);
+    if decoration.surface.is_null() {
+        wl_resource_post_error(resource, ZXDG_TOPLEVEL_DECORATION_V1_ERROR_ORPHANED, ORPHANED_MSG.as_ptr());
+        return;
+    }
+    decoration.requested = None;
+    decoration.negotiate();
+}
+
+unsafe extern "C" fn run_decoration_destroyed<SC>(resource: *mut wl_resource) {
+    let mut decoration = Box::from_raw(wl_resource_get_user_data(resource) as *mut ToplevelDecoration<SC>);
+    let this = &mut *decoration as *mut ToplevelDecoration<SC>;
+    (*decoration.manager).decorations.retain(|&d| d != this);
+    if decoration.surface.is_null() {
+        return;
+    }
+    wl_list_remove(&mut decoration.toplevel_destroy.link);
+    let prev = mode_of(decoration.surface);
+    set_mode_of(decoration.surface, None);
+    // Without the object the client is back to drawing its own decorations
+    if prev == Some(DecorationMode::ServerSide) {
+        let surface = DesktopSurfaceRef::from_ptr_mut(decoration.surface);
+        decoration.api().decoration_mode_changed(surface, DecorationMode::ClientSide);
+    }
+}
+
+// Runs before libweston-desktop frees the surface, so it's still fine to look it up here
+unsafe extern "C" fn run_toplevel_destroyed<SC>(listener: *mut wl_listener, _data: *mut libc::c_void) {
+    let decoration = &mut *wl_container_of!(listener, ToplevelDecoration<SC>, toplevel_destroy);
+    wl_list_remove(&mut decoration.toplevel_destroy.link);
+    set_mode_of(decoration.surface, None);
+    decoration.surface = ptr::null_mut();
+}
@@ -0,0 +1,508 @@
+//! `zwlr_foreign_toplevel_manager_v1`: the window list for external taskbars and docks.
+//!
+//! The shell feeds it from its `DesktopApi` (`add` in `surface_added`, `remove` in `surface_removed`,
+//! `update` whenever a window might have changed) and gets the taskbar's requests through `ForeignToplevelApi`.
+
+use libc;
+use std::{ffi, mem, ptr};
+use libweston_sys::{
+    weston_compositor, weston_desktop_surface, weston_output, weston_seat, weston_surface,
+    weston_head_from_resource,
+    zwlr_foreign_toplevel_manager_v1_interface, zwlr_foreign_toplevel_handle_v1_interface,
+};
+use wayland_sys::common::wl_array;
+use wayland_sys::server::{
+    wl_client, wl_resource, wl_global,
+    wl_global_create, wl_global_destroy, wl_client_post_no_memory,
+    wl_resource_create, wl_resource_destroy, wl_resource_set_implementation,
+    wl_resource_get_user_data, wl_resource_set_user_data, wl_resource_get_version,
+    wl_resource_get_client, wl_resource_find_for_client, wl_resource_post_event,
+};
+use foreign_types::ForeignTypeRef;
+use ::Geometry;
+use ::compositor::CompositorRef;
+use ::output::OutputRef;
+use ::seat::SeatRef;
+use ::surface::SurfaceRef;
+use super::surface::DesktopSurfaceRef;
+
+const ZWLR_FOREIGN_TOPLEVEL_MANAGER_V1_TOPLEVEL: u32 = 0;
+const ZWLR_FOREIGN_TOPLEVEL_MANAGER_V1_FINISHED: u32 = 1;
+const ZWLR_FOREIGN_TOPLEVEL_HANDLE_V1_TITLE: u32 = 0;
+const ZWLR_FOREIGN_TOPLEVEL_HANDLE_V1_APP_ID: u32 = 1;
+const ZWLR_FOREIGN_TOPLEVEL_HANDLE_V1_OUTPUT_ENTER: u32 = 2;
+const ZWLR_FOREIGN_TOPLEVEL_HANDLE_V1_OUTPUT_LEAVE: u32 = 3;
+const ZWLR_FOREIGN_TOPLEVEL_HANDLE_V1_STATE: u32 = 4;
+const ZWLR_FOREIGN_TOPLEVEL_HANDLE_V1_DONE: u32 = 5;
+const ZWLR_FOREIGN_TOPLEVEL_HANDLE_V1_CLOSED: u32 = 6;
+const ZWLR_FOREIGN_TOPLEVEL_HANDLE_V1_STATE_FULLSCREEN_SINCE_VERSION: i32 = 2;
+
+bitflags! {
+    /// Window states as reported to taskbars
+    #[derive(Default)]
+    pub struct ToplevelState: u32 {
+        const MAXIMIZED = 1 << 0;
+        const MINIMIZED = 1 << 1;
+        const ACTIVATED = 1 << 2;
+        const FULLSCREEN = 1 << 3;
+    }
+}
+
+impl ToplevelState {
+    /// The `state` array: the bit numbers are the protocol values
+    fn to_wire(self, version: i32) -> Vec<u32> {
+        (0..4).filter(|&i| self.bits() & (1 << i) != 0)
+            .filter(|&i| i != 3 || version >= ZWLR_FOREIGN_TOPLEVEL_HANDLE_V1_STATE_FULLSCREEN_SINCE_VERSION)
+            .collect()
+    }
+}
+
+/// What taskbars ask for. The shell decides whether to go along with it.
+pub trait ForeignToplevelApi<SC> {
+    fn activate_requested(&mut self, surface: &mut DesktopSurfaceRef<SC>, seat: &mut SeatRef);
+
+    fn close_requested(&mut self, surface: &mut DesktopSurfaceRef<SC>) {
+        surface.close();
+    }
+
+    fn maximize_requested(&mut self, _surface: &mut DesktopSurfaceRef<SC>, _maximized: bool) {}
+
+    /// libweston-desktop has no minimized state (see `shell::minimize`), report it with
+    /// `ForeignToplevelManager::set_minimized` (or `MinimizeManager::report_to`).
+    fn minimize_requested(&mut self, _surface: &mut DesktopSurfaceRef<SC>, _minimized: bool) {}
+
+    fn fullscreen_requested(&mut self, _surface: &mut DesktopSurfaceRef<SC>, _fullscreen: bool, _output: Option<&mut OutputRef>) {}
+
+    /// Where the taskbar shows the window (relative to `on`), e.g. for a minimize animation.
+    /// `None` when the taskbar removed the rectangle.
+    fn rectangle_set(&mut self, _surface: &mut DesktopSurfaceRef<SC>, _on: &mut SurfaceRef, _rect: Option<Geometry>) {}
+}
+
+struct Toplevel<SC> {
+    manager: *mut ManagerState<SC>,
+    surface: *mut weston_desktop_surface,
+    /// `None` until the client sets it, and it's not sent until then
+    title: Option<ffi::CString>,
+    app_id: Option<ffi::CString>,
+    state: ToplevelState,
+    /// weston_surface.output_mask as last reported
+    output_mask: u32,
+    minimized: bool,
+    handles: Vec<*mut wl_resource>,
+}
+
+impl<SC> Toplevel<SC> {
+    fn dsurf(&self) -> &mut DesktopSurfaceRef<SC> {
+        unsafe { DesktopSurfaceRef::from_ptr_mut(self.surface) }
+    }
+
+    fn current_state(&self) -> ToplevelState {
+        let dsurf = self.dsurf();
+        let mut state = ToplevelState::empty();
+        state.set(ToplevelState::MAXIMIZED, dsurf.get_maximized());
+        state.set(ToplevelState::MINIMIZED, self.minimized);
+        state.set(ToplevelState::ACTIVATED, dsurf.get_activated());
+        state.set(ToplevelState::FULLSCREEN, dsurf.get_fullscreen());
+        state
+    }
+
+    fn output_mask(&self) -> u32 {
+        unsafe { (*(self.surface_ptr())).output_mask }
+    }
+
+    fn surface_ptr(&self) -> *mut weston_surface {
+        self.dsurf().surface().as_ptr()
+    }
+
+    fn compositor(&self) -> *mut weston_compositor {
+        unsafe { (*self.manager).compositor }
+    }
+
+    fn send_state(&self, handle: *mut wl_resource) {
+        let mut states = self.state.to_wire(unsafe { wl_resource_get_version(handle) });
+        let mut array = wl_array {
+            size: states.len() * mem::size_of::<u32>(),
+            alloc: states.len() * mem::size_of::<u32>(),
+            data: states.as_mut_ptr() as *mut libc::c_void,
+        };
+        unsafe { wl_resource_post_event(handle, ZWLR_FOREIGN_TOPLEVEL_HANDLE_V1_STATE, &mut array as *mut wl_array); }
+    }
+
+    /// output_enter/output_leave for the outputs in `mask`, with the client's wl_output objects
+    fn send_outputs(&self, handle: *mut wl_resource, mask: u32, event: u32) {
+        let client = unsafe { wl_resource_get_client(handle) };
+        let compositor = unsafe { CompositorRef::from_ptr(self.compositor()) };
+        for output in compositor.iterate_outputs() {
+            if mask & (1 << output.id()) == 0 {
+                continue;
+            }
+            for head in output.iterate_heads() {
+                let resource = unsafe { wl_resource_find_for_client(head.resource_list(), client) };
+                if !resource.is_null() {
+                    unsafe { wl_resource_post_event(handle, event, resource); }
+                }
+            }
+        }
+    }
+
+    fn send_all(&self, handle: *mut wl_resource) {
+        unsafe {
+            if let Some(ref title) = self.title {
+                wl_resource_post_event(handle, ZWLR_FOREIGN_TOPLEVEL_HANDLE_V1_TITLE, title.as_ptr());
+            }
+            if let Some(ref app_id) = self.app_id {
+                wl_resource_post_event(handle, ZWLR_FOREIGN_TOPLEVEL_HANDLE_V1_APP_ID, app_id.as_ptr());
+            }
+        }
+        self.send_outputs(handle, self.output_mask, ZWLR_FOREIGN_TOPLEVEL_HANDLE_V1_OUTPUT_ENTER);
+        self.send_state(handle);
+        unsafe { wl_resource_post_event(handle, ZWLR_FOREIGN_TOPLEVEL_HANDLE_V1_DONE); }
+    }
+
+    fn create_handle(&mut self, manager: *mut wl_resource) {
+        unsafe {
+            let client = wl_resource_get_client(manager);
+            let handle = wl_resource_create(client, &zwlr_foreign_toplevel_handle_v1_interface, wl_resource_get_version(manager), 0);
+            if handle.is_null() {
+                wl_client_post_no_memory(client);
+                return;
+            }
+            wl_resource_set_implementation(handle, &(*self.manager).handle_impl as *const _ as *const libc::c_void,
+                                           self as *mut Toplevel<SC> as *mut libc::c_void, Some(run_handle_destroyed::<SC>));
+            self.handles.push(handle);
+            wl_resource_post_event(manager, ZWLR_FOREIGN_TOPLEVEL_MANAGER_V1_TOPLEVEL, handle);
+            self.send_all(handle);
+        }
+    }
+
+    /// Sends whatever changed since last time
+    fn update(&mut self) {
+        let dsurf = self.dsurf();
+        let title = dsurf.title().map(|t| t.to_owned());
+        let app_id = dsurf.app_id().map(|a| a.to_owned());
+        let state = self.current_state();
+        let output_mask = self.output_mask();
+        let title_changed = title != self.title;
+        let app_id_changed = app_id != self.app_id;
+        let state_changed = state != self.state;
+        let entered = output_mask & !self.output_mask;
+        let left = self.output_mask & !output_mask;
+        if !title_changed && !app_id_changed && !state_changed && entered == 0 && left == 0 {
+            return;
+        }
+        self.title = title;
+        self.app_id = app_id;
+        self.state = state;
+        self.output_mask = output_mask;
+        for &handle in &self.handles {
+            unsafe {
+                // Once set, libweston-desktop doesn't unset them
+                match self.title {
+                    Some(ref title) if title_changed =>
+                        wl_resource_post_event(handle, ZWLR_FOREIGN_TOPLEVEL_HANDLE_V1_TITLE, title.as_ptr()),
+                    _ => (),
+                }
+                match self.app_id {
+                    Some(ref app_id) if app_id_changed =>
+                        wl_resource_post_event(handle, ZWLR_FOREIGN_TOPLEVEL_HANDLE_V1_APP_ID, app_id.as_ptr()),
+                    _ => (),
+                }
+            }
+            self.send_outputs(handle, entered, ZWLR_FOREIGN_TOPLEVEL_HANDLE_V1_OUTPUT_ENTER);
+            self.send_outputs(handle, left, ZWLR_FOREIGN_TOPLEVEL_HANDLE_V1_OUTPUT_LEAVE);
+            if state_changed {
+                self.send_state(handle);
+            }
+            unsafe { wl_resource_post_event(handle, ZWLR_FOREIGN_TOPLEVEL_HANDLE_V1_DONE); }
+        }
+    }
+
+    /// The window is gone, the handles stay around (inert) until their clients destroy them
+    fn close(&mut self) {
+        for handle in self.handles.drain(..) {
+            unsafe {
+                wl_resource_post_event(handle, ZWLR_FOREIGN_TOPLEVEL_HANDLE_V1_CLOSED);
+                wl_resource_set_user_data(handle, ptr::null_mut());
+            }
+        }
+    }
+}
+
+#[repr(C)]
+struct ManagerInterface {
+    stop: unsafe extern "C" fn(*mut wl_client, *mut wl_resource),
+}
+
+#[repr(C)]
+struct HandleInterface {
+    set_maximized: unsafe extern "C" fn(*mut wl_client, *mut wl_resource),
+    unset_maximized: unsafe extern "C" fn(*mut wl_client, *mut wl_resource),
+    set_minimized: unsafe extern "C" fn(*mut wl_client, *mut wl_resource),
+    unset_minimized: unsafe extern "C" fn(*mut wl_client, *mut wl_resource),
+    activate: unsafe extern "C" fn(*mut wl_client, *mut wl_resource, *mut wl_resource),
+    close: unsafe extern "C" fn(*mut wl_client, *mut wl_resource),
+    set_rectangle: unsafe extern "C" fn(*mut wl_client, *mut wl_resource, *mut wl_resource, i32, i32, i32, i32),
+    destroy: unsafe extern "C" fn(*mut wl_client, *mut wl_resource),
+    set_fullscreen: unsafe extern "C" fn(*mut wl_client, *mut wl_resource, *mut wl_resource),
+    unset_fullscreen: unsafe extern "C" fn(*mut wl_client, *mut wl_resource),
+}
+
+// The handlers do nothing without a toplevel, any SC will do
+static INERT_HANDLE_IMPL: HandleInterface = HandleInterface {
+    set_maximized: run_set_maximized::<()>,
+    unset_maximized: run_unset_maximized::<()>,
+    set_minimized: run_set_minimized::<()>,
+    unset_minimized: run_unset_minimized::<()>,
+    activate: run_activate::<()>,
+    close: run_close::<()>,
+    set_rectangle: run_set_rectangle::<()>,
+    destroy: run_destroy,
+    set_fullscreen: run_set_fullscreen::<()>,
+    unset_fullscreen: run_unset_fullscreen::<()>,
+};
+
+static INERT_MANAGER_IMPL: ManagerInterface = ManagerInterface {
+    stop: run_stop,
+};
+
+pub(crate) struct ManagerState<SC> {
+    compositor: *mut weston_compositor,
+    api: Box<dyn ForeignToplevelApi<SC>>,
+    global: *mut wl_global,
+    managers: Vec<*mut wl_resource>,
+    /// In the order the windows appeared
+    toplevels: Vec<Box<Toplevel<SC>>>,
+    manager_impl: ManagerInterface,
+    // points at this SC's handlers, see INERT_HANDLE_IMPL for the handles that outlive it
+    handle_impl: HandleInterface,
+}
+
+impl<SC> ManagerState<SC> {
+    fn find(&mut self, surface: *mut weston_desktop_surface) -> Option<&mut Toplevel<SC>> {
+        self.toplevels.iter_mut().find(|t| t.surface == surface).map(|t| &mut **t)
+    }
+
+    pub(crate) fn set_minimized(&mut self, surface: *mut weston_desktop_surface, minimized: bool) {
+        if let Some(toplevel) = self.find(surface) {
+            toplevel.minimized = minimized;
+            toplevel.update();
+        }
+    }
+}
+
+unsafe extern "C" fn bind_manager<SC>(client: *mut wl_client, data: *mut libc::c_void, version: u32, id: u32) {
+    let state = &mut *(data as *mut ManagerState<SC>);
+    let resource = wl_resource_create(client, &zwlr_foreign_toplevel_manager_v1_interface, version as libc::c_int, id);
+    if resource.is_null() {
+        wl_client_post_no_memory(client);
+        return;
+    }
+    wl_resource_set_implementation(resource, &state.manager_impl as *const _ as *const libc::c_void, data, Some(run_manager_destroyed::<SC>));
+    state.managers.push(resource);
+    for toplevel in state.toplevels.iter_mut() {
+        toplevel.create_handle(resource);
+    }
+}
+
+unsafe extern "C" fn run_manager_destroyed<SC>(resource: *mut wl_resource) {
+    let state = &mut *(wl_resource_get_user_data(resource) as *mut ManagerState<SC>);
+    state.managers.retain(|&r| r != resource);
+}
+
+unsafe extern "C" fn run_stop(_client: *mut wl_client, resource: *mut wl_resource) {
+    wl_resource_post_event(resource, ZWLR_FOREIGN_TOPLEVEL_MANAGER_V1_FINISHED);
+    wl_resource_destroy(resource);
+}
+
+/// The handle's toplevel, `None` once the window is closed
+unsafe fn toplevel<'a, SC>(handle: *mut wl_resource) -> Option<&'a mut Toplevel<SC>> {
+    let ptr = wl_resource_get_user_data(handle) as *mut Toplevel<SC>;
+    if ptr.is_null() { None } else { Some(&mut *ptr) }
+}
+
+unsafe fn api<'a, SC>(toplevel: &Toplevel<SC>) -> &'a mut dyn ForeignToplevelApi<SC> {
+    &mut *(*toplevel.manager).api
+}
+
+unsafe extern "C" fn run_handle_destroyed<SC>(handle: *mut wl_resource) {
+    if let Some(toplevel) = toplevel::<SC>(handle) {
+        toplevel.handles.retain(|&h| h != handle);
+    }
+}
+
+unsafe extern "C" fn run_destroy(_client: *mut wl_client, resource: *mut wl_resource) {
+    wl_resource_destroy(resource);
+}
+
+unsafe extern "C" fn run_set_maximized<SC>(_client: *mut wl_client, handle: *mut wl_resource) {
+    if let Some(t) = toplevel::<SC>(handle) {
+        api(t).maximize_requested(t.dsurf(), true);
+    }
+}
+
+unsafe extern "C" fn run_unset_maximized<SC>(_client: *mut wl_client, handle: *mut wl_resource) {
+    if let Some(t) = toplevel::<SC>(handle) {
+        api(t).maximize_requested(t.dsurf(), false);
+    }
+}
+
+unsafe extern "C" fn run_set_minimized<SC>(_client: *mut wl_client, handle: *mut wl_resource) {
+    if let Some(t) = toplevel::<SC>(handle) {
+        api(t).minimize_requested(t.dsurf(), true);
+    }
+}
+
+unsafe extern "C" fn run_unset_minimized<SC>(_client: *mut wl_client, handle: *mut wl_resource) {
+    if let Some(t) = toplevel::<SC>(handle) {
+        api(t).minimize_requested(t.dsurf(), false);
+    }
+}
+
+unsafe extern "C" fn run_activate<SC>(_client: *mut wl_client, handle: *mut wl_resource, seat: *mut wl_resource) {
+    if let Some(t) = toplevel::<SC>(handle) {
+        // wl_seat objects carry the weston_seat
+        let seat = SeatRef::from_ptr_mut(wl_resource_get_user_data(seat) as *mut weston_seat);
+        api(t).activate_requested(t.dsurf(), seat);
+    }
+}
+
+unsafe extern "C" fn run_close<SC>(_client: *mut wl_client, handle: *mut wl_resource) {
+    if let Some(t) = toplevel::<SC>(handle) {
+        api(t).close_requested(t.dsurf());
+    }
+}
+
+unsafe extern "C" fn run_set_rectangle<SC>(_client: *mut wl_client, handle: *mut wl_resource, surface: *mut wl_resource,
+                                           x: i32, y: i32, width: i32, height: i32) {
+    if let Some(t) = toplevel::<SC>(handle) {
+        let on = SurfaceRef::from_ptr_mut(wl_resource_get_user_data(surface) as *mut weston_surface);
+        let rect = if width == 0 && height == 0 { None } else { Some(Geometry { x, y, width, height }) };
+        api(t).rectangle_set(t.dsurf(), on, rect);
+    }
+}
+
+unsafe extern "C" fn run_set_fullscreen<SC>(_client: *mut wl_client, handle: *mut wl_resource, output: *mut wl_resource) {
+    if let Some(t) = toplevel::<SC>(handle) {
+        let head = if output.is_null() { ptr::null_mut() } else { weston_head_from_resource(output) };
+        let output: *mut weston_output = if head.is_null() { ptr::null_mut() } else { (*head).output };
+        let output = if output.is_null() { None } else { Some(OutputRef::from_ptr_mut(output)) };
+        api(t).fullscreen_requested(t.dsurf(), true, output);
+    }
+}
+
+unsafe extern "C" fn run_unset_fullscreen<SC>(_client: *mut wl_client, handle: *mut wl_resource) {
+    if let Some(t) = toplevel::<SC>(handle) {
+        api(t).fullscreen_requested(t.dsurf(), false, None);
+    }
+}
+
+/// The `zwlr_foreign_toplevel_manager_v1` global and the windows it reports.
+pub struct ForeignToplevelManager<SC> {
+    state: Box<ManagerState<SC>>,
+}
+
+impl<SC> ForeignToplevelManager<SC> {
+    pub fn new(compositor: &mut CompositorRef, api: Box<dyn ForeignToplevelApi<SC>>) -> ForeignToplevelManager<SC> {
+        let mut state = Box::new(ManagerState {
+            compositor: compositor.as_ptr(),
+            api,
+            global: ptr::null_mut(),
+            managers: Vec::new(),
+            toplevels: Vec::new(),
+            manager_impl: ManagerInterface {
+                stop: run_stop,
+            },
+            handle_impl: HandleInterface {
+                set_maximized: run_set_maximized::<SC>,
+                unset_maximized: run_unset_maximized::<SC>,
+                set_minimized: run_set_minimized::<SC>,
+                unset_minimized: run_unset_minimized::<SC>,
+                activate: run_activate::<SC>,
+                close: run_close::<SC>,
+                set_rectangle: run_set_rectangle::<SC>,
+                destroy: run_destroy,
+                set_fullscreen: run_set_fullscreen::<SC>,
+                unset_fullscreen: run_unset_fullscreen::<SC>,
+            },
+        });
+        state.global = unsafe {
+            wl_global_create((*compositor.as_ptr()).wl_display, &zwlr_foreign_toplevel_manager_v1_interface, 2,
+                             &mut *state as *mut _ as *mut libc::c_void, bind_manager::<SC>)
+        };
+        ForeignToplevelManager { state }
+    }
+
+    pub fn api(&mut self) -> &mut dyn ForeignToplevelApi<SC> {
+        &mut *self.state.api
+    }
+
+    /// Starts reporting a window. Popups are ignored.
+    pub fn add(&mut self, surface: &DesktopSurfaceRef<SC>) {
+        if surface.is_popup() || self.state.find(surface.as_ptr()).is_some() {
+            return;
+        }
+        let mut toplevel = Box::new(Toplevel {
+            manager: &mut *self.state,
+            surface: surface.as_ptr(),
+            title: surface.title().map(|t| t.to_owned()),
+            app_id: surface.app_id().map(|a| a.to_owned()),
+            state: ToplevelState::empty(),
+            output_mask: 0,
+            minimized: false,
+            handles: Vec::new(),
+        });
+        toplevel.state = toplevel.current_state();
+        toplevel.output_mask = toplevel.output_mask();
+        for &manager in &self.state.managers {
+            toplevel.create_handle(manager);
+        }
+        self.state.toplevels.push(toplevel);
+    }
+
+    pub fn remove(&mut self, surface: &DesktopSurfaceRef<SC>) {
+        if let Some(pos) = self.state.toplevels.iter().position(|t| t.surface == surface.as_ptr()) {
+            self.state.toplevels.remove(pos).close();
+        }
+    }
+
+    /// Tells taskbars about title, app_id, state and output changes, if any.
+    /// Cheap enough for every `DesktopApi::committed`.
+    pub fn update(&mut self, surface: &DesktopSurfaceRef<SC>) {
+        if let Some(toplevel) = self.state.find(surface.as_ptr()) {
+            toplevel.update();
+        }
+    }
+
+    /// Done by `shell::minimize::MinimizeManager` when it's given this manager (`report_to`)
+    pub fn set_minimized(&mut self, surface: &DesktopSurfaceRef<SC>, minimized: bool) {
+        self.state.set_minimized(surface.as_ptr(), minimized);
+    }
+
+    pub fn is_minimized(&self, surface: &DesktopSurfaceRef<SC>) -> bool {
+        self.state.toplevels.iter().any(|t| t.surface == surface.as_ptr() && t.minimized)
+    }
+
+    pub(crate) fn state_ptr_mut(&mut self) -> *mut ManagerState<SC> {
+        &mut *self.state
+    }
+}
+
+impl<SC> Drop for ForeignToplevelManager<SC> {
+    fn drop(&mut self) {
+        unsafe {
+            // The request tables go away with the state, the clients' resources mustn't point at them
+            for toplevel in self.state.toplevels.iter_mut() {
+                for &handle in &toplevel.handles {
+                    wl_resource_set_implementation(handle, &INERT_HANDLE_IMPL as *const _ as *const libc::c_void,
+                                                   ptr::null_mut(), None);
+                }
+                toplevel.close();
+            }
+            for &manager in &self.state.managers {
+                wl_resource_set_implementation(manager, &INERT_MANAGER_IMPL as *const _ as *const libc::c_void,
+                                               ptr::null_mut(), None);
+            }
+            wl_global_destroy(self.state.global);
+        }
+    }
+}
@@ -1,3 +1,11 @@
+//! libweston-desktop: the shell protocols (`xdg_wm_base`, `zxdg_shell_v6`, `wl_shell`
+//! and Xwayland) behind one API. Surfaces of all of them are `DesktopSurfaceRef`s and
+//! go through the same `DesktopApi` callbacks, so a shell doesn't care which one a client uses.
+//!
+//! `zxdg_decoration_manager_v1` and `zwlr_foreign_toplevel_manager_v1` are implemented on top,
+//! see `decoration` and `foreign_toplevel`.
+//! `rules` matches new windows against per-application window rules.
+
 use libc;
 use libweston_sys::{
     weston_desktop, weston_desktop_create, weston_desktop_destroy,
@@ -5,14 +13,25 @@ use libweston_sys::{
 };
 use foreign_types::ForeignTypeRef;
 use ::compositor::CompositorRef;
+use self::popup::PopupTracker;
+use self::decoration::DecorationManager;
 
 pub mod api;
 pub mod client;
 pub mod surface;
+pub mod popup;
+pub mod decoration;
+pub mod foreign_toplevel;
+pub mod rules;
+mod changes;
 
 pub use self::api::DesktopApi;
 pub use self::client::{DesktopClient, DesktopClientRef};
-pub use self::surface::DesktopSurfaceRef;
+pub use self::surface::{DesktopSurfaceRef, DesktopSurfaceRole};
+pub use self::popup::{Positioner, PositionerAnchor, PositionerGravity, ConstraintAdjustment};
+pub use self::decoration::DecorationMode;
+pub use self::foreign_toplevel::{ForeignToplevelManager, ForeignToplevelApi, ToplevelState};
+pub use self::rules::{Rule, RuleBuilder, RuleActions, Rules};
 
 
 pub struct Desktop<SC> {
@@ -20,6 +39,8 @@ pub struct Desktop<SC> {
     wapi: Box<weston_desktop_api>,
     api: Box<Box<dyn DesktopApi<SC>>>, // heard you like boxes :D
     // but the outer one gets turned into a raw pointer and we get the inner one in callbacks
+    _popups: Box<PopupTracker<SC>>,
+    _decoration: Box<DecorationManager<SC>>,
 }
 
 unsafe impl<SC> Sync for Desktop<SC> {}
@@ -28,10 +49,14 @@ impl<SC> Desktop<SC> {
     pub fn new(compositor: &CompositorRef, api: Box<dyn DesktopApi<SC>>) -> Desktop<SC> {
         let wapi = self::api::make_weston_api::<SC>();
         let mut api = Box::new(api);
+        let popups = PopupTracker::new(compositor, &mut *api);
+        let decoration = DecorationManager::new(compositor, &mut *api);
         Desktop {
             ptr: unsafe { weston_desktop_create(compositor.as_ptr(), &*wapi, &mut *api as *mut _ as *mut libc::c_void) },
             wapi,
             api,
+            _popups: popups,
+            _decoration: decoration,
         }
     }
 
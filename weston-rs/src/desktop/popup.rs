@@ -0,0 +1,521 @@
+//! Popups (menus, tooltips, dropdowns).
+//!
+//! libweston-desktop handles popups by itself (they're placed according to the client's
+//! `xdg_positioner` and get a popup grab if requested) and never reports them to the shell.
+//! `Desktop` watches surface commits to notice them and calls the `DesktopApi::popup_*` callbacks.
+//!
+//! The positioner itself stays inside libweston-desktop, which doesn't implement the
+//! constraint adjustments. Popups that end up outside of `DesktopApi::popup_bounds` are slid
+//! back in. `Positioner` implements the full rules for shell-drawn popups.
+
+use libc;
+use std::cmp;
+use std::mem::ManuallyDrop;
+use std::collections::{HashMap, HashSet};
+use libweston_sys::{
+    weston_compositor, weston_surface, weston_seat, weston_desktop_surface,
+    weston_pointer_grab_interface, weston_keyboard_grab_interface,
+    weston_surface_get_desktop_surface,
+    weston_desktop_seat_from_seat, weston_desktop_seat_popup_grab_get_topmost_surface,
+};
+use wayland_sys::server::wl_list_remove;
+use foreign_types::ForeignTypeRef;
+use ::Geometry;
+use ::compositor::CompositorRef;
+use ::listener::WlListener;
+use ::seat::SeatRef;
+use ::surface::SurfaceRef;
+use super::api::DesktopApi;
+use super::surface::DesktopSurfaceRef;
+
+/// Values of `xdg_positioner.anchor`
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Primitive)]
+pub enum PositionerAnchor {
+    None = 0,
+    Top = 1,
+    Bottom = 2,
+    Left = 3,
+    Right = 4,
+    TopLeft = 5,
+    BottomLeft = 6,
+    TopRight = 7,
+    BottomRight = 8,
+}
+
+/// `xdg_positioner.gravity` has the same values as the anchor
+pub type PositionerGravity = PositionerAnchor;
+
+impl PositionerAnchor {
+    fn from_directions(h: i32, v: i32) -> PositionerAnchor {
+        match (h.signum(), v.signum()) {
+            (0, -1) => PositionerAnchor::Top,
+            (0, 1) => PositionerAnchor::Bottom,
+            (-1, 0) => PositionerAnchor::Left,
+            (1, 0) => PositionerAnchor::Right,
+            (-1, -1) => PositionerAnchor::TopLeft,
+            (-1, 1) => PositionerAnchor::BottomLeft,
+            (1, -1) => PositionerAnchor::TopRight,
+            (1, 1) => PositionerAnchor::BottomRight,
+            _ => PositionerAnchor::None,
+        }
+    }
+
+    /// -1 for left, 1 for right, 0 for centered
+    fn horizontal(self) -> i32 {
+        match self {
+            PositionerAnchor::Left | PositionerAnchor::TopLeft | PositionerAnchor::BottomLeft => -1,
+            PositionerAnchor::Right | PositionerAnchor::TopRight | PositionerAnchor::BottomRight => 1,
+            _ => 0,
+        }
+    }
+
+    /// -1 for top, 1 for bottom, 0 for centered
+    fn vertical(self) -> i32 {
+        match self {
+            PositionerAnchor::Top | PositionerAnchor::TopLeft | PositionerAnchor::TopRight => -1,
+            PositionerAnchor::Bottom | PositionerAnchor::BottomLeft | PositionerAnchor::BottomRight => 1,
+            _ => 0,
+        }
+    }
+
+    pub fn flip_x(self) -> PositionerAnchor {
+        PositionerAnchor::from_directions(-self.horizontal(), self.vertical())
+    }
+
+    pub fn flip_y(self) -> PositionerAnchor {
+        PositionerAnchor::from_directions(self.horizontal(), -self.vertical())
+    }
+}
+
+bitflags! {
+    /// Values of `xdg_positioner.constraint_adjustment`
+    #[derive(Default)]
+    pub struct ConstraintAdjustment: u32 {
+        const SLIDE_X = 1;
+        const SLIDE_Y = 2;
+        const FLIP_X = 4;
+        const FLIP_Y = 8;
+        const RESIZE_X = 16;
+        const RESIZE_Y = 32;
+    }
+}
+
+/// The `xdg_positioner` rules: where a popup goes relative to its parent's window geometry.
+#[derive(Debug, Clone, Copy)]
+pub struct Positioner {
+    pub width: i32,
+    pub height: i32,
+    pub anchor_rect: Geometry,
+    pub anchor: PositionerAnchor,
+    pub gravity: PositionerGravity,
+    pub constraint_adjustment: ConstraintAdjustment,
+    pub offset_x: i32,
+    pub offset_y: i32,
+}
+
+impl Positioner {
+    /// A popup centered on the anchor rectangle, adjust the fields from there.
+    pub fn new(width: i32, height: i32, anchor_rect: Geometry) -> Positioner {
+        Positioner {
+            width,
+            height,
+            anchor_rect,
+            anchor: PositionerAnchor::None,
+            gravity: PositionerAnchor::None,
+            constraint_adjustment: ConstraintAdjustment::empty(),
+            offset_x: 0,
+            offset_y: 0,
+        }
+    }
+
+    fn place(&self, anchor: PositionerAnchor, gravity: PositionerGravity) -> Geometry {
+        let r = &self.anchor_rect;
+        let ax = match anchor.horizontal() {
+            -1 => r.x,
+            1 => r.x + r.width,
+            _ => r.x + r.width / 2,
+        };
+        let ay = match anchor.vertical() {
+            -1 => r.y,
+            1 => r.y + r.height,
+            _ => r.y + r.height / 2,
+        };
+        let x = match gravity.horizontal() {
+            -1 => ax - self.width,
+            1 => ax,
+            _ => ax - self.width / 2,
+        };
+        let y = match gravity.vertical() {
+            -1 => ay - self.height,
+            1 => ay,
+            _ => ay - self.height / 2,
+        };
+        Geometry { x: x + self.offset_x, y: y + self.offset_y, width: self.width, height: self.height }
+    }
+
+    /// The popup geometry (relative to the parent) without any constraints.
+    pub fn unconstrained_geometry(&self) -> Geometry {
+        self.place(self.anchor, self.gravity)
+    }
+
+    /// The popup geometry (relative to the parent) adjusted to fit into `bounds`
+    /// (in global coordinates, e.g. an output's work area) where the adjustments allow.
+    ///
+    /// Like the protocol says, flipping goes first, then sliding, then resizing.
+    pub fn constrained_geometry(&self, parent_x: i32, parent_y: i32, bounds: &Geometry) -> Geometry {
+        let b = Geometry { x: bounds.x - parent_x, y: bounds.y - parent_y, width: bounds.width, height: bounds.height };
+        let mut g = self.unconstrained_geometry();
+        let adj = self.constraint_adjustment;
+
+        if out_x(&g, &b) {
+            if adj.contains(ConstraintAdjustment::FLIP_X) {
+                let flipped = self.place(self.anchor.flip_x(), self.gravity.flip_x());
+                if !out_x(&flipped, &b) {
+                    g.x = flipped.x;
+                }
+            }
+            if out_x(&g, &b) && adj.contains(ConstraintAdjustment::SLIDE_X) {
+                if g.x + g.width > b.x + b.width {
+                    g.x = b.x + b.width - g.width;
+                }
+                // the top left corner wins if it doesn't fit at all
+                if g.x < b.x {
+                    g.x = b.x;
+                }
+            }
+            if out_x(&g, &b) && adj.contains(ConstraintAdjustment::RESIZE_X) {
+                let left = cmp::max(g.x, b.x);
+                let right = cmp::min(g.x + g.width, b.x + b.width);
+                if right > left {
+                    g.x = left;
+                    g.width = right - left;
+                }
+            }
+        }
+
+        if out_y(&g, &b) {
+            if adj.contains(ConstraintAdjustment::FLIP_Y) {
+                let flipped = self.place(self.anchor.flip_y(), self.gravity.flip_y());
+                if !out_y(&flipped, &b) {
+                    g.y = flipped.y;
+                }
+            }
+            if out_y(&g, &b) && adj.contains(ConstraintAdjustment::SLIDE_Y) {
+                if g.y + g.height > b.y + b.height {
+                    g.y = b.y + b.height - g.height;
+                }
+                if g.y < b.y {
+                    g.y = b.y;
+                }
+            }
+            if out_y(&g, &b) && adj.contains(ConstraintAdjustment::RESIZE_Y) {
+                let top = cmp::max(g.y, b.y);
+                let bottom = cmp::min(g.y + g.height, b.y + b.height);
+                if bottom > top {
+                    g.y = top;
+                    g.height = bottom - top;
+                }
+            }
+        }
+
+        g
+    }
+}
+
+fn out_x(g: &Geometry, b: &Geometry) -> bool {
+    g.x < b.x || g.x + g.width > b.x + b.width
+}
+
+fn out_y(g: &Geometry, b: &Geometry) -> bool {
+    g.y < b.y || g.y + g.height > b.y + b.height
+}
+
+type SurfaceListener = ManuallyDrop<Box<WlListener<SurfaceRef>>>;
+
+/// A `PopupTracker` method, with the tracker type-erased so that the listeners don't need
+/// `SC: 'static`
+type TrackerCallback = unsafe fn(*mut libc::c_void, &mut SurfaceRef);
+
+fn tracker_listener(tracker: *mut libc::c_void, callback: TrackerCallback) -> SurfaceListener {
+    WlListener::new(Box::new(move |surface: &mut SurfaceRef| unsafe { callback(tracker, surface) }))
+}
+
+unsafe fn run_watch<SC>(tracker: *mut libc::c_void, surface: &mut SurfaceRef) {
+    (*(tracker as *mut PopupTracker<SC>)).watch(surface)
+}
+
+unsafe fn run_committed<SC>(tracker: *mut libc::c_void, surface: &mut SurfaceRef) {
+    (*(tracker as *mut PopupTracker<SC>)).committed(surface)
+}
+
+unsafe fn run_destroyed<SC>(tracker: *mut libc::c_void, surface: &mut SurfaceRef) {
+    (*(tracker as *mut PopupTracker<SC>)).destroyed(surface)
+}
+
+/// The seat's pointer grab, unless it's the default one
+fn pointer_grab(seat: &SeatRef) -> Option<*const weston_pointer_grab_interface> {
+    seat.pointer().filter(|p| !p.is_default_grab())
+        .map(|p| unsafe { (*(*p.as_ptr()).grab).interface })
+}
+
+/// The seat's keyboard grab, unless it's the default one
+fn keyboard_grab(seat: &SeatRef) -> Option<*const weston_keyboard_grab_interface> {
+    seat.keyboard().filter(|k| !k.is_default_grab())
+        .map(|k| unsafe { (*(*k.as_ptr()).grab).interface })
+}
+
+pub(crate) struct PopupTracker<SC> {
+    api: *mut Box<dyn DesktopApi<SC>>,
+    mapped: HashSet<*mut weston_surface>,
+    grabbed: HashSet<*mut weston_seat>,
+    compositor: *mut weston_compositor,
+    /// The interfaces of libweston-desktop's popup grab. They aren't exported, so they're
+    /// picked up from the first popup that grabs (moves, resizes and menus use others).
+    popup_grab: (Option<*const weston_pointer_grab_interface>, Option<*const weston_keyboard_grab_interface>),
+    create_listener: Option<SurfaceListener>,
+    listeners: HashMap<*mut weston_surface, Vec<SurfaceListener>>,
+    // Listeners can't be freed while they run, these go on the next surface creation
+    dead: Vec<SurfaceListener>,
+}
+
+impl<SC> PopupTracker<SC> {
+    pub(crate) fn new(compositor: &CompositorRef, api: *mut Box<dyn DesktopApi<SC>>) -> Box<PopupTracker<SC>> {
+        let mut tracker = Box::new(PopupTracker {
+            api,
+            mapped: HashSet::new(),
+            grabbed: HashSet::new(),
+            compositor: compositor.as_ptr(),
+            popup_grab: (None, None),
+            create_listener: None,
+            listeners: HashMap::new(),
+            dead: Vec::new(),
+        });
+        let tracker_ptr = &mut *tracker as *mut PopupTracker<SC> as *mut libc::c_void;
+        let mut create = tracker_listener(tracker_ptr, run_watch::<SC>);
+        create.signal_add(compositor.create_surface_signal());
+        tracker.create_listener = Some(create);
+        tracker
+    }
+
+    fn watch(&mut self, surface: &mut SurfaceRef) {
+        for listener in self.dead.drain(..) {
+            drop(ManuallyDrop::into_inner(listener));
+        }
+        let this = self as *mut PopupTracker<SC> as *mut libc::c_void;
+        let mut commit = tracker_listener(this, run_committed::<SC>);
+        commit.signal_add(surface.commit_signal());
+        let mut destroy = tracker_listener(this, run_destroyed::<SC>);
+        destroy.signal_add(surface.destroy_signal());
+        self.listeners.insert(surface.as_ptr(), vec![commit, destroy]);
+    }
+
+    /// A popup's `xdg_popup.grab` comes before it's mapped: the seats where libweston-desktop
+    /// says it's the topmost grabbing popup are in the popup grab.
+    fn learn_popup_grab(&mut self, popup: *mut weston_desktop_surface) {
+        if self.popup_grab.0.is_some() && self.popup_grab.1.is_some() {
+            return;
+        }
+        let compositor = unsafe { CompositorRef::from_ptr(self.compositor) };
+        let grabbing = |seat: &&mut SeatRef| unsafe {
+            let desktop_seat = weston_desktop_seat_from_seat(seat.as_ptr());
+            !desktop_seat.is_null() && weston_desktop_seat_popup_grab_get_topmost_surface(desktop_seat) == popup
+        };
+        for seat in compositor.iterate_seats().filter(grabbing) {
+            if self.popup_grab.0.is_none() {
+                self.popup_grab.0 = pointer_grab(seat);
+            }
+            if self.popup_grab.1.is_none() {
+                self.popup_grab.1 = keyboard_grab(seat);
+            }
+        }
+    }
+
+    fn is_popup_grabbed(&self, seat: &SeatRef) -> bool {
+        let pointer = pointer_grab(seat);
+        let keyboard = keyboard_grab(seat);
+        (pointer.is_some() && pointer == self.popup_grab.0) || (keyboard.is_some() && keyboard == self.popup_grab.1)
+    }
+
+    fn forget(&mut self, surface: &SurfaceRef) {
+        if let Some(listeners) = self.listeners.remove(&surface.as_ptr()) {
+            for mut listener in listeners {
+                unsafe { wl_list_remove(&mut listener.wll.link); }
+                self.dead.push(listener);
+            }
+        }
+    }
+
+    fn api(&self) -> &mut Box<dyn DesktopApi<SC>> {
+        unsafe { &mut *self.api }
+    }
+
+    fn committed(&mut self, surface: &mut SurfaceRef) {
+        if !DesktopSurfaceRef::<SC>::from_surface(surface).map(|d| d.is_popup()).unwrap_or(false) {
+            // Roles are forever, no need to keep looking at other surfaces
+            if surface.role_name().is_some() {
+                self.forget(surface);
+            }
+            return
+        }
+        let popup_ptr = unsafe { weston_surface_get_desktop_surface(surface.as_ptr()) };
+        let popup = unsafe { DesktopSurfaceRef::<SC>::from_ptr_mut(popup_ptr) };
+        let was_mapped = self.mapped.contains(&surface.as_ptr());
+        if surface.is_mapped() && !was_mapped {
+            self.mapped.insert(surface.as_ptr());
+            self.learn_popup_grab(popup_ptr);
+            if let Some(parent) = popup.parent().map(|p| p.as_ptr()) {
+                self.api().popup_mapped(popup, unsafe { DesktopSurfaceRef::from_ptr_mut(parent) });
+            }
+        } else if !surface.is_mapped() && was_mapped {
+            self.unmapped(surface);
+        }
+        if surface.is_mapped() {
+            self.update_grabs(Some(popup_ptr));
+            if let Some(bounds) = self.api().popup_bounds(popup) {
+                slide_into(popup, &bounds);
+            }
+        }
+    }
+
+    fn destroyed(&mut self, surface: &mut SurfaceRef) {
+        if self.mapped.contains(&surface.as_ptr()) {
+            self.unmapped(surface);
+        }
+        self.forget(surface);
+    }
+
+    fn unmapped(&mut self, surface: &mut SurfaceRef) {
+        self.mapped.remove(&surface.as_ptr());
+        self.api().popup_unmapped(surface);
+        self.update_grabs(None);
+    }
+
+    /// libweston-desktop takes over the pointer and keyboard while grabbing popups are shown.
+    fn update_grabs(&mut self, popup: Option<*mut weston_desktop_surface>) {
+        let compositor = unsafe { CompositorRef::from_ptr(self.compositor) };
+        for seat in compositor.iterate_seats() {
+            let grabbing = !self.mapped.is_empty() && self.is_popup_grabbed(seat);
+            let was_grabbing = self.grabbed.contains(&seat.as_ptr());
+            if grabbing && !was_grabbing {
+                if let Some(popup) = popup {
+                    self.grabbed.insert(seat.as_ptr());
+                    self.api().popup_grab_started(unsafe { DesktopSurfaceRef::from_ptr_mut(popup) }, seat);
+                }
+            } else if !grabbing && was_grabbing {
+                self.grabbed.remove(&seat.as_ptr());
+                self.api().popup_grab_ended(seat);
+            }
+        }
+    }
+}
+
+impl<SC> Drop for PopupTracker<SC> {
+    fn drop(&mut self) {
+        let surfaces = self.listeners.drain().flat_map(|(_, listeners)| listeners);
+        for mut listener in self.create_listener.take().into_iter().chain(surfaces) {
+            unsafe { wl_list_remove(&mut listener.wll.link); }
+            drop(ManuallyDrop::into_inner(listener));
+        }
+        for listener in self.dead.drain(..) {
+            drop(ManuallyDrop::into_inner(listener));
+        }
+    }
+}
+
+/// Moves the popup's views so that its window geometry is inside the bounds.
+fn slide_into<SC>(popup: &mut DesktopSurfaceRef<SC>, bounds: &Geometry) {
+    let mut geom = popup.get_geometry();
+    if geom.width <= 0 || geom.height <= 0 {
+        geom = Geometry { x: 0, y: 0, width: popup.surface().width(), height: popup.surface().height() };
+    }
+    for view in popup.surface().iterate_views() {
+        let (parent_x, parent_y) = match view.transform_parent() {
+            Some(parent) => parent.to_global_float(0.0, 0.0),
+            None => continue,
+        };
+        let (x, y) = view.get_position();
+        let at = Geometry { x: x as i32 + geom.x, y: y as i32 + geom.y, width: 0, height: 0 };
+        let mut positioner = Positioner::new(geom.width, geom.height, at);
+        positioner.anchor = PositionerAnchor::TopLeft;
+        positioner.gravity = PositionerAnchor::BottomRight;
+        positioner.constraint_adjustment = ConstraintAdjustment::SLIDE_X | ConstraintAdjustment::SLIDE_Y;
+        let fitted = positioner.constrained_geometry(parent_x as i32, parent_y as i32, bounds);
+        if fitted.x != at.x || fitted.y != at.y {
+            view.set_position((fitted.x - geom.x) as f32, (fitted.y - geom.y) as f32);
+            view.schedule_repaint();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDS: Geometry = Geometry { x: 0, y: 0, width: 100, height: 100 };
+
+    fn positioner(width: i32, height: i32, anchor_rect: Geometry, anchor: PositionerAnchor,
+                  constraint_adjustment: ConstraintAdjustment) -> Positioner {
+        let mut positioner = Positioner::new(width, height, anchor_rect);
+        positioner.anchor = anchor;
+        positioner.gravity = anchor;
+        positioner.constraint_adjustment = constraint_adjustment;
+        positioner
+    }
+
+    fn tuple(g: Geometry) -> (i32, i32, i32, i32) {
+        (g.x, g.y, g.width, g.height)
+    }
+
+    #[test]
+    fn unconstrained_when_it_fits() {
+        let p = positioner(30, 20, Geometry { x: 10, y: 10, width: 10, height: 10 }, PositionerAnchor::BottomRight,
+                           ConstraintAdjustment::all());
+        assert_eq!(tuple(p.constrained_geometry(0, 0, &BOUNDS)), (20, 20, 30, 20));
+    }
+
+    #[test]
+    fn flip() {
+        let p = positioner(30, 20, Geometry { x: 80, y: 10, width: 10, height: 10 }, PositionerAnchor::Right,
+                           ConstraintAdjustment::FLIP_X);
+        assert_eq!(tuple(p.constrained_geometry(0, 0, &BOUNDS)), (50, 5, 30, 20));
+        let p = positioner(30, 40, Geometry { x: 10, y: 80, width: 10, height: 10 }, PositionerAnchor::Bottom,
+                           ConstraintAdjustment::FLIP_Y);
+        assert_eq!(tuple(p.constrained_geometry(0, 0, &BOUNDS)), (0, 40, 30, 40));
+    }
+
+    #[test]
+    fn flip_that_does_not_fit_goes_on_to_slide() {
+        let p = positioner(60, 20, Geometry { x: 50, y: 10, width: 10, height: 10 }, PositionerAnchor::Right,
+                           ConstraintAdjustment::FLIP_X | ConstraintAdjustment::SLIDE_X);
+        assert_eq!(tuple(p.constrained_geometry(0, 0, &BOUNDS)), (40, 5, 60, 20));
+    }
+
+    #[test]
+    fn slide() {
+        let p = positioner(30, 20, Geometry { x: 85, y: 10, width: 10, height: 10 }, PositionerAnchor::Right,
+                           ConstraintAdjustment::SLIDE_X);
+        assert_eq!(tuple(p.constrained_geometry(0, 0, &BOUNDS)), (70, 5, 30, 20));
+        // The bounds are global, the geometry relative to the parent
+        let p = positioner(30, 20, Geometry { x: 30, y: 10, width: 10, height: 10 }, PositionerAnchor::Right,
+                           ConstraintAdjustment::SLIDE_X);
+        assert_eq!(tuple(p.constrained_geometry(50, 0, &BOUNDS)), (20, 5, 30, 20));
+    }
+
+    #[test]
+    fn slide_keeps_the_top_left_corner_of_a_popup_too_big() {
+        let p = positioner(120, 20, Geometry { x: 50, y: 50, width: 0, height: 0 }, PositionerAnchor::None,
+                           ConstraintAdjustment::SLIDE_X);
+        assert_eq!(tuple(p.constrained_geometry(0, 0, &BOUNDS)), (0, 40, 120, 20));
+    }
+
+    #[test]
+    fn resize() {
+        let p = positioner(30, 20, Geometry { x: 80, y: 10, width: 10, height: 10 }, PositionerAnchor::Right,
+                           ConstraintAdjustment::RESIZE_X);
+        assert_eq!(tuple(p.constrained_geometry(0, 0, &BOUNDS)), (90, 5, 10, 20));
+        let p = positioner(30, 40, Geometry { x: 10, y: 80, width: 10, height: 10 }, PositionerAnchor::Bottom,
+                           ConstraintAdjustment::RESIZE_Y);
+        assert_eq!(tuple(p.constrained_geometry(0, 0, &BOUNDS)), (0, 90, 30, 10));
+    }
+}
@@ -0,0 +1,141 @@
+//! Window rules: per-application special cases as data instead of code in the `DesktopApi` impl.
+//!
+//! Clients usually set their app_id and title right before the first commit, so `Rules::actions_for`
+//! is best called on the first `committed` of a surface (or from `title_changed`/`app_id_changed`),
+//! `surface_added` is often too early.
+
+use regex::Regex;
+use ::compositor::CompositorRef;
+use ::view::ViewRef;
+use super::surface::{DesktopSurfaceRef, DesktopSurfaceRole};
+
+/// What to do with a matching window. `None` means "no opinion", later rules fill in or override
+/// what earlier ones set.
+///
+/// `floating`, `always_on_top` and `no_focus` are up to the shell, `apply` only handles what
+/// libweston-desktop can do by itself.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuleActions {
+    /// Relative to the `output` if there is one, global coordinates otherwise
+    pub position: Option<(i32, i32)>,
+    pub size: Option<(i32, i32)>,
+    /// Head name, e.g. "HDMI-A-1"
+    pub output: Option<String>,
+    /// `Some(false)` means tiled
+    pub floating: Option<bool>,
+    pub fullscreen: Option<bool>,
+    pub always_on_top: Option<bool>,
+    /// Never give the window keyboard focus by itself (clicking it still can)
+    pub no_focus: Option<bool>,
+}
+
+impl RuleActions {
+    fn merge(&mut self, other: &RuleActions) {
+        fn over<T: Clone>(a: &mut Option<T>, b: &Option<T>) {
+            if b.is_some() {
+                *a = b.clone();
+            }
+        }
+        over(&mut self.position, &other.position);
+        over(&mut self.size, &other.size);
+        over(&mut self.output, &other.output);
+        over(&mut self.floating, &other.floating);
+        over(&mut self.fullscreen, &other.fullscreen);
+        over(&mut self.always_on_top, &other.always_on_top);
+        over(&mut self.no_focus, &other.no_focus);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == RuleActions::default()
+    }
+
+    /// Position, size and fullscreen state. The view should already be in a layer.
+    pub fn apply<SC>(&self, surface: &mut DesktopSurfaceRef<SC>, view: &mut ViewRef, compositor: &CompositorRef) {
+        let origin = self.output.as_ref()
+            .and_then(|name| compositor.iterate_outputs().find(|o| o.iterate_heads().any(|h| h.get_name().to_str().ok() == Some(name.as_str()))))
+            .map(|o| (o.x(), o.y()));
+        if let Some((width, height)) = self.size {
+            surface.set_size(width, height);
+        }
+        let position = match (self.position, origin) {
+            (Some((x, y)), Some((ox, oy))) => Some((ox + x, oy + y)),
+            (Some(pos), None) => Some(pos),
+            // Only an output: at least put it there
+            (None, Some(origin)) => Some(origin),
+            (None, None) => None,
+        };
+        if let Some((x, y)) = position {
+            view.set_position(x as f32, y as f32);
+            view.geometry_dirty();
+        }
+        if let Some(fullscreen) = self.fullscreen {
+            surface.set_fullscreen(fullscreen);
+        }
+    }
+}
+
+/// A window rule. All the criteria that are set have to match.
+#[derive(Builder, Debug, Clone)]
+pub struct Rule {
+    #[builder(default, setter(into))]
+    app_id: Option<Regex>,
+    #[builder(default, setter(into))]
+    title: Option<Regex>,
+    #[builder(default, setter(into))]
+    role: Option<DesktopSurfaceRole>,
+    /// Whether the window is a child of another one (dialogs), see `DesktopApi::set_parent`
+    #[builder(default, setter(into))]
+    has_parent: Option<bool>,
+    /// Matched against the parent's app_id, never matches windows without a parent
+    #[builder(default, setter(into))]
+    parent_app_id: Option<Regex>,
+    #[builder(default)]
+    actions: RuleActions,
+}
+
+impl Rule {
+    pub fn matches<SC>(&self, surface: &DesktopSurfaceRef<SC>, parent: Option<&DesktopSurfaceRef<SC>>) -> bool {
+        let re_matches = |re: &Option<Regex>, s: &DesktopSurfaceRef<SC>, app_id: bool| {
+            re.as_ref().map(|re| {
+                // Not set yet (always the case in `surface_added`): no match
+                let value = if app_id { s.app_id() } else { s.title() };
+                value.map(|v| re.is_match(&v.to_string_lossy())).unwrap_or(false)
+            }).unwrap_or(true)
+        };
+        re_matches(&self.app_id, surface, true) &&
+            re_matches(&self.title, surface, false) &&
+            self.role.map(|r| r == surface.role()).unwrap_or(true) &&
+            self.has_parent.map(|p| p == parent.is_some()).unwrap_or(true) &&
+            (self.parent_app_id.is_none() || parent.map(|p| re_matches(&self.parent_app_id, p, true)).unwrap_or(false))
+    }
+
+    pub fn actions(&self) -> &RuleActions {
+        &self.actions
+    }
+}
+
+/// An ordered list of rules
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    pub fn new() -> Rules {
+        Rules::default()
+    }
+
+    pub fn add(&mut self, rule: Rule) -> &mut Rules {
+        self.rules.push(rule);
+        self
+    }
+
+    /// The combined actions of all matching rules. `parent` is what the shell got in `DesktopApi::set_parent`.
+    pub fn actions_for<SC>(&self, surface: &DesktopSurfaceRef<SC>, parent: Option<&DesktopSurfaceRef<SC>>) -> RuleActions {
+        let mut result = RuleActions::default();
+        for rule in self.rules.iter().filter(|r| r.matches(surface, parent)) {
+            result.merge(&rule.actions);
+        }
+        result
+    }
+}
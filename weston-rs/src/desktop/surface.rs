@@ -23,6 +23,25 @@ use ::{Geometry, Size};
 use ::surface::SurfaceRef;
 use ::view::View;
 use super::client::DesktopClientRef;
+use super::decoration::{self, DecorationMode};
+
+const_cstr!{
+    ROLE_XDG_TOPLEVEL = "xdg_toplevel";
+    ROLE_XDG_POPUP = "xdg_popup";
+    ROLE_WL_SHELL_SURFACE = "wl_shell_surface";
+    ROLE_XWAYLAND = "xwayland";
+}
+
+/// What kind of shell surface this is, for both `xdg_wm_base` and `zxdg_shell_v6`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DesktopSurfaceRole {
+    Toplevel,
+    /// Only seen through `DesktopApi::popup_*`, libweston-desktop doesn't report popups as added surfaces
+    Popup,
+    WlShell,
+    Xwayland,
+    Unknown,
+}
 
 #[allow(dead_code)]
 #[repr(C)] // prevent reordering to allow ForeignTypeRef to cast self (== first field) to the ptr
@@ -46,6 +65,34 @@ impl<T> DesktopSurfaceRef<T> {
         None
     }
 
+    pub fn role(&self) -> DesktopSurfaceRole {
+        match self.surface().role_name() {
+            Some(r) if r == ROLE_XDG_TOPLEVEL.as_cstr() => DesktopSurfaceRole::Toplevel,
+            Some(r) if r == ROLE_XDG_POPUP.as_cstr() => DesktopSurfaceRole::Popup,
+            Some(r) if r == ROLE_WL_SHELL_SURFACE.as_cstr() => DesktopSurfaceRole::WlShell,
+            Some(r) if r == ROLE_XWAYLAND.as_cstr() => DesktopSurfaceRole::Xwayland,
+            _ => DesktopSurfaceRole::Unknown,
+        }
+    }
+
+    pub fn is_popup(&self) -> bool {
+        self.role() == DesktopSurfaceRole::Popup
+    }
+
+    /// The surface a popup is attached to (found through its view's transform parent).
+    ///
+    /// Toplevels with a parent get `DesktopApi::set_parent` instead.
+    pub fn parent(&self) -> Option<&mut DesktopSurfaceRef<T>> {
+        let view = self.surface().iterate_views().next()?;
+        DesktopSurfaceRef::from_surface(view.transform_parent()?.surface())
+    }
+
+    /// What was negotiated over `zxdg_toplevel_decoration_v1`, `None` if the client doesn't use it
+    /// (then it's the client's call, most draw their own).
+    pub fn decoration_mode(&self) -> Option<DecorationMode> {
+        decoration::mode_of(self.as_ptr())
+    }
+
     pub fn set_user_data(&self, data: Box<T>) -> Option<Box<T>> {
         let prev = self.get_user_data();
         unsafe { weston_desktop_surface_set_user_data(self.as_ptr(), Box::into_raw(data) as *mut libc::c_void); }
@@ -110,12 +157,26 @@ impl<T> DesktopSurfaceRef<T> {
         unsafe { weston_desktop_surface_close(self.as_ptr()); }
     }
 
+    /// `None` until the client sets one
+    pub fn title(&self) -> Option<&ffi::CStr> {
+        let title = unsafe { weston_desktop_surface_get_title(self.as_ptr()) };
+        if title.is_null() { None } else { Some(unsafe { ffi::CStr::from_ptr(title) }) }
+    }
+
+    /// `None` until the client sets one
+    pub fn app_id(&self) -> Option<&ffi::CStr> {
+        let app_id = unsafe { weston_desktop_surface_get_app_id(self.as_ptr()) };
+        if app_id.is_null() { None } else { Some(unsafe { ffi::CStr::from_ptr(app_id) }) }
+    }
+
+    /// Empty until the client sets one
     pub fn get_title(&self) -> &ffi::CStr {
-        unsafe { ffi::CStr::from_ptr(weston_desktop_surface_get_title(self.as_ptr())) }
+        self.title().unwrap_or_default()
     }
 
+    /// Empty until the client sets one
     pub fn get_app_id(&self) -> &ffi::CStr {
-        unsafe { ffi::CStr::from_ptr(weston_desktop_surface_get_app_id(self.as_ptr())) }
+        self.app_id().unwrap_or_default()
     }
 
     pub fn get_pid(&self) -> libc::pid_t {
@@ -0,0 +1,543 @@
+use libc;
+use std::{ffi, mem, ptr};
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use libweston_sys::{
+    weston_surface,
+    os_create_anonymous_file, weston_log,
+};
+use wayland_sys::common::wl_interface;
+use wayland_sys::client::{
+    wl_display as wl_client_display, wl_proxy,
+    wl_display_connect_to_fd, wl_display_disconnect, wl_display_get_fd,
+    wl_display_flush, wl_display_dispatch,
+    wl_proxy_marshal, wl_proxy_marshal_constructor, wl_proxy_marshal_constructor_versioned,
+    wl_proxy_add_listener, wl_proxy_destroy, wl_proxy_get_id,
+};
+use wayland_sys::server::{
+    wl_client, wl_event_source, wl_listener,
+    wl_client_create, wl_client_destroy, wl_client_get_object, wl_client_add_destroy_listener,
+    wl_list_remove,
+    wl_resource_get_user_data,
+    wl_display_get_event_loop,
+    wl_event_loop_add_fd, wl_event_source_remove,
+};
+use foreign_types::ForeignTypeRef;
+use ::Geometry;
+use ::compositor::CompositorRef;
+use ::surface::SurfaceRef;
+
+// libwayland-client exports the core protocol interfaces
+extern "C" {
+    static wl_registry_interface: wl_interface;
+    static wl_callback_interface: wl_interface;
+    static wl_compositor_interface: wl_interface;
+    static wl_shm_interface: wl_interface;
+    static wl_shm_pool_interface: wl_interface;
+    static wl_buffer_interface: wl_interface;
+    static wl_surface_interface: wl_interface;
+    static wl_region_interface: wl_interface;
+}
+
+const WL_DISPLAY_SYNC: u32 = 0;
+const WL_DISPLAY_GET_REGISTRY: u32 = 1;
+const WL_REGISTRY_BIND: u32 = 0;
+const WL_COMPOSITOR_CREATE_SURFACE: u32 = 0;
+const WL_COMPOSITOR_CREATE_REGION: u32 = 1;
+const WL_REGION_DESTROY: u32 = 0;
+const WL_REGION_ADD: u32 = 1;
+const WL_SHM_CREATE_POOL: u32 = 0;
+const WL_SHM_POOL_CREATE_BUFFER: u32 = 0;
+const WL_SHM_POOL_DESTROY: u32 = 1;
+const WL_BUFFER_DESTROY: u32 = 0;
+const WL_SURFACE_DESTROY: u32 = 0;
+const WL_SURFACE_ATTACH: u32 = 1;
+const WL_SURFACE_DAMAGE: u32 = 2;
+const WL_SURFACE_SET_INPUT_REGION: u32 = 5;
+const WL_SURFACE_COMMIT: u32 = 6;
+const WL_SHM_FORMAT_ARGB8888: u32 = 0;
+const WL_EVENT_READABLE: u32 = 0x01;
+
+const_cstr!{
+    DISPATCH_FAILED_MSG = "Internal client connection failed, disconnecting it\n";
+    NO_GLOBALS_MSG = "Internal client: the compositor has no wl_compositor or wl_shm\n";
+}
+
+#[repr(C)]
+struct RegistryListener {
+    global: unsafe extern "C" fn(*mut libc::c_void, *mut wl_proxy, u32, *const libc::c_char, u32),
+    global_remove: unsafe extern "C" fn(*mut libc::c_void, *mut wl_proxy, u32),
+}
+
+static REGISTRY_LISTENER: RegistryListener = RegistryListener {
+    global: run_registry_global,
+    global_remove: run_registry_global_remove,
+};
+
+#[repr(C)]
+struct BufferListener {
+    release: unsafe extern "C" fn(*mut libc::c_void, *mut wl_proxy),
+}
+
+static TRANSIENT_BUFFER_LISTENER: BufferListener = BufferListener {
+    release: run_transient_buffer_release,
+};
+
+static REUSED_BUFFER_LISTENER: BufferListener = BufferListener {
+    release: run_reused_buffer_release,
+};
+
+#[repr(C)]
+struct CallbackListener {
+    done: unsafe extern "C" fn(*mut libc::c_void, *mut wl_proxy, u32),
+}
+
+static INIT_CALLBACK_LISTENER: CallbackListener = CallbackListener {
+    done: run_init_done,
+};
+
+struct Connection {
+    display: *mut wl_client_display,
+    /// Null once the compositor destroyed it (protocol error, shutdown)
+    client: *mut wl_client,
+    client_destroy: wl_listener,
+    source: *mut wl_event_source,
+    registry: *mut wl_proxy,
+    compositor: *mut wl_proxy,
+    shm: *mut wl_proxy,
+    /// Surfaces created before the globals were bound
+    pending: RefCell<Vec<Rc<Cell<*mut wl_proxy>>>>,
+}
+
+impl Connection {
+    fn flush(&self) {
+        unsafe { wl_display_flush(self.display); }
+    }
+
+    fn is_ready(&self) -> bool {
+        !self.compositor.is_null() && !self.shm.is_null()
+    }
+
+    fn new_surface_proxy(&self) -> *mut wl_proxy {
+        unsafe {
+            wl_proxy_marshal_constructor(self.compositor, WL_COMPOSITOR_CREATE_SURFACE, &wl_surface_interface, ptr::null_mut::<libc::c_void>())
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.source.is_null() {
+                wl_event_source_remove(self.source);
+            }
+            for proxy in &[self.shm, self.compositor, self.registry] {
+                if !proxy.is_null() {
+                    wl_proxy_destroy(*proxy);
+                }
+            }
+            wl_display_disconnect(self.display);
+            if !self.client.is_null() {
+                wl_list_remove(&mut self.client_destroy.link);
+                wl_client_destroy(self.client);
+            }
+        }
+    }
+}
+
+/// A Wayland client living inside the compositor process.
+///
+/// libweston only renders surfaces backed by client buffers, so anything the compositor
+/// wants to draw by itself (cursors, decorations, menus) goes through this connection:
+/// the buffers are created on the client side, and the resulting `weston_surface`s can be
+/// used like any other surface on the server side.
+///
+/// The globals are bound asynchronously, on the first event loop iterations. Until then
+/// (see `is_ready`) buffers can't be created, and surfaces are only created once it's done.
+pub struct InternalClient {
+    conn: Rc<Connection>,
+}
+
+impl InternalClient {
+    pub fn new(compositor: &CompositorRef) -> Option<InternalClient> {
+        let mut fds = [0; 2];
+        if unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0, fds.as_mut_ptr()) } != 0 {
+            return None
+        }
+        let server_display = unsafe { (*compositor.as_ptr()).wl_display };
+        let event_loop = unsafe { wl_display_get_event_loop(server_display) };
+        let client = unsafe { wl_client_create(server_display, fds[0]) };
+        if client.is_null() {
+            unsafe { libc::close(fds[0]); libc::close(fds[1]); }
+            return None
+        }
+        let display = unsafe { wl_display_connect_to_fd(fds[1]) };
+        if display.is_null() {
+            unsafe { wl_client_destroy(client); libc::close(fds[1]); }
+            return None
+        }
+        let mut conn = Rc::new(Connection {
+            display,
+            client,
+            client_destroy: unsafe { mem::zeroed() },
+            source: ptr::null_mut(),
+            registry: ptr::null_mut(),
+            compositor: ptr::null_mut(),
+            shm: ptr::null_mut(),
+            pending: RefCell::new(Vec::new()),
+        });
+        {
+            let conn = Rc::get_mut(&mut conn).expect("fresh Rc");
+            let conn_ptr = conn as *mut Connection as *mut libc::c_void;
+            unsafe {
+                conn.client_destroy.notify = run_client_destroyed;
+                wl_client_add_destroy_listener(client, &mut conn.client_destroy);
+                conn.source = wl_event_loop_add_fd(event_loop, wl_display_get_fd(display), WL_EVENT_READABLE, run_dispatch, conn_ptr);
+                conn.registry = wl_proxy_marshal_constructor(
+                    display as *mut wl_proxy, WL_DISPLAY_GET_REGISTRY, &wl_registry_interface, ptr::null_mut::<libc::c_void>());
+                wl_proxy_add_listener(conn.registry, &REGISTRY_LISTENER as *const _ as *mut _, conn_ptr);
+                // The globals are announced before the sync is done
+                let callback = wl_proxy_marshal_constructor(
+                    display as *mut wl_proxy, WL_DISPLAY_SYNC, &wl_callback_interface, ptr::null_mut::<libc::c_void>());
+                wl_proxy_add_listener(callback, &INIT_CALLBACK_LISTENER as *const _ as *mut _, conn_ptr);
+            }
+            conn.flush();
+        }
+        Some(InternalClient { conn })
+    }
+
+    /// Whether the compositor's globals are bound, i.e. buffers can be created
+    pub fn is_ready(&self) -> bool {
+        self.conn.is_ready()
+    }
+
+    /// The server side of the connection, null once the compositor destroyed it
+    pub fn client(&self) -> *mut wl_client {
+        self.conn.client
+    }
+
+    /// Creates a surface. The server side `SurfaceRef` becomes available once the compositor
+    /// has processed the request (i.e. on the next event loop iteration, or later if the client
+    /// isn't ready yet).
+    pub fn create_surface(&mut self) -> InternalSurface {
+        let proxy = Rc::new(Cell::new(ptr::null_mut()));
+        if self.conn.is_ready() {
+            proxy.set(self.conn.new_surface_proxy());
+            self.conn.flush();
+        } else {
+            self.conn.pending.borrow_mut().push(Rc::clone(&proxy));
+        }
+        InternalSurface {
+            conn: Rc::clone(&self.conn),
+            proxy,
+            reused: None,
+        }
+    }
+
+    /// Creates a buffer with the given ARGB8888 (premultiplied, native endian) pixels.
+    pub fn create_buffer(&mut self, width: i32, height: i32, pixels: &[u8]) -> Option<ShmBuffer> {
+        let proxy = create_shm_buffer(&self.conn, width, height, pixels)?;
+        Some(ShmBuffer {
+            conn: Rc::clone(&self.conn),
+            proxy,
+            width,
+            height,
+        })
+    }
+}
+
+fn create_shm_buffer(conn: &Connection, width: i32, height: i32, pixels: &[u8]) -> Option<*mut wl_proxy> {
+    let size = width as usize * 4 * height as usize;
+    if pixels.len() < size {
+        return None
+    }
+    let (buffer, data) = create_mapped_buffer(conn, width, height)?;
+    unsafe {
+        ptr::copy_nonoverlapping(pixels.as_ptr(), data as *mut u8, size);
+        libc::munmap(data, size);
+    }
+    Some(buffer)
+}
+
+/// A buffer and its memory, mapped until the caller unmaps it
+fn create_mapped_buffer(conn: &Connection, width: i32, height: i32) -> Option<(*mut wl_proxy, *mut libc::c_void)> {
+    let stride = width * 4;
+    let size = stride as usize * height as usize;
+    if !conn.is_ready() || width <= 0 || height <= 0 {
+        return None
+    }
+    unsafe {
+        let fd = os_create_anonymous_file(size as libc::off_t);
+        if fd < 0 {
+            return None
+        }
+        let data = libc::mmap(ptr::null_mut(), size, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED, fd, 0);
+        if data == libc::MAP_FAILED {
+            libc::close(fd);
+            return None
+        }
+        let pool = wl_proxy_marshal_constructor(conn.shm, WL_SHM_CREATE_POOL, &wl_shm_pool_interface,
+                                                ptr::null_mut::<libc::c_void>(), fd, size as i32);
+        let buffer = wl_proxy_marshal_constructor(pool, WL_SHM_POOL_CREATE_BUFFER, &wl_buffer_interface,
+                                                  ptr::null_mut::<libc::c_void>(), 0i32, width, height, stride, WL_SHM_FORMAT_ARGB8888);
+        wl_proxy_marshal(pool, WL_SHM_POOL_DESTROY);
+        wl_proxy_destroy(pool);
+        libc::close(fd); // libwayland has its own copy for sending
+        Some((buffer, data))
+    }
+}
+
+/// The buffer `attach_pixels` draws into again while the size stays the same
+struct ReusedBuffer {
+    proxy: *mut wl_proxy,
+    data: *mut libc::c_void,
+    width: i32,
+    height: i32,
+    /// Attached and not released yet, writing to it now could show up half drawn
+    busy: bool,
+    /// Its surface is gone or moved on to another size: destroyed on release
+    orphaned: bool,
+}
+
+impl ReusedBuffer {
+    fn size(&self) -> usize {
+        self.width as usize * 4 * self.height as usize
+    }
+
+    unsafe fn destroy(&mut self) {
+        libc::munmap(self.data, self.size());
+        wl_proxy_marshal(self.proxy, WL_BUFFER_DESTROY);
+        wl_proxy_destroy(self.proxy);
+    }
+}
+
+/// Destroys the buffer now, or once the compositor is done with it
+fn discard_reused(mut buffer: Box<ReusedBuffer>) {
+    if buffer.busy {
+        buffer.orphaned = true;
+        // Freed by the release handler
+        Box::into_raw(buffer);
+    } else {
+        unsafe { buffer.destroy(); }
+    }
+}
+
+/// A reusable buffer (e.g. a cursor image), destroyed when dropped.
+pub struct ShmBuffer {
+    conn: Rc<Connection>,
+    proxy: *mut wl_proxy,
+    width: i32,
+    height: i32,
+}
+
+impl ShmBuffer {
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+}
+
+impl Drop for ShmBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            wl_proxy_marshal(self.proxy, WL_BUFFER_DESTROY);
+            wl_proxy_destroy(self.proxy);
+        }
+        self.conn.flush();
+    }
+}
+
+/// A surface owned by the `InternalClient`, destroyed when dropped.
+pub struct InternalSurface {
+    conn: Rc<Connection>,
+    /// Null until the client is ready, shared with `Connection::pending` until then
+    proxy: Rc<Cell<*mut wl_proxy>>,
+    /// Boxed: the release handler points at it
+    reused: Option<Box<ReusedBuffer>>,
+}
+
+impl InternalSurface {
+    /// The compositor's side of this surface, if it has been created already.
+    pub fn surface(&self) -> Option<&mut SurfaceRef> {
+        if self.proxy.get().is_null() || self.conn.client.is_null() {
+            return None
+        }
+        unsafe {
+            let resource = wl_client_get_object(self.conn.client, wl_proxy_get_id(self.proxy.get()));
+            if resource.is_null() {
+                return None
+            }
+            Some(SurfaceRef::from_ptr_mut(wl_resource_get_user_data(resource) as *mut weston_surface))
+        }
+    }
+
+    /// Shows the buffer's contents on the next repaint (nothing happens before the client is ready).
+    pub fn attach(&mut self, buffer: &ShmBuffer) {
+        self.attach_proxy(buffer.proxy);
+    }
+
+    /// Shows the given ARGB8888 pixels. They're copied into the surface's own buffer, which is
+    /// kept for the next call with the same size; while the compositor still holds on to that one,
+    /// into a buffer that is destroyed once the compositor releases it.
+    pub fn attach_pixels(&mut self, width: i32, height: i32, pixels: &[u8]) -> bool {
+        if self.proxy.get().is_null() || pixels.len() < width as usize * 4 * height as usize {
+            return false
+        }
+        let resized = self.reused.as_ref().map(|b| b.width != width || b.height != height).unwrap_or(true);
+        if resized {
+            if let Some(old) = self.reused.take() {
+                discard_reused(old);
+            }
+            if let Some((proxy, data)) = create_mapped_buffer(&self.conn, width, height) {
+                let mut buffer = Box::new(ReusedBuffer { proxy, data, width, height, busy: false, orphaned: false });
+                unsafe {
+                    wl_proxy_add_listener(proxy, &REUSED_BUFFER_LISTENER as *const _ as *mut _,
+                                          &mut *buffer as *mut ReusedBuffer as *mut libc::c_void);
+                }
+                self.reused = Some(buffer);
+            }
+        }
+        let reused = match self.reused {
+            Some(ref mut buffer) if !buffer.busy => {
+                unsafe { ptr::copy_nonoverlapping(pixels.as_ptr(), buffer.data as *mut u8, buffer.size()); }
+                buffer.busy = true;
+                Some(buffer.proxy)
+            },
+            _ => None,
+        };
+        if let Some(buffer) = reused {
+            self.attach_proxy(buffer);
+            true
+        } else if let Some(buffer) = create_shm_buffer(&self.conn, width, height, pixels) {
+            unsafe { wl_proxy_add_listener(buffer, &TRANSIENT_BUFFER_LISTENER as *const _ as *mut _, ptr::null_mut()); }
+            self.attach_proxy(buffer);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Limits where the surface gets input (e.g. to exclude shadows), `None` for everywhere.
+    /// Takes effect with the next attach.
+    pub fn set_input_region(&mut self, rect: Option<Geometry>) {
+        if self.proxy.get().is_null() {
+            return
+        }
+        unsafe {
+            let region = match rect {
+                Some(rect) => {
+                    let region = wl_proxy_marshal_constructor(self.conn.compositor, WL_COMPOSITOR_CREATE_REGION, &wl_region_interface,
+                                                              ptr::null_mut::<libc::c_void>());
+                    wl_proxy_marshal(region, WL_REGION_ADD, rect.x, rect.y, rect.width, rect.height);
+                    region
+                },
+                None => ptr::null_mut(),
+            };
+            wl_proxy_marshal(self.proxy.get(), WL_SURFACE_SET_INPUT_REGION, region);
+            if !region.is_null() {
+                wl_proxy_marshal(region, WL_REGION_DESTROY);
+                wl_proxy_destroy(region);
+            }
+        }
+    }
+
+    /// Removes the contents, unmapping the surface.
+    pub fn detach(&mut self) {
+        self.attach_proxy(ptr::null_mut());
+    }
+
+    fn attach_proxy(&mut self, buffer: *mut wl_proxy) {
+        let proxy = self.proxy.get();
+        if proxy.is_null() {
+            return
+        }
+        unsafe {
+            wl_proxy_marshal(proxy, WL_SURFACE_ATTACH, buffer, 0i32, 0i32);
+            wl_proxy_marshal(proxy, WL_SURFACE_DAMAGE, 0i32, 0i32, i32::max_value(), i32::max_value());
+            wl_proxy_marshal(proxy, WL_SURFACE_COMMIT);
+        }
+        self.conn.flush();
+    }
+}
+
+impl Drop for InternalSurface {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.reused.take() {
+            discard_reused(buffer);
+        }
+        let proxy = self.proxy.get();
+        if proxy.is_null() {
+            self.conn.pending.borrow_mut().retain(|p| !Rc::ptr_eq(p, &self.proxy));
+            return
+        }
+        unsafe {
+            wl_proxy_marshal(proxy, WL_SURFACE_DESTROY);
+            wl_proxy_destroy(proxy);
+        }
+        self.conn.flush();
+    }
+}
+
+unsafe extern "C" fn run_dispatch(_fd: libc::c_int, _mask: u32, data: *mut libc::c_void) -> libc::c_int {
+    let conn = &mut *(data as *mut Connection);
+    if wl_display_dispatch(conn.display) < 0 {
+        // The surfaces and buffers stay around as dead proxies until dropped
+        weston_log(DISPATCH_FAILED_MSG.as_ptr());
+        wl_event_source_remove(conn.source);
+        conn.source = ptr::null_mut();
+        return 0
+    }
+    conn.flush();
+    0
+}
+
+unsafe extern "C" fn run_client_destroyed(listener: *mut wl_listener, _data: *mut libc::c_void) {
+    let conn = &mut *wl_container_of!(listener, Connection, client_destroy);
+    conn.client = ptr::null_mut();
+}
+
+unsafe extern "C" fn run_init_done(data: *mut libc::c_void, callback: *mut wl_proxy, _serial: u32) {
+    let conn = &*(data as *mut Connection);
+    wl_proxy_destroy(callback);
+    if !conn.is_ready() {
+        weston_log(NO_GLOBALS_MSG.as_ptr());
+        return
+    }
+    for proxy in conn.pending.borrow_mut().drain(..) {
+        proxy.set(conn.new_surface_proxy());
+    }
+}
+
+unsafe extern "C" fn run_registry_global(data: *mut libc::c_void, registry: *mut wl_proxy, name: u32, interface: *const libc::c_char, version: u32) {
+    let conn = &mut *(data as *mut Connection);
+    let interface = ffi::CStr::from_ptr(interface);
+    if interface == ffi::CStr::from_ptr(wl_compositor_interface.name) && conn.compositor.is_null() {
+        conn.compositor = wl_proxy_marshal_constructor_versioned(
+            registry, WL_REGISTRY_BIND, &wl_compositor_interface, version.min(4),
+            name, wl_compositor_interface.name, version.min(4), ptr::null_mut::<libc::c_void>());
+    } else if interface == ffi::CStr::from_ptr(wl_shm_interface.name) && conn.shm.is_null() {
+        conn.shm = wl_proxy_marshal_constructor_versioned(
+            registry, WL_REGISTRY_BIND, &wl_shm_interface, 1,
+            name, wl_shm_interface.name, 1u32, ptr::null_mut::<libc::c_void>());
+    }
+}
+
+unsafe extern "C" fn run_registry_global_remove(_data: *mut libc::c_void, _registry: *mut wl_proxy, _name: u32) {}
+
+unsafe extern "C" fn run_transient_buffer_release(_data: *mut libc::c_void, buffer: *mut wl_proxy) {
+    wl_proxy_marshal(buffer, WL_BUFFER_DESTROY);
+    wl_proxy_destroy(buffer);
+}
+
+unsafe extern "C" fn run_reused_buffer_release(data: *mut libc::c_void, _buffer: *mut wl_proxy) {
+    let buffer = data as *mut ReusedBuffer;
+    if (*buffer).orphaned {
+        let mut buffer = Box::from_raw(buffer);
+        buffer.destroy();
+    } else {
+        (*buffer).busy = false;
+    }
+}
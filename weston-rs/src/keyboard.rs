@@ -118,6 +118,7 @@ impl KeyboardRef {
     obj_accessors!(opt SurfaceRef | focus focus_mut = |&this| { (*this.as_ptr()).focus });
     prop_accessors!(u32 | focus_serial, grab_key, grab_serial);
     prop_accessors!(ptr wl_signal | focus_signal);
+    prop_accessors!(weston_keyboard_grab | default_grab);
 
     pub fn set_focus(&mut self, surface: &SurfaceRef) {
         unsafe { weston_keyboard_set_focus(self.as_ptr(), surface.as_ptr()); }
@@ -152,4 +153,38 @@ impl KeyboardRef {
     pub fn end_grab(&mut self) {
         unsafe { weston_keyboard_end_grab(self.as_ptr()); }
     }
+
+    /// Replaces the grab that's active when nothing else grabs the keyboard.
+    ///
+    /// libweston has no setter for this (unlike `weston_pointer_set_default_grab`), so the
+    /// interface is swapped in place. The previous one is returned, keyboard events
+    /// should usually be passed on to it (with `default_grab_ptr` as the grab argument).
+    pub fn set_default_grab<T: KeyboardGrab>(&mut self, grab: T) -> *const weston_keyboard_grab_interface {
+        unsafe {
+            let prev = (*self.as_ptr()).default_grab.interface;
+            (*self.as_ptr()).default_grab.interface = grab.into_weston();
+            prev
+        }
+    }
+
+    /// Undoes `set_default_grab`, freeing the grab (of type `T`) that it set.
+    ///
+    /// Does nothing and returns false if another default grab was set over it since.
+    pub unsafe fn restore_default_grab<T: KeyboardGrab>(&mut self, grab: *const weston_keyboard_grab_interface,
+                                                        prev: *const weston_keyboard_grab_interface) -> bool {
+        if (*self.as_ptr()).default_grab.interface != grab {
+            return false
+        }
+        (*self.as_ptr()).default_grab.interface = prev;
+        drop(Box::from_raw(wl_container_of!(grab, KeyboardGrabWrapper<T>, base)));
+        true
+    }
+
+    pub fn default_grab_ptr(&mut self) -> *mut weston_keyboard_grab {
+        unsafe { &mut (*self.as_ptr()).default_grab }
+    }
+
+    pub fn is_default_grab(&self) -> bool {
+        unsafe { (*self.as_ptr()).grab == &mut (*self.as_ptr()).default_grab }
+    }
 }
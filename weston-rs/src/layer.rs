@@ -15,7 +15,7 @@ use libweston_sys::{
     weston_layer_entry_insert
 };
 use foreign_types::{ForeignType, ForeignTypeRef};
-use ::compositor::Compositor;
+use ::compositor::CompositorRef;
 use ::view::ViewRef;
 
 /// Layer order (higher value means higher in the stack).
@@ -49,7 +49,7 @@ foreign_type! {
 unsafe impl Sync for Layer {}
 
 impl Layer {
-    pub fn new(compositor: &Compositor) -> Layer {
+    pub fn new(compositor: &CompositorRef) -> Layer {
         let ptr = Box::into_raw(Box::new(unsafe { mem::zeroed() }));
         unsafe {
             weston_layer_init(ptr, compositor.as_ptr());
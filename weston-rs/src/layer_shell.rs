@@ -0,0 +1,764 @@
+//! `zwlr_layer_shell_v1`: panels, docks, wallpapers, launchers and notifications as separate clients.
+//!
+//! The protocol's layers are weston layers at `POSITION_BACKGROUND`, `POSITION_BOTTOM_UI`,
+//! `POSITION_UI` and `POSITION_TOP_UI`, so they stack around windows in `POSITION_NORMAL`.
+//! Exclusive zones shrink the output's work area, see `OutputRef::work_area`.
+//!
+//! `get_popup` is a protocol error: libweston-desktop can't give an `xdg_popup` a parent from
+//! another protocol (and refuses to create one without a parent in the first place).
+
+use libc;
+use std::{cmp, ffi, mem, ptr};
+use libweston_sys::{
+    weston_compositor, weston_output, weston_surface,
+    weston_surface_set_role, weston_head_from_resource, weston_layer_unset_position,
+    zwlr_layer_shell_v1_interface, zwlr_layer_surface_v1_interface,
+};
+use wayland_sys::server::{
+    wl_client, wl_resource, wl_global, wl_listener,
+    wl_global_create, wl_global_destroy, wl_client_post_no_memory, wl_display_next_serial,
+    wl_resource_create, wl_resource_destroy, wl_resource_set_implementation,
+    wl_resource_get_user_data, wl_resource_set_user_data, wl_resource_get_version, wl_resource_add_destroy_listener,
+    wl_resource_post_event, wl_resource_post_error, wl_list_remove,
+};
+use foreign_types::ForeignTypeRef;
+use ::Geometry;
+use ::compositor::CompositorRef;
+use ::layer::{
+    Layer, LayerRef, LayerPosition,
+    POSITION_BACKGROUND, POSITION_BOTTOM_UI, POSITION_UI, POSITION_TOP_UI,
+};
+use ::listener::{WlListener, ListenerList};
+use ::output::OutputRef;
+use ::surface::SurfaceRef;
+use ::view::{View, ViewRef, ActivateFlag};
+
+const ZWLR_LAYER_SHELL_V1_ERROR_ROLE: u32 = 0;
+const ZWLR_LAYER_SHELL_V1_ERROR_INVALID_LAYER: u32 = 1;
+const ZWLR_LAYER_SHELL_V1_ERROR_ALREADY_CONSTRUCTED: u32 = 2;
+const ZWLR_LAYER_SURFACE_V1_CONFIGURE: u32 = 0;
+const ZWLR_LAYER_SURFACE_V1_CLOSED: u32 = 1;
+const ZWLR_LAYER_SURFACE_V1_ERROR_INVALID_SURFACE_STATE: u32 = 0;
+const ZWLR_LAYER_SURFACE_V1_ERROR_INVALID_SIZE: u32 = 1;
+const ZWLR_LAYER_SURFACE_V1_ERROR_INVALID_ANCHOR: u32 = 2;
+
+const_cstr!{
+    LAYER_SURFACE_ROLE = "zwlr_layer_surface_v1";
+    INVALID_LAYER_MSG = "invalid layer";
+    ALREADY_CONSTRUCTED_MSG = "the surface already has a buffer";
+    WRONG_SERIAL_MSG = "wrong configure serial";
+    NO_POPUPS_MSG = "popups of layer surfaces aren't supported";
+    INVALID_ANCHOR_MSG = "invalid anchor";
+    INVALID_WIDTH_MSG = "width 0 needs anchoring to the left and right edges";
+    INVALID_HEIGHT_MSG = "height 0 needs anchoring to the top and bottom edges";
+    EARLY_BUFFER_MSG = "buffer committed before ack_configure";
+}
+
+/// Values of `zwlr_layer_shell_v1.layer`
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Primitive)]
+pub enum LayerShellLayer {
+    Background = 0,
+    Bottom = 1,
+    Top = 2,
+    Overlay = 3,
+}
+
+impl LayerShellLayer {
+    /// Where the surfaces of this layer are stacked
+    pub fn position(self) -> LayerPosition {
+        match self {
+            LayerShellLayer::Background => POSITION_BACKGROUND,
+            LayerShellLayer::Bottom => POSITION_BOTTOM_UI,
+            LayerShellLayer::Top => POSITION_UI,
+            LayerShellLayer::Overlay => POSITION_TOP_UI,
+        }
+    }
+}
+
+// Top to bottom, exclusive zones of higher layers push lower layers away
+const ARRANGE_ORDER: [LayerShellLayer; 4] =
+    [LayerShellLayer::Overlay, LayerShellLayer::Top, LayerShellLayer::Bottom, LayerShellLayer::Background];
+
+bitflags! {
+    /// Values of `zwlr_layer_surface_v1.anchor`
+    #[derive(Default)]
+    pub struct LayerAnchor: u32 {
+        const TOP = 1;
+        const BOTTOM = 2;
+        const LEFT = 4;
+        const RIGHT = 8;
+    }
+}
+
+impl LayerAnchor {
+    /// The edge an exclusive zone is taken from: the surface has to be anchored to one edge,
+    /// or to one edge and both perpendicular ones.
+    pub fn exclusive_edge(self) -> Option<LayerAnchor> {
+        let horizontal = LayerAnchor::LEFT | LayerAnchor::RIGHT;
+        let vertical = LayerAnchor::TOP | LayerAnchor::BOTTOM;
+        for &edge in &[LayerAnchor::TOP, LayerAnchor::BOTTOM] {
+            if self == edge || self == edge | horizontal {
+                return Some(edge);
+            }
+        }
+        for &edge in &[LayerAnchor::LEFT, LayerAnchor::RIGHT] {
+            if self == edge || self == edge | vertical {
+                return Some(edge);
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct LayerMargin {
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+    pub left: i32,
+}
+
+/// The double-buffered part of a layer surface
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct LayerSurfaceState {
+    /// 0 means as large as the anchors allow
+    pub width: u32,
+    pub height: u32,
+    pub anchor: LayerAnchor,
+    /// Positive: reserved area, 0: moved out of other exclusive zones, -1: ignores them
+    pub exclusive_zone: i32,
+    pub margin: LayerMargin,
+    pub keyboard_interactive: bool,
+}
+
+/// Position along one axis: centered when anchored to both or no edges
+fn place_axis(start: i32, len: i32, size: i32, margin_start: i32, margin_end: i32, anchor_start: bool, anchor_end: bool) -> i32 {
+    match (anchor_start, anchor_end) {
+        // Margins larger than the output leave no room to center in
+        (true, true) => start + margin_start + cmp::max(0, len - margin_start - margin_end - size) / 2,
+        (true, false) => start + margin_start,
+        (false, true) => start + len - margin_end - size,
+        (false, false) => start + (len - size) / 2,
+    }
+}
+
+pub trait LayerShellApi {
+    /// Output for surfaces whose client left the choice to the compositor. Defaults to the first one.
+    fn default_output<'a>(&mut self, compositor: &'a mut CompositorRef, _namespace: &ffi::CStr) -> Option<&'a mut OutputRef> {
+        compositor.iterate_outputs().next()
+    }
+
+    fn surface_mapped(&mut self, _surface: &mut LayerSurface) {}
+
+    fn surface_unmapped(&mut self, _surface: &mut LayerSurface) {}
+
+    /// A surface on the top or overlay layer asks for the keyboard (e.g. a launcher).
+    ///
+    /// Defaults to activating it on every seat.
+    fn keyboard_focus_wanted(&mut self, surface: &mut LayerSurface) {
+        if let Some(view) = surface.view_mut() {
+            let compositor = unsafe { CompositorRef::from_ptr((*view.surface().as_ptr()).compositor) };
+            for seat in compositor.iterate_seats() {
+                view.activate(seat, ActivateFlag::NONE);
+            }
+        }
+    }
+
+    /// A surface that got `keyboard_focus_wanted` went away or doesn't want the keyboard anymore.
+    /// The focus has to be given back to a window.
+    fn keyboard_focus_released(&mut self, _surface: &mut LayerSurface) {}
+}
+
+/// A `zwlr_layer_surface_v1`
+pub struct LayerSurface {
+    shell: *mut LayerShellState,
+    resource: *mut wl_resource,
+    /// Null once the wl_surface is gone
+    surface: *mut weston_surface,
+    view: Option<View>,
+    /// Null once closed
+    output: *mut weston_output,
+    layer: LayerShellLayer,
+    namespace: ffi::CString,
+    pending: LayerSurfaceState,
+    current: LayerSurfaceState,
+    /// The initial commit happened, the surface takes part in arranging
+    initialized: bool,
+    configured_size: Option<(i32, i32)>,
+    /// Sent and not acked yet, oldest first
+    configure_serials: Vec<u32>,
+    acked: bool,
+    mapped: bool,
+    has_keyboard: bool,
+    geometry: Geometry,
+    surface_destroy: wl_listener,
+}
+
+impl LayerSurface {
+    pub fn surface(&self) -> Option<&SurfaceRef> {
+        if self.surface.is_null() {
+            None
+        } else {
+            Some(unsafe { SurfaceRef::from_ptr(self.surface) })
+        }
+    }
+
+    pub fn view(&self) -> Option<&ViewRef> {
+        self.view.as_ref().map(|v| &**v)
+    }
+
+    pub fn view_mut(&mut self) -> Option<&mut ViewRef> {
+        self.view.as_mut().map(|v| &mut **v)
+    }
+
+    pub fn output(&self) -> Option<&OutputRef> {
+        if self.output.is_null() {
+            None
+        } else {
+            Some(unsafe { OutputRef::from_ptr(self.output) })
+        }
+    }
+
+    pub fn layer(&self) -> LayerShellLayer {
+        self.layer
+    }
+
+    /// What the client says the surface is for, e.g. "waybar" or "wallpaper"
+    pub fn namespace(&self) -> &ffi::CStr {
+        &self.namespace
+    }
+
+    pub fn state(&self) -> &LayerSurfaceState {
+        &self.current
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        self.mapped
+    }
+
+    /// Where the surface was last placed, in global coordinates
+    pub fn geometry(&self) -> Geometry {
+        self.geometry
+    }
+
+    /// Takes the surface off the screen for good, the client is told to destroy it.
+    pub fn close(&mut self) {
+        if self.output.is_null() {
+            return;
+        }
+        let output = self.output;
+        self.unmap();
+        self.output = ptr::null_mut();
+        unsafe {
+            wl_resource_post_event(self.resource, ZWLR_LAYER_SURFACE_V1_CLOSED);
+            (*self.shell).arrange(output);
+        }
+    }
+
+    // Not tied to the borrow of self, the shell outlives its surfaces
+    fn shell<'a>(&self) -> &'a mut LayerShellState {
+        unsafe { &mut *self.shell }
+    }
+
+    fn unmap(&mut self) {
+        if !self.mapped {
+            return;
+        }
+        self.mapped = false;
+        if let Some(view) = self.view.as_mut() {
+            view.unmap();
+        }
+        if self.has_keyboard {
+            self.has_keyboard = false;
+            self.shell().api.keyboard_focus_released(self);
+        }
+        self.shell().api.surface_unmapped(self);
+    }
+
+    fn map(&mut self) {
+        self.mapped = true;
+        let layer = self.layer;
+        if let Some(view) = self.view.as_mut() {
+            self.shell().layer_mut(layer).view_list_entry_insert(view);
+        }
+        self.shell().api.surface_mapped(self);
+    }
+
+    fn update_keyboard(&mut self) {
+        let wants = self.mapped && self.current.keyboard_interactive &&
+            (self.layer == LayerShellLayer::Top || self.layer == LayerShellLayer::Overlay);
+        if wants && !self.has_keyboard {
+            self.has_keyboard = true;
+            self.shell().api.keyboard_focus_wanted(self);
+        } else if !wants && self.has_keyboard {
+            self.has_keyboard = false;
+            self.shell().api.keyboard_focus_released(self);
+        }
+    }
+
+    /// Sizes and positions the surface inside `bounds`, sending a configure if the size changed.
+    fn place(&mut self, bounds: &Geometry) {
+        let s = self.current;
+        let horizontal = s.anchor.contains(LayerAnchor::LEFT | LayerAnchor::RIGHT);
+        let vertical = s.anchor.contains(LayerAnchor::TOP | LayerAnchor::BOTTOM);
+        let mut width = s.width as i32;
+        let mut height = s.height as i32;
+        // At least a pixel even when the margins take it all, 0 would let the client pick
+        if width == 0 && horizontal {
+            width = cmp::max(1, bounds.width - s.margin.left - s.margin.right);
+        }
+        if height == 0 && vertical {
+            height = cmp::max(1, bounds.height - s.margin.top - s.margin.bottom);
+        }
+        if self.configured_size != Some((width, height)) {
+            self.configured_size = Some((width, height));
+            unsafe {
+                let serial = wl_display_next_serial((*(*self.shell).compositor).wl_display);
+                self.configure_serials.push(serial);
+                wl_resource_post_event(self.resource, ZWLR_LAYER_SURFACE_V1_CONFIGURE, serial, width as u32, height as u32);
+            }
+        }
+        // Clients may pick a smaller size, center what they actually drew
+        if let Some(surface) = self.surface() {
+            if self.mapped {
+                width = surface.width();
+                height = surface.height();
+            }
+        }
+        self.geometry = Geometry {
+            x: place_axis(bounds.x, bounds.width, width, s.margin.left, s.margin.right,
+                          s.anchor.contains(LayerAnchor::LEFT), s.anchor.contains(LayerAnchor::RIGHT)),
+            y: place_axis(bounds.y, bounds.height, height, s.margin.top, s.margin.bottom,
+                          s.anchor.contains(LayerAnchor::TOP), s.anchor.contains(LayerAnchor::BOTTOM)),
+            width,
+            height,
+        };
+        if let Some(view) = self.view.as_mut() {
+            view.set_position(self.geometry.x as f32, self.geometry.y as f32);
+            if self.mapped {
+                view.update_transform();
+                view.schedule_repaint();
+            }
+        }
+    }
+
+    /// Cuts the exclusive zone out of `usable`
+    fn reserve(&self, usable: &mut Geometry) {
+        let s = &self.current;
+        if s.exclusive_zone <= 0 {
+            return;
+        }
+        match s.anchor.exclusive_edge() {
+            Some(LayerAnchor::TOP) => {
+                let d = s.exclusive_zone + s.margin.top;
+                usable.y += d;
+                usable.height -= d;
+            },
+            Some(LayerAnchor::BOTTOM) => usable.height -= s.exclusive_zone + s.margin.bottom,
+            Some(LayerAnchor::LEFT) => {
+                let d = s.exclusive_zone + s.margin.left;
+                usable.x += d;
+                usable.width -= d;
+            },
+            Some(LayerAnchor::RIGHT) => usable.width -= s.exclusive_zone + s.margin.right,
+            _ => (),
+        }
+    }
+
+    /// Acks `serial` and all the configures before it
+    fn ack(&mut self, serial: u32) -> bool {
+        match self.configure_serials.iter().position(|&s| s == serial) {
+            Some(index) => {
+                self.configure_serials.drain(..index + 1);
+                self.acked = true;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// The wl_surface or the protocol object went away
+    fn make_inert(&mut self) {
+        if self.surface.is_null() {
+            return;
+        }
+        let output = self.output;
+        self.unmap();
+        self.view = None;
+        unsafe {
+            wl_list_remove(&mut self.surface_destroy.link);
+            (*self.surface).committed = None;
+            (*self.surface).committed_private = ptr::null_mut();
+        }
+        self.surface = ptr::null_mut();
+        self.output = ptr::null_mut();
+        let this = self as *mut LayerSurface;
+        let shell = self.shell();
+        shell.surfaces.retain(|&s| s != this);
+        if !output.is_null() {
+            shell.arrange(output);
+        }
+    }
+}
+
+struct LayerShellState {
+    compositor: *mut weston_compositor,
+    api: Box<dyn LayerShellApi>,
+    layers: Vec<Layer>,
+    /// In creation order, which is also the stacking order within a layer
+    surfaces: Vec<*mut LayerSurface>,
+    global: *mut wl_global,
+    /// Bound `zwlr_layer_shell_v1`s
+    resources: Vec<*mut wl_resource>,
+    listeners: ListenerList,
+}
+
+impl LayerShellState {
+    fn layer_mut(&mut self, layer: LayerShellLayer) -> &mut LayerRef {
+        &mut self.layers[layer as usize]
+    }
+
+    fn arrange(&mut self, output: *mut weston_output) {
+        let full = unsafe { OutputRef::from_ptr(output) }.geometry();
+        let mut usable = full;
+        for &layer in &ARRANGE_ORDER {
+            // Surfaces with exclusive zones first, so that the others can avoid them
+            for &exclusive in &[true, false] {
+                for &ls in &self.surfaces {
+                    let ls = unsafe { &mut *ls };
+                    if ls.output != output || ls.layer != layer || !ls.initialized ||
+                        (ls.current.exclusive_zone > 0) != exclusive {
+                        continue;
+                    }
+                    let bounds = if ls.current.exclusive_zone == -1 { full } else { usable };
+                    ls.place(&bounds);
+                    if ls.mapped {
+                        ls.reserve(&mut usable);
+                    }
+                }
+            }
+        }
+        unsafe { OutputRef::from_ptr_mut(output) }.set_work_area(usable);
+    }
+
+    fn output_destroyed(&mut self, output: *mut weston_output) {
+        for ls in self.surfaces.clone() {
+            let ls = unsafe { &mut *ls };
+            if ls.output == output {
+                ls.close();
+            }
+        }
+    }
+
+    fn committed(&mut self, ls: &mut LayerSurface) {
+        if ls.output.is_null() {
+            // Closed, the client just hasn't noticed yet
+            return;
+        }
+        ls.current = ls.pending;
+        let s = ls.current;
+        if s.width == 0 && !s.anchor.contains(LayerAnchor::LEFT | LayerAnchor::RIGHT) {
+            unsafe { wl_resource_post_error(ls.resource, ZWLR_LAYER_SURFACE_V1_ERROR_INVALID_SIZE, INVALID_WIDTH_MSG.as_ptr()); }
+            return;
+        }
+        if s.height == 0 && !s.anchor.contains(LayerAnchor::TOP | LayerAnchor::BOTTOM) {
+            unsafe { wl_resource_post_error(ls.resource, ZWLR_LAYER_SURFACE_V1_ERROR_INVALID_SIZE, INVALID_HEIGHT_MSG.as_ptr()); }
+            return;
+        }
+        let has_buffer = unsafe { !(*ls.surface).buffer_ref.buffer.is_null() };
+        if !ls.initialized {
+            if has_buffer {
+                unsafe { wl_resource_post_error(ls.resource, ZWLR_LAYER_SURFACE_V1_ERROR_INVALID_SURFACE_STATE, EARLY_BUFFER_MSG.as_ptr()); }
+                return;
+            }
+            // The initial commit, answered with the first configure
+            ls.initialized = true;
+            self.arrange(ls.output);
+            return;
+        }
+        if !has_buffer {
+            if ls.mapped {
+                // Unmapping resets the surface, the next commit is an initial one again
+                ls.unmap();
+                ls.initialized = false;
+                ls.acked = false;
+                ls.configured_size = None;
+                ls.configure_serials.clear();
+                self.arrange(ls.output);
+            }
+            return;
+        }
+        if !ls.acked {
+            unsafe { wl_resource_post_error(ls.resource, ZWLR_LAYER_SURFACE_V1_ERROR_INVALID_SURFACE_STATE, EARLY_BUFFER_MSG.as_ptr()); }
+            return;
+        }
+        if !ls.mapped {
+            ls.map();
+        }
+        self.arrange(ls.output);
+        ls.update_keyboard();
+    }
+}
+
+#[repr(C)]
+struct LayerShellInterface {
+    get_layer_surface: unsafe extern "C" fn(*mut wl_client, *mut wl_resource, u32, *mut wl_resource, *mut wl_resource, u32, *const libc::c_char),
+}
+
+#[repr(C)]
+struct LayerSurfaceInterface {
+    set_size: unsafe extern "C" fn(*mut wl_client, *mut wl_resource, u32, u32),
+    set_anchor: unsafe extern "C" fn(*mut wl_client, *mut wl_resource, u32),
+    set_exclusive_zone: unsafe extern "C" fn(*mut wl_client, *mut wl_resource, i32),
+    set_margin: unsafe extern "C" fn(*mut wl_client, *mut wl_resource, i32, i32, i32, i32),
+    set_keyboard_interactivity: unsafe extern "C" fn(*mut wl_client, *mut wl_resource, u32),
+    get_popup: unsafe extern "C" fn(*mut wl_client, *mut wl_resource, *mut wl_resource),
+    ack_configure: unsafe extern "C" fn(*mut wl_client, *mut wl_resource, u32),
+    destroy: unsafe extern "C" fn(*mut wl_client, *mut wl_resource),
+}
+
+static LAYER_SHELL_IMPL: LayerShellInterface = LayerShellInterface {
+    get_layer_surface: run_get_layer_surface,
+};
+
+static LAYER_SURFACE_IMPL: LayerSurfaceInterface = LayerSurfaceInterface {
+    set_size: run_set_size,
+    set_anchor: run_set_anchor,
+    set_exclusive_zone: run_set_exclusive_zone,
+    set_margin: run_set_margin,
+    set_keyboard_interactivity: run_set_keyboard_interactivity,
+    get_popup: run_get_popup,
+    ack_configure: run_ack_configure,
+    destroy: run_destroy,
+};
+
+unsafe extern "C" fn bind_layer_shell(client: *mut wl_client, data: *mut libc::c_void, version: u32, id: u32) {
+    let resource = wl_resource_create(client, &zwlr_layer_shell_v1_interface, version as libc::c_int, id);
+    if resource.is_null() {
+        wl_client_post_no_memory(client);
+        return;
+    }
+    wl_resource_set_implementation(resource, &LAYER_SHELL_IMPL as *const _ as *const libc::c_void, data,
+                                   Some(run_layer_shell_destroyed));
+    (*(data as *mut LayerShellState)).resources.push(resource);
+}
+
+unsafe extern "C" fn run_layer_shell_destroyed(resource: *mut wl_resource) {
+    let shell = wl_resource_get_user_data(resource) as *mut LayerShellState;
+    if shell.is_null() {
+        return;
+    }
+    (*shell).resources.retain(|&r| r != resource);
+}
+
+unsafe extern "C" fn run_get_layer_surface(client: *mut wl_client, resource: *mut wl_resource, id: u32,
+                                           surface_resource: *mut wl_resource, output_resource: *mut wl_resource,
+                                           layer: u32, namespace: *const libc::c_char) {
+    use num_traits::FromPrimitive;
+    let shell = wl_resource_get_user_data(resource) as *mut LayerShellState;
+    let surface = wl_resource_get_user_data(surface_resource) as *mut weston_surface;
+    let layer = match LayerShellLayer::from_u32(layer) {
+        Some(layer) => layer,
+        None => {
+            wl_resource_post_error(resource, ZWLR_LAYER_SHELL_V1_ERROR_INVALID_LAYER, INVALID_LAYER_MSG.as_ptr());
+            return;
+        },
+    };
+    if weston_surface_set_role(surface, LAYER_SURFACE_ROLE.as_ptr(), resource, ZWLR_LAYER_SHELL_V1_ERROR_ROLE) < 0 {
+        return;
+    }
+    if !(*surface).buffer_ref.buffer.is_null() {
+        wl_resource_post_error(resource, ZWLR_LAYER_SHELL_V1_ERROR_ALREADY_CONSTRUCTED, ALREADY_CONSTRUCTED_MSG.as_ptr());
+        return;
+    }
+    let namespace = ffi::CStr::from_ptr(namespace).to_owned();
+    // Without an output (or once the shell is gone) the surface is created closed, like wlroots does
+    let output = if shell.is_null() {
+        ptr::null_mut()
+    } else if output_resource.is_null() {
+        let compositor = CompositorRef::from_ptr_mut((*shell).compositor);
+        (*shell).api.default_output(compositor, &namespace).map(|o| o.as_ptr()).unwrap_or(ptr::null_mut())
+    } else {
+        let head = weston_head_from_resource(output_resource);
+        if head.is_null() { ptr::null_mut() } else { (*head).output }
+    };
+    let layer_resource = wl_resource_create(client, &zwlr_layer_surface_v1_interface, wl_resource_get_version(resource), id);
+    if layer_resource.is_null() {
+        wl_client_post_no_memory(client);
+        return;
+    }
+    let mut ls = Box::new(LayerSurface {
+        shell,
+        resource: layer_resource,
+        surface,
+        view: None,
+        output,
+        layer,
+        namespace,
+        pending: LayerSurfaceState::default(),
+        current: LayerSurfaceState::default(),
+        initialized: false,
+        configured_size: None,
+        configure_serials: Vec::new(),
+        acked: false,
+        mapped: false,
+        has_keyboard: false,
+        geometry: mem::zeroed(),
+        surface_destroy: wl_listener {
+            link: mem::zeroed(),
+            notify: run_surface_destroyed,
+        },
+    });
+    if output.is_null() {
+        ls.surface = ptr::null_mut();
+        wl_resource_set_implementation(layer_resource, &LAYER_SURFACE_IMPL as *const _ as *const libc::c_void,
+                                       Box::into_raw(ls) as *mut libc::c_void, Some(run_layer_surface_destroyed));
+        wl_resource_post_event(layer_resource, ZWLR_LAYER_SURFACE_V1_CLOSED);
+        return;
+    }
+    ls.view = Some(View::new(SurfaceRef::from_ptr(surface)));
+    wl_resource_add_destroy_listener(surface_resource, &mut ls.surface_destroy);
+    let ls = Box::into_raw(ls);
+    (*surface).committed = Some(run_committed);
+    (*surface).committed_private = ls as *mut libc::c_void;
+    (*shell).surfaces.push(ls);
+    wl_resource_set_implementation(layer_resource, &LAYER_SURFACE_IMPL as *const _ as *const libc::c_void,
+                                   ls as *mut libc::c_void, Some(run_layer_surface_destroyed));
+}
+
+unsafe fn layer_surface<'a>(resource: *mut wl_resource) -> &'a mut LayerSurface {
+    &mut *(wl_resource_get_user_data(resource) as *mut LayerSurface)
+}
+
+unsafe extern "C" fn run_set_size(_client: *mut wl_client, resource: *mut wl_resource, width: u32, height: u32) {
+    let ls = layer_surface(resource);
+    ls.pending.width = width;
+    ls.pending.height = height;
+}
+
+unsafe extern "C" fn run_set_anchor(_client: *mut wl_client, resource: *mut wl_resource, anchor: u32) {
+    match LayerAnchor::from_bits(anchor) {
+        Some(anchor) => layer_surface(resource).pending.anchor = anchor,
+        None => wl_resource_post_error(resource, ZWLR_LAYER_SURFACE_V1_ERROR_INVALID_ANCHOR, INVALID_ANCHOR_MSG.as_ptr()),
+    }
+}
+
+unsafe extern "C" fn run_set_exclusive_zone(_client: *mut wl_client, resource: *mut wl_resource, zone: i32) {
+    layer_surface(resource).pending.exclusive_zone = zone;
+}
+
+unsafe extern "C" fn run_set_margin(_client: *mut wl_client, resource: *mut wl_resource, top: i32, right: i32, bottom: i32, left: i32) {
+    layer_surface(resource).pending.margin = LayerMargin { top, right, bottom, left };
+}
+
+unsafe extern "C" fn run_set_keyboard_interactivity(_client: *mut wl_client, resource: *mut wl_resource, interactive: u32) {
+    layer_surface(resource).pending.keyboard_interactive = interactive != 0;
+}
+
+unsafe extern "C" fn run_get_popup(_client: *mut wl_client, resource: *mut wl_resource, _popup: *mut wl_resource) {
+    wl_resource_post_error(resource, ZWLR_LAYER_SURFACE_V1_ERROR_INVALID_SURFACE_STATE, NO_POPUPS_MSG.as_ptr());
+}
+
+unsafe extern "C" fn run_ack_configure(_client: *mut wl_client, resource: *mut wl_resource, serial: u32) {
+    let ls = layer_surface(resource);
+    // Closed surfaces don't get configures, there's nothing to check
+    if !ls.output.is_null() && !ls.ack(serial) {
+        wl_resource_post_error(resource, ZWLR_LAYER_SURFACE_V1_ERROR_INVALID_SURFACE_STATE, WRONG_SERIAL_MSG.as_ptr());
+    }
+}
+
+unsafe extern "C" fn run_destroy(_client: *mut wl_client, resource: *mut wl_resource) {
+    wl_resource_destroy(resource);
+}
+
+unsafe extern "C" fn run_committed(surface: *mut weston_surface, _sx: i32, _sy: i32) {
+    let ls = &mut *((*surface).committed_private as *mut LayerSurface);
+    (*ls.shell).committed(ls);
+}
+
+unsafe extern "C" fn run_layer_surface_destroyed(resource: *mut wl_resource) {
+    let mut ls = Box::from_raw(wl_resource_get_user_data(resource) as *mut LayerSurface);
+    ls.make_inert();
+}
+
+unsafe extern "C" fn run_surface_destroyed(listener: *mut wl_listener, _data: *mut libc::c_void) {
+    let ls = &mut *wl_container_of!(listener, LayerSurface, surface_destroy);
+    ls.make_inert();
+}
+
+/// The `zwlr_layer_shell_v1` global plus the weston layers its surfaces go into.
+pub struct LayerShell {
+    state: Box<LayerShellState>,
+}
+
+impl LayerShell {
+    pub fn new(compositor: &mut CompositorRef, api: Box<dyn LayerShellApi>) -> LayerShell {
+        let layers = [LayerShellLayer::Background, LayerShellLayer::Bottom, LayerShellLayer::Top, LayerShellLayer::Overlay]
+            .iter().map(|l| {
+                let mut layer = Layer::new(compositor);
+                layer.set_position(l.position());
+                layer
+            }).collect();
+        let mut state = Box::new(LayerShellState {
+            compositor: compositor.as_ptr(),
+            api,
+            layers,
+            surfaces: Vec::new(),
+            global: ptr::null_mut(),
+            resources: Vec::new(),
+            listeners: ListenerList::new(),
+        });
+        let state_ptr = &mut *state as *mut LayerShellState;
+        state.global = unsafe {
+            wl_global_create((*compositor.as_ptr()).wl_display, &zwlr_layer_shell_v1_interface, 1,
+                             state_ptr as *mut libc::c_void, bind_layer_shell)
+        };
+        state.listeners.add(WlListener::new(Box::new(move |output: &mut OutputRef| unsafe { (*state_ptr).output_destroyed(output.as_ptr()) })),
+                            compositor.output_destroyed_signal());
+        state.listeners.add(WlListener::new(Box::new(move |output: &mut OutputRef| unsafe { (*state_ptr).arrange(output.as_ptr()) })),
+                            compositor.output_moved_signal());
+        state.listeners.add(WlListener::new(Box::new(move |output: &mut OutputRef| unsafe { (*state_ptr).arrange(output.as_ptr()) })),
+                            compositor.output_resized_signal());
+        LayerShell { state }
+    }
+
+    pub fn api(&mut self) -> &mut dyn LayerShellApi {
+        &mut *self.state.api
+    }
+
+    /// The weston layer a protocol layer's surfaces are in, e.g. to put a compositor-drawn background below them.
+    pub fn layer(&mut self, layer: LayerShellLayer) -> &mut LayerRef {
+        self.state.layer_mut(layer)
+    }
+
+    /// Places the layer surfaces of an output again
+    pub fn arrange(&mut self, output: &OutputRef) {
+        self.state.arrange(output.as_ptr());
+    }
+
+    pub fn surfaces(&mut self) -> Vec<&mut LayerSurface> {
+        self.state.surfaces.iter().map(|&ls| unsafe { &mut *ls }).collect()
+    }
+}
+
+impl Drop for LayerShell {
+    fn drop(&mut self) {
+        // The surfaces' resources stay with the clients: close them and cut them off the shell
+        for ls in self.state.surfaces.clone() {
+            let ls = unsafe { &mut *ls };
+            ls.close();
+            ls.make_inert();
+        }
+        unsafe {
+            for &resource in &self.state.resources {
+                wl_resource_set_user_data(resource, ptr::null_mut());
+            }
+            wl_global_destroy(self.state.global);
+            // Dropping a layer doesn't take it out of the compositor's list
+            for layer in &self.state.layers {
+                weston_layer_unset_position(layer.as_ptr());
+            }
+        }
+    }
+}
@@ -22,6 +22,7 @@ extern crate derive_builder;
 #[macro_use]
 extern crate lazy_static;
 extern crate mut_static;
+extern crate regex;
 extern crate tiny_nix_ipc;
 extern crate loginw;
 
@@ -126,6 +127,7 @@ macro_rules! wl_container_of {
 
 pub mod ev;
 pub mod matrix;
+mod object_map;
 pub mod listener;
 pub mod compositor;
 pub mod launcher;
@@ -136,12 +138,21 @@ pub mod output;
 pub mod head;
 pub mod seat;
 pub mod pointer;
+pub mod pointer_constraints;
+pub mod cursor;
 pub mod keyboard;
 pub mod touch;
 pub mod layer;
+pub mod layer_shell;
+pub mod shell;
 pub mod surface;
 pub mod view;
 pub mod desktop;
+pub mod decorations;
+pub mod internal_client;
+pub mod timer;
+#[cfg(feature = "xwayland")]
+pub mod xwayland;
 
 pub use memoffset::*;
 pub use matrix::*;
@@ -155,12 +166,15 @@ pub use output::*;
 pub use head::*;
 pub use seat::*;
 pub use pointer::*;
+pub use pointer_constraints::*;
 pub use keyboard::*;
 pub use touch::*;
 pub use layer::*;
 pub use surface::*;
 pub use view::*;
 pub use desktop::*;
+pub use internal_client::*;
+pub use timer::*;
 
 
 #[cfg(target_arch = "aarch64")]
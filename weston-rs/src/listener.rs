@@ -1,7 +1,8 @@
 use std::mem;
 use std::os::raw::c_void;
 use foreign_types::ForeignTypeRef;
-use wayland_sys::server::{signal, wl_signal, wl_listener, wl_list_init};
+use libweston_sys::weston_seat;
+use wayland_sys::server::{signal, wl_signal, wl_listener, wl_list_init, wl_list_remove};
 
 pub struct WlListener<T: ForeignTypeRef> {
     cb: Box<FnMut(&mut T)>,
@@ -28,3 +29,88 @@ impl<T: ForeignTypeRef> WlListener<T> {
         unsafe { signal::wl_signal_add(signal, &mut self.wll); }
     }
 }
+
+/// A listener of any signal, for keeping them together
+pub trait Listener {
+    /// Unlinks the listener from its signal (doing it twice is fine)
+    fn remove(&mut self);
+}
+
+impl<T: ForeignTypeRef> Listener for WlListener<T> {
+    fn remove(&mut self) {
+        unsafe {
+            wl_list_remove(&mut self.wll.link);
+            wl_list_init(&mut self.wll.link);
+        }
+    }
+}
+
+/// Listeners owned by some state, removed when it's dropped.
+///
+/// A listener can't be freed while it runs: in a callback, `detach` the list and drop it later.
+#[derive(Default)]
+pub struct ListenerList {
+    listeners: Vec<Box<dyn Listener>>,
+}
+
+impl ListenerList {
+    pub fn new() -> ListenerList {
+        ListenerList { listeners: Vec::new() }
+    }
+
+    pub fn add<T: ForeignTypeRef + 'static>(&mut self, mut listener: mem::ManuallyDrop<Box<WlListener<T>>>, signal: &mut wl_signal) {
+        listener.signal_add(signal);
+        self.listeners.push(mem::ManuallyDrop::into_inner(listener));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+
+    /// Unlinks all the listeners, returning them to be freed once they can't be running
+    pub fn detach(&mut self) -> ListenerList {
+        for listener in &mut self.listeners {
+            listener.remove();
+        }
+        ListenerList { listeners: mem::replace(&mut self.listeners, Vec::new()) }
+    }
+
+    /// Frees the listeners without unlinking them, when their signals are already gone
+    /// (e.g. the signals of a seat's devices once the seat's `destroy_signal` is emitted)
+    pub unsafe fn forget(&mut self) {
+        self.listeners.clear();
+    }
+}
+
+impl Drop for ListenerList {
+    fn drop(&mut self) {
+        for listener in &mut self.listeners {
+            listener.remove();
+        }
+    }
+}
+
+/// Listeners on a seat and on its devices.
+///
+/// `weston_seat_release` frees the devices before emitting the seat's `destroy_signal`,
+/// so their listeners can't be unlinked from there: see `destroyed`.
+pub struct SeatListeners {
+    pub seat: *mut weston_seat,
+    /// On the pointer, keyboard or touch
+    pub devices: ListenerList,
+    /// On the seat itself (including its `destroy_signal`)
+    pub own: ListenerList,
+}
+
+impl SeatListeners {
+    pub fn new(seat: *mut weston_seat) -> SeatListeners {
+        SeatListeners { seat, devices: ListenerList::new(), own: ListenerList::new() }
+    }
+
+    /// For the seat's `destroy_signal`: frees the device listeners and returns the seat's own
+    /// (one of them is running), to be dropped later
+    pub fn destroyed(mut self) -> ListenerList {
+        unsafe { self.devices.forget(); }
+        self.own.detach()
+    }
+}
@@ -0,0 +1,41 @@
+//! State that C callbacks reach through the libweston object they were called with.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// Some state per libweston object, keyed by its address.
+///
+/// The compositor runs on a single thread, so these live in `thread_local!`s.
+/// Accessing a map from inside one of its own `with` calls finds nothing.
+pub(crate) struct ObjectMap<K, V> {
+    map: RefCell<HashMap<*mut K, V>>,
+}
+
+impl<K, V> ObjectMap<K, V> {
+    pub fn new() -> ObjectMap<K, V> {
+        ObjectMap { map: RefCell::new(HashMap::new()) }
+    }
+
+    pub fn contains(&self, key: *mut K) -> bool {
+        self.map.try_borrow().map(|m| m.contains_key(&key)).unwrap_or(false)
+    }
+
+    pub fn get(&self, key: *mut K) -> Option<V> where V: Clone {
+        self.map.try_borrow().ok().and_then(|m| m.get(&key).cloned())
+    }
+
+    pub fn insert(&self, key: *mut K, value: V) -> Option<V> {
+        self.map.try_borrow_mut().ok().and_then(|mut m| m.insert(key, value))
+    }
+
+    pub fn remove(&self, key: *mut K) -> Option<V> {
+        self.map.try_borrow_mut().ok().and_then(|mut m| m.remove(&key))
+    }
+
+    /// Runs `f` on the object's state, if it has any
+    pub fn with<R, F: FnOnce(&mut V) -> R>(&self, key: *mut K, f: F) -> Option<R> {
+        let mut map = self.map.try_borrow_mut().ok()?;
+        let result = map.get_mut(&key).map(f);
+        result
+    }
+}
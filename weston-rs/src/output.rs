@@ -1,4 +1,4 @@
-use std::ptr;
+use std::{ptr, mem};
 use libc;
 use libweston_sys::{
     weston_output,
@@ -6,9 +6,12 @@ use libweston_sys::{
     weston_output_enable, weston_output_disable, weston_output_destroy,
     weston_output_iterate_heads, weston_output_attach_head, weston_head,
 };
-use wayland_sys::server::wl_signal;
+use wayland_sys::server::{signal, wl_signal, wl_list_remove};
 use foreign_types::ForeignTypeRef;
+use ::Geometry;
 use ::head::HeadRef;
+use ::listener::WlListener;
+use ::object_map::ObjectMap;
 
 foreign_type! {
     type CType = weston_output;
@@ -35,6 +38,16 @@ impl<'a> Iterator for HeadIterator<'a> {
     }
 }
 
+struct WorkArea {
+    area: Option<Geometry>,
+    // boxed so that listeners can keep pointing at it when the map grows
+    changed_signal: Box<wl_signal>,
+}
+
+thread_local! {
+    static WORK_AREAS: ObjectMap<weston_output, WorkArea> = ObjectMap::new();
+}
+
 impl OutputRef {
     prop_accessors!(u32 | id, transform);
     prop_accessors!(i32 | x, y, width, height, native_scale, current_scale, original_scale);
@@ -72,4 +85,54 @@ impl OutputRef {
             head: ptr::null_mut(),
         }
     }
+
+    /// The output's rectangle in global coordinates
+    pub fn geometry(&self) -> Geometry {
+        Geometry { x: self.x(), y: self.y(), width: self.width(), height: self.height() }
+    }
+
+    /// The part of the output not covered by panels, where maximized windows should go.
+    ///
+    /// Kept up to date by `layer_shell::LayerShell` from exclusive zones. The whole output if nothing set it.
+    pub fn work_area(&self) -> Geometry {
+        WORK_AREAS.with(|w| w.with(self.as_ptr(), |entry| entry.area))
+            .and_then(|area| area)
+            .unwrap_or_else(|| self.geometry())
+    }
+
+    /// For shells drawing their own panels. Emits `work_area_changed_signal` if the area is different.
+    pub fn set_work_area(&mut self, area: Geometry) {
+        let signal = self.work_area_changed_signal() as *mut wl_signal;
+        let geometry = self.geometry();
+        let changed = WORK_AREAS.with(|w| w.with(self.as_ptr(), |entry| {
+            let prev = entry.area.unwrap_or(geometry);
+            entry.area = Some(area);
+            prev.x != area.x || prev.y != area.y || prev.width != area.width || prev.height != area.height
+        })).unwrap_or(false);
+        // not inside the map's borrow, listeners usually call work_area()
+        if changed {
+            unsafe { signal::wl_signal_emit(&mut *signal, self.as_ptr() as *mut libc::c_void); }
+        }
+    }
+
+    /// Emitted with the output when `work_area` changes
+    pub fn work_area_changed_signal(&mut self) -> &mut wl_signal {
+        let ptr = self.as_ptr();
+        let existing = WORK_AREAS.with(|w| w.with(ptr, |e| &mut *e.changed_signal as *mut wl_signal));
+        if let Some(signal) = existing {
+            return unsafe { &mut *signal };
+        }
+        let mut changed_signal: Box<wl_signal> = Box::new(unsafe { mem::zeroed() });
+        unsafe { signal::wl_signal_init(&mut *changed_signal); }
+        let signal = &mut *changed_signal as *mut wl_signal;
+        WORK_AREAS.with(|w| w.insert(ptr, WorkArea { area: None, changed_signal }));
+        WlListener::new(Box::new(|output: &mut OutputRef| {
+            if let Some(mut entry) = WORK_AREAS.with(|w| w.remove(output.as_ptr())) {
+                // Listeners still on the signal unlink themselves later (maybe from this same
+                // destroy_signal), leave them a list without the freed head
+                unsafe { wl_list_remove(&mut entry.changed_signal.listener_list); }
+            }
+        })).signal_add(self.destroy_signal());
+        unsafe { &mut *signal }
+    }
 }
@@ -15,12 +15,13 @@ use libweston_sys::{
     weston_pointer_set_focus, weston_pointer_clear_focus,
     weston_pointer_clamp, weston_pointer_move,
 };
-use wayland_sys::common::wl_fixed_t;
+use wayland_sys::common::{wl_fixed_t, wl_fixed_to_double};
 use wayland_sys::server::wl_signal;
 pub use wayland_server::protocol::wl_pointer::{Axis, AxisSource, ButtonState};
 use foreign_types::ForeignTypeRef;
 use ::seat::SeatRef;
 use ::view::ViewRef;
+use ::pointer_constraints::PointerConstraintRef;
 
 bitflags! {
     #[derive(Default)]
@@ -52,6 +53,122 @@ impl<'a> From<&'a weston_pointer_motion_event> for PointerMotionEvent {
     }
 }
 
+impl PointerMotionEvent {
+    /// Synthetic relative motion, e.g. from a mouse. Unaccelerated deltas are the same as the given ones.
+    pub fn relative(time: libc::timespec, dx: f64, dy: f64) -> PointerMotionEvent {
+        PointerMotionEvent {
+            mask: PointerMotionMask::REL | PointerMotionMask::RELUNACCEL,
+            time, x: 0.0, y: 0.0, dx, dy, dx_unaccel: dx, dy_unaccel: dy,
+        }
+    }
+
+    /// Synthetic absolute motion to global coordinates, e.g. from a tablet.
+    pub fn absolute(time: libc::timespec, x: f64, y: f64) -> PointerMotionEvent {
+        PointerMotionEvent {
+            mask: PointerMotionMask::ABS,
+            time, x, y, dx: 0.0, dy: 0.0, dx_unaccel: 0.0, dy_unaccel: 0.0,
+        }
+    }
+
+    #[inline]
+    pub fn mask(&self) -> PointerMotionMask {
+        self.mask
+    }
+
+    #[inline]
+    pub fn time(&self) -> &libc::timespec {
+        &self.time
+    }
+
+    /// Absolute position (global coordinates), only meaningful with `PointerMotionMask::ABS`
+    #[inline]
+    pub fn x(&self) -> f64 {
+        self.x
+    }
+
+    #[inline]
+    pub fn y(&self) -> f64 {
+        self.y
+    }
+
+    /// Accelerated relative motion, only meaningful with `PointerMotionMask::REL`
+    #[inline]
+    pub fn dx(&self) -> f64 {
+        self.dx
+    }
+
+    #[inline]
+    pub fn dy(&self) -> f64 {
+        self.dy
+    }
+
+    /// Unaccelerated relative motion, as reported by the input device.
+    ///
+    /// This is what `zwp_relative_pointer_v1` clients (games etc.) receive as raw motion.
+    /// libweston advertises `zwp_relative_pointer_manager_v1` itself and sends the relative
+    /// events from `PointerRef::send_motion`, so grabs that want clients to keep receiving
+    /// raw motion must call `send_motion` with the original event.
+    #[inline]
+    pub fn dx_unaccel(&self) -> f64 {
+        self.dx_unaccel
+    }
+
+    #[inline]
+    pub fn dy_unaccel(&self) -> f64 {
+        self.dy_unaccel
+    }
+
+    /// Whether the event carries unaccelerated relative motion at all
+    /// (absolute devices like tablets and touchscreens don't).
+    #[inline]
+    pub fn has_unaccel(&self) -> bool {
+        self.mask.contains(PointerMotionMask::RELUNACCEL)
+    }
+
+    /// Replaces the accelerated relative motion, keeping the raw deltas intact.
+    pub fn with_delta(mut self, dx: f64, dy: f64) -> PointerMotionEvent {
+        self.mask |= PointerMotionMask::REL;
+        self.dx = dx;
+        self.dy = dy;
+        self
+    }
+
+    /// Turns the event into absolute motion to the given global coordinates.
+    pub fn with_position(mut self, x: f64, y: f64) -> PointerMotionEvent {
+        self.mask |= PointerMotionMask::ABS;
+        self.x = x;
+        self.y = y;
+        self
+    }
+
+    /// Applies a transformation to the accelerated relative motion (for custom acceleration curves etc.)
+    pub fn map_delta<F: FnOnce(f64, f64) -> (f64, f64)>(self, f: F) -> PointerMotionEvent {
+        let (dx, dy) = f(self.dx, self.dy);
+        self.with_delta(dx, dy)
+    }
+
+    /// Multiplies the accelerated relative motion (and the absolute position relative to the
+    /// given origin, for absolute events) by the given factors.
+    pub fn scaled(mut self, origin_x: f64, origin_y: f64, factor_x: f64, factor_y: f64) -> PointerMotionEvent {
+        if self.mask.contains(PointerMotionMask::ABS) {
+            self.x = origin_x + (self.x - origin_x) * factor_x;
+            self.y = origin_y + (self.y - origin_y) * factor_y;
+        }
+        if self.mask.contains(PointerMotionMask::REL) {
+            self.dx *= factor_x;
+            self.dy *= factor_y;
+        }
+        self
+    }
+
+    /// Resolves the event against the pointer's current position, producing absolute motion
+    /// to where the pointer would end up. Relative deltas are kept for relative pointer clients.
+    pub fn into_absolute(self, pointer: &PointerRef) -> PointerMotionEvent {
+        let (x, y) = pointer.motion_to_abs(self);
+        self.with_position(wl_fixed_to_double(x), wl_fixed_to_double(y))
+    }
+}
+
 impl Into<weston_pointer_motion_event> for PointerMotionEvent {
     fn into(self) -> weston_pointer_motion_event {
         let PointerMotionEvent { mask, time, x, y, dx, dy, dx_unaccel, dy_unaccel } = self;
@@ -67,8 +184,51 @@ pub struct PointerAxisEvent {
     value: f64,
     has_discrete: bool,
     discrete: i32,
+}
+
+impl PointerAxisEvent {
+    /// Synthetic continuous scroll (e.g. from a touchpad)
+    pub fn new(axis: Axis, value: f64) -> PointerAxisEvent {
+        PointerAxisEvent { axis, value, has_discrete: false, discrete: 0 }
+    }
+
+    /// Synthetic wheel scroll by the given number of clicks, each worth `value_per_step`
+    pub fn discrete(axis: Axis, steps: i32, value_per_step: f64) -> PointerAxisEvent {
+        PointerAxisEvent { axis, value: f64::from(steps) * value_per_step, has_discrete: true, discrete: steps }
+    }
+
+    #[inline]
+    pub fn axis(&self) -> Axis {
+        self.axis
+    }
+
+    #[inline]
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    /// Wheel clicks, if the event came from a device with discrete steps
+    #[inline]
+    pub fn discrete_steps(&self) -> Option<i32> {
+        if self.has_discrete {
+            Some(self.discrete)
+        } else {
+            None
+        }
+    }
+
+    pub fn with_axis(mut self, axis: Axis) -> PointerAxisEvent {
+        self.axis = axis;
+        self
+    }
 
+    /// Multiplies the scroll distance. Discrete steps are left as is, since they count clicks.
+    pub fn scaled(mut self, factor: f64) -> PointerAxisEvent {
+        self.value *= factor;
+        self
+    }
 }
+
 impl<'a> From<&'a weston_pointer_axis_event> for PointerAxisEvent {
     fn from(source: &weston_pointer_axis_event) -> Self {
         let &weston_pointer_axis_event { axis, value, has_discrete, discrete } = source;
@@ -253,4 +413,19 @@ impl PointerRef {
     pub fn is_default_grab(&self) -> bool {
         unsafe { (*self.as_ptr()).grab == &mut (*self.as_ptr()).default_grab }
     }
+
+    /// Shows a compositor-drawn cursor from the theme loaded by `cursor::init` (e.g. "move"
+    /// or "se-resize" during grabs) over whatever the client has set, or goes back to the
+    /// normal behavior with `None`. Returns false if the cursor could not be found.
+    pub fn set_cursor(&mut self, name: Option<&str>) -> bool {
+        ::cursor::set_override(self, name)
+    }
+
+    /// The lock/confinement currently enforced on this pointer, if any
+    pub fn active_constraint(&self) -> Option<&mut PointerConstraintRef> {
+        if self.is_default_grab() {
+            return None
+        }
+        self.focus()?.surface().pointer_constraints().find(|c| c.is_enabled())
+    }
 }
@@ -0,0 +1,299 @@
+use libc;
+use std::{ffi, ptr};
+use std::collections::HashMap;
+use libweston_sys::{
+    weston_pointer_constraint, weston_pointer, weston_compositor, weston_surface,
+};
+use wayland_sys::common::{wl_list, wl_fixed_t};
+use wayland_sys::server::{
+    wl_resource, wl_event_source, wl_display_get_event_loop, wl_event_loop_add_idle,
+    wl_event_source_remove,
+};
+use foreign_types::ForeignTypeRef;
+use ::compositor::CompositorRef;
+use ::listener::{WlListener, ListenerList, SeatListeners};
+use ::pointer::PointerRef;
+use ::keyboard::KeyboardRef;
+use ::seat::{SeatRef, SurfaceActivationRef};
+use ::surface::SurfaceRef;
+use ::view::ViewRef;
+
+extern "C" {
+    fn wl_resource_get_class(resource: *mut wl_resource) -> *const libc::c_char;
+}
+
+const_cstr!{
+    LOCKED_POINTER_CLASS = "zwp_locked_pointer_v1";
+}
+
+/// What the client asked for: keeping the pointer in place or inside a region.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PointerConstraintKind {
+    Lock,
+    Confine,
+}
+
+/// Values of `zwp_pointer_constraints_v1.lifetime`
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Primitive)]
+pub enum PointerConstraintLifetime {
+    /// The constraint is destroyed once deactivated
+    Oneshot = 1,
+    /// The constraint can be reactivated (e.g. when the surface is clicked again)
+    Persistent = 2,
+}
+
+pub struct PointerConstraintIterator<'a> {
+    surface: &'a SurfaceRef,
+    link: *mut wl_list,
+}
+
+impl<'a> Iterator for PointerConstraintIterator<'a> {
+    type Item = &'a mut PointerConstraintRef;
+
+    fn next(&mut self) -> Option<&'a mut PointerConstraintRef> {
+        self.link = unsafe { (*self.link).next };
+        if self.link == unsafe { &mut (*self.surface.as_ptr()).pointer_constraints as *mut _ } {
+            None
+        } else {
+            Some(unsafe { PointerConstraintRef::from_ptr_mut(wl_container_of!(self.link, weston_pointer_constraint, link)) })
+        }
+    }
+}
+
+impl<'a> PointerConstraintIterator<'a> {
+    pub(crate) fn new(surface: &'a SurfaceRef) -> PointerConstraintIterator<'a> {
+        PointerConstraintIterator {
+            surface,
+            link: unsafe { &mut (*surface.as_ptr()).pointer_constraints },
+        }
+    }
+}
+
+// Constraints are owned by their protocol objects
+unsafe fn noop_destroy(_: *mut weston_pointer_constraint) {}
+
+foreign_type! {
+    type CType = weston_pointer_constraint;
+    fn drop = noop_destroy;
+    pub struct PointerConstraint;
+    pub struct PointerConstraintRef;
+}
+
+impl PointerConstraintRef {
+    obj_accessors!(SurfaceRef | surface surface_mut = |&this| { (*this.as_ptr()).surface });
+    obj_accessors!(opt ViewRef | view view_mut = |&this| { (*this.as_ptr()).view });
+    obj_accessors!(PointerRef | pointer pointer_mut = |&this| { (*this.as_ptr()).pointer });
+    prop_accessors!(wl_fixed_t | hint_x, hint_y);
+
+    pub fn kind(&self) -> PointerConstraintKind {
+        let class = unsafe { ffi::CStr::from_ptr(wl_resource_get_class((*self.as_ptr()).resource)) };
+        if class == LOCKED_POINTER_CLASS.as_cstr() {
+            PointerConstraintKind::Lock
+        } else {
+            PointerConstraintKind::Confine
+        }
+    }
+
+    pub fn lifetime(&self) -> PointerConstraintLifetime {
+        use num_traits::FromPrimitive;
+        PointerConstraintLifetime::from_u32(unsafe { (*self.as_ptr()).lifetime })
+            .unwrap_or(PointerConstraintLifetime::Oneshot)
+    }
+
+    /// Whether libweston currently enforces this constraint (its grab is the pointer's grab)
+    pub fn is_enabled(&self) -> bool {
+        unsafe { (*(*self.as_ptr()).pointer).grab == &mut (*self.as_ptr()).grab as *mut _ }
+    }
+
+    /// Deactivates the constraint the same way libweston does when its grab is cancelled:
+    /// the client gets `unlocked`/`unconfined`, and oneshot constraints become defunct.
+    pub fn break_constraint(&mut self) {
+        if !self.is_enabled() {
+            return
+        }
+        unsafe {
+            let grab = &mut (*self.as_ptr()).grab as *mut _;
+            (*(*grab).interface).cancel.expect("cancel ptr")(grab);
+        }
+    }
+}
+
+/// Shell policy for pointer locking/confinement (`zwp_pointer_constraints_v1`).
+///
+/// libweston implements the protocol itself and enables a constraint when its surface has
+/// keyboard focus and was click-activated (see `ActivateFlag::CLICKED`). These callbacks
+/// are run right after that happens.
+pub trait PointerConstraintsApi {
+    /// Decide whether a freshly enabled constraint may stay. Returning false breaks it.
+    fn constraint_requested(&mut self, _constraint: &mut PointerConstraintRef) -> bool { true }
+
+    /// The constraint is in effect, e.g. show an "Esc to release" indicator now.
+    fn constraint_activated(&mut self, _constraint: &mut PointerConstraintRef) {}
+
+    /// The pointer is free again (the constraint might already be destroyed).
+    fn constraint_deactivated(&mut self, _pointer: &mut PointerRef) {}
+}
+
+struct ConstraintsState {
+    api: Box<dyn PointerConstraintsApi>,
+    compositor: *mut weston_compositor,
+    active: HashMap<*mut weston_pointer, *mut weston_pointer_constraint>,
+    /// Surfaces whose constraint was broken, with the activation serial at that time.
+    /// libweston re-enables persistent constraints on commit, until the surface is clicked again.
+    released: HashMap<*mut weston_surface, u32>,
+    idle: *mut wl_event_source,
+    listeners: ListenerList,
+    seats: Vec<SeatListeners>,
+    // Listeners can't be freed while they run, these go on the next check
+    dead: Vec<ListenerList>,
+}
+
+impl ConstraintsState {
+    fn watch_seat(&mut self, seat: &mut SeatRef) {
+        let state = self as *mut ConstraintsState;
+        let mut listeners = SeatListeners::new(seat.as_ptr());
+        if let Some(pointer) = seat.pointer_mut() {
+            listeners.devices.add(WlListener::new(Box::new(move |_: &mut PointerRef| unsafe { schedule_check(state) })),
+                                  pointer.focus_signal());
+            listeners.devices.add(WlListener::new(Box::new(move |_: &mut PointerRef| unsafe { schedule_check(state) })),
+                                  pointer.motion_signal());
+        }
+        if let Some(keyboard) = seat.keyboard_mut() {
+            listeners.devices.add(WlListener::new(Box::new(move |_: &mut KeyboardRef| unsafe { schedule_check(state) })),
+                                  keyboard.focus_signal());
+        }
+        listeners.own.add(WlListener::new(Box::new(move |seat: &mut SeatRef| unsafe { (*state).seat_destroyed(seat) })),
+                          seat.destroy_signal());
+        self.seats.push(listeners);
+    }
+
+    fn seat_destroyed(&mut self, seat: &mut SeatRef) {
+        // The pointer is already freed, only its address is left
+        self.active.remove(&unsafe { (*seat.as_ptr()).pointer_state });
+        let index = self.seats.iter().position(|s| s.seat == seat.as_ptr());
+        if let Some(i) = index {
+            let listeners = self.seats.remove(i).destroyed();
+            self.dead.push(listeners);
+        }
+    }
+
+    /// Breaks the constraint until its surface is clicked again
+    fn release(&mut self, constraint: &mut PointerConstraintRef) {
+        let serial = unsafe { (*self.compositor).activate_serial };
+        self.released.insert(constraint.surface().as_ptr(), serial);
+        constraint.break_constraint();
+    }
+}
+
+/// Runs a `PointerConstraintsApi` for all seats of the compositor.
+///
+/// Constraint changes are picked up after focus changes, activations and pointer motion.
+/// A client may also lock the pointer while it already has focus; call `check` from
+/// `DesktopApi::committed` to catch that on its next commit.
+///
+/// The `zwp_pointer_constraints_v1` global belongs to libweston and stays when this is dropped,
+/// constraints are then left to libweston's own policy.
+pub struct PointerConstraints {
+    state: Box<ConstraintsState>,
+}
+
+impl PointerConstraints {
+    pub fn new(compositor: &mut CompositorRef, api: Box<dyn PointerConstraintsApi>) -> PointerConstraints {
+        let mut state = Box::new(ConstraintsState {
+            api,
+            compositor: compositor.as_ptr(),
+            active: HashMap::new(),
+            released: HashMap::new(),
+            idle: ptr::null_mut(),
+            listeners: ListenerList::new(),
+            seats: Vec::new(),
+            dead: Vec::new(),
+        });
+        let state_ptr = &mut *state as *mut ConstraintsState;
+        state.listeners.add(WlListener::new(Box::new(move |_: &mut SurfaceActivationRef| unsafe { schedule_check(state_ptr) })),
+                            compositor.activate_signal());
+        for seat in compositor.iterate_seats() {
+            state.watch_seat(seat);
+        }
+        state.listeners.add(WlListener::new(Box::new(move |seat: &mut SeatRef| unsafe { (*state_ptr).watch_seat(seat) })),
+                            compositor.seat_created_signal());
+        PointerConstraints { state }
+    }
+
+    pub fn api(&mut self) -> &mut dyn PointerConstraintsApi {
+        &mut *self.state.api
+    }
+
+    /// Schedules a policy check for when the current dispatch is done.
+    pub fn check(&mut self) {
+        unsafe { schedule_check(&mut *self.state); }
+    }
+
+    /// Forcibly releases the pointer, e.g. from an Esc key binding.
+    ///
+    /// Persistent constraints are kept broken until the surface is clicked again.
+    pub fn release(&mut self, pointer: &mut PointerRef) {
+        if let Some(constraint) = pointer.active_constraint() {
+            self.state.release(constraint);
+        }
+        run_check(&mut self.state);
+    }
+}
+
+impl Drop for PointerConstraints {
+    fn drop(&mut self) {
+        if !self.state.idle.is_null() {
+            unsafe { wl_event_source_remove(self.state.idle); }
+        }
+    }
+}
+
+unsafe fn schedule_check(state: *mut ConstraintsState) {
+    if !(*state).idle.is_null() {
+        return
+    }
+    let event_loop = wl_display_get_event_loop((*(*state).compositor).wl_display);
+    (*state).idle = wl_event_loop_add_idle(event_loop, run_idle_check, state as *mut libc::c_void);
+}
+
+unsafe extern "C" fn run_idle_check(data: *mut libc::c_void) {
+    let state = &mut *(data as *mut ConstraintsState);
+    state.idle = ptr::null_mut();
+    run_check(state);
+}
+
+fn run_check(state: &mut ConstraintsState) {
+    state.dead.clear();
+    // A click anywhere bumps the serial, the released surfaces may be constrained again
+    let serial = unsafe { (*state.compositor).activate_serial };
+    state.released.retain(|_, released| *released == serial);
+    let compositor = unsafe { CompositorRef::from_ptr(state.compositor) };
+    for seat in compositor.iterate_seats() {
+        let pointer = match seat.pointer_mut() {
+            Some(pointer) => pointer,
+            None => continue,
+        };
+        let current = pointer.active_constraint().map(|c| c.as_ptr());
+        let previous = state.active.get(&pointer.as_ptr()).cloned();
+        if current == previous {
+            continue
+        }
+        if previous.is_some() {
+            state.active.remove(&pointer.as_ptr());
+            state.api.constraint_deactivated(pointer);
+        }
+        if let Some(constraint) = current {
+            let constraint = unsafe { PointerConstraintRef::from_ptr_mut(constraint) };
+            if state.released.contains_key(&constraint.surface().as_ptr()) {
+                // Re-enabled by libweston without a new click
+                constraint.break_constraint();
+            } else if state.api.constraint_requested(constraint) {
+                state.active.insert(pointer.as_ptr(), constraint.as_ptr());
+                state.api.constraint_activated(constraint);
+            } else {
+                state.release(constraint);
+            }
+        }
+    }
+}
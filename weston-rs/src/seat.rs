@@ -1,6 +1,7 @@
 use libc;
+use std::ptr;
 use libweston_sys::{
-    weston_seat,
+    weston_seat, weston_surface_activation_data,
     weston_seat_release,
     weston_seat_get_pointer, weston_seat_get_keyboard, weston_seat_get_touch,
     weston_seat_set_keyboard_focus,
@@ -11,6 +12,7 @@ use ::pointer::PointerRef;
 use ::keyboard::{KeyboardModifier, KeyboardRef};
 use ::touch::TouchRef;
 use ::surface::SurfaceRef;
+use ::compositor::CompositorRef;
 
 foreign_type! {
     type CType = weston_seat;
@@ -20,6 +22,7 @@ foreign_type! {
 }
 
 impl SeatRef {
+    obj_accessors!(CompositorRef | compositor compositor_mut = |&this| { (*this.as_ptr()).compositor });
     obj_accessors!(opt PointerRef | pointer pointer_mut = |&this| { weston_seat_get_pointer(this.as_ptr()) });
     obj_accessors!(opt KeyboardRef | keyboard keyboard_mut = |&this| { weston_seat_get_keyboard(this.as_ptr()) });
     obj_accessors!(opt TouchRef | touch touch_mut = |&this| { weston_seat_get_touch(this.as_ptr()) });
@@ -34,4 +37,23 @@ impl SeatRef {
     pub fn set_keyboard_focus(&mut self, surface: &SurfaceRef) {
         unsafe { weston_seat_set_keyboard_focus(self.as_ptr(), surface.as_ptr()); }
     }
+
+    pub fn clear_keyboard_focus(&mut self) {
+        unsafe { weston_seat_set_keyboard_focus(self.as_ptr(), ptr::null_mut()); }
+    }
+}
+
+unsafe fn noop_destroy(_: *mut weston_surface_activation_data) {}
+
+foreign_type! {
+    type CType = weston_surface_activation_data;
+    fn drop = noop_destroy;
+    /// Data of the compositor's `activate_signal`
+    pub struct SurfaceActivation;
+    pub struct SurfaceActivationRef;
+}
+
+impl SurfaceActivationRef {
+    obj_accessors!(opt SurfaceRef | surface surface_mut = |&this| { (*this.as_ptr()).surface });
+    obj_accessors!(SeatRef | seat seat_mut = |&this| { (*this.as_ptr()).seat });
 }
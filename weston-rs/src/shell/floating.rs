@@ -0,0 +1,767 @@
+//! A stacking window manager: windows go where the client or the user puts them, clicking one
+//! raises and focuses it.
+//!
+//! `FloatingShell` is a `DesktopApi`, the per-surface user data is anything that implements
+//! `ShellSurface` (`FloatingSurface` itself when nothing else needs to be stored).
+
+use libc;
+use std::{any, cmp, marker, ptr};
+use std::cell::Cell;
+use std::mem::ManuallyDrop;
+use std::rc::Rc;
+use libweston_sys::{
+    weston_layer, weston_pointer, weston_pointer_grab, weston_binding, weston_desktop_surface,
+    weston_compositor_add_button_binding, weston_binding_destroy, weston_layer_unset_position,
+};
+use wayland_server::protocol::wl_shell_surface::Resize;
+use foreign_types::ForeignTypeRef;
+use ::{Geometry, wl_fixed_to_double, wl_fixed_to_int};
+use ::ev;
+use ::compositor::CompositorRef;
+use ::desktop::{DesktopApi, DesktopSurfaceRef};
+use ::keyboard::KeyboardModifier;
+use ::layer::{Layer, LayerRef, LayerPosition, POSITION_NORMAL, POSITION_HIDDEN};
+use ::listener::{WlListener, Listener};
+use ::output::OutputRef;
+use ::pointer::{PointerRef, PointerGrab, PointerMotionEvent, ButtonState};
+use ::seat::SeatRef;
+use ::surface::SurfaceRef;
+use ::view::{View, ViewRef, ActivateFlag};
+use super::focus::{FocusManager, FocusPolicy};
+use super::menu::WindowMenu;
+use super::minimize::{MinimizeManager, MinimizedHook, has_layer, layer_views};
+use super::snap::{self, Snapper, SnapConfig};
+
+#[derive(Builder)]
+pub struct FloatingConfig {
+    /// Where windows go (always on top ones go right above)
+    #[builder(default = "POSITION_NORMAL")]
+    layer_position: LayerPosition,
+    /// Left and right clicks raise and focus the window under the pointer
+    #[builder(default = "true")]
+    click_to_activate: bool,
+    /// Whether the pointer moves the focus around without clicks
+    #[builder(default = "FocusPolicy::Click")]
+    focus_policy: FocusPolicy,
+    /// New windows get focused right away
+    #[builder(default = "true")]
+    activate_new: bool,
+    /// Holding these and dragging with the left button moves the window under the pointer
+    #[builder(default)]
+    move_modifier: Option<KeyboardModifier>,
+    /// Moves and resizes stick to edges and can tile to halves of outputs
+    #[builder(default)]
+    snap: Option<SnapConfig>,
+}
+
+/// The window manager's part of the per-surface data
+pub struct FloatingSurface {
+    view: View,
+    /// Commit handling must check if a resize with top/left edges is happening
+    /// and move the surface accordingly
+    resize_edges: Resize,
+    last_width: f32,
+    last_height: f32,
+    /// Size from before the window got tiled by dragging it to an edge
+    restore_size: Option<(i32, i32)>,
+    /// Window geometry (in global coordinates) from before it got maximized or fullscreen
+    restore_geometry: Option<Geometry>,
+    /// The layer to go back to when minimized (see `minimize`)
+    pub(super) minimized_from: Option<*mut weston_layer>,
+    /// The layer to go back to when not always on top anymore
+    above_from: Option<*mut weston_layer>,
+    /// The shell's layers, for `set_always_on_top` (null once the shell is gone)
+    shell: Rc<Cell<*mut FloatingState>>,
+}
+
+impl FloatingSurface {
+    pub fn view(&self) -> &ViewRef {
+        &self.view
+    }
+
+    pub fn view_mut(&mut self) -> &mut ViewRef {
+        &mut self.view
+    }
+
+    pub fn is_minimized(&self) -> bool {
+        self.minimized_from.is_some()
+    }
+
+    pub fn is_always_on_top(&self) -> bool {
+        self.above_from.is_some()
+    }
+}
+
+/// Per-surface user data for `FloatingShell`
+pub trait ShellSurface: 'static {
+    /// Makes the user data for a new surface
+    fn from_floating(floating: FloatingSurface) -> Self;
+
+    fn floating(&mut self) -> &mut FloatingSurface;
+}
+
+impl ShellSurface for FloatingSurface {
+    fn from_floating(floating: FloatingSurface) -> FloatingSurface {
+        floating
+    }
+
+    fn floating(&mut self) -> &mut FloatingSurface {
+        self
+    }
+}
+
+struct FloatingState {
+    config: FloatingConfig,
+    layer: Layer,
+    /// For always on top windows
+    above: Layer,
+    /// The `move_modifier` one, it reads the snap config from here
+    move_binding: *mut weston_binding,
+    /// The `click_to_activate` ones
+    click_bindings: Vec<*mut weston_binding>,
+    /// Handed to every surface, nulled when the state goes away
+    shared: Rc<Cell<*mut FloatingState>>,
+}
+
+impl Drop for FloatingState {
+    fn drop(&mut self) {
+        if !self.move_binding.is_null() {
+            unsafe { weston_binding_destroy(self.move_binding); }
+        }
+        for binding in self.click_bindings.drain(..) {
+            unsafe { weston_binding_destroy(binding); }
+        }
+        self.shared.set(ptr::null_mut());
+        // Windows that are still around lose their layer instead of pointing into freed memory
+        for layer in &[self.layer.as_ptr(), self.above.as_ptr()] {
+            for view in layer_views(*layer) {
+                unsafe { ViewRef::from_ptr_mut(view) }.layer_entry_remove();
+            }
+        }
+        // Dropping a layer doesn't take it out of the compositor's list
+        unsafe {
+            weston_layer_unset_position(self.layer.as_ptr());
+            weston_layer_unset_position(self.above.as_ptr());
+        }
+    }
+}
+
+fn floating_of<SC: ShellSurface>(dsurf: &DesktopSurfaceRef<SC>) -> &mut FloatingSurface {
+    dsurf.borrow_user_data().expect("user_data").floating()
+}
+
+/// The desktop surface a view belongs to, if it's one of ours
+pub(super) fn desktop_surface_of<SC: ShellSurface>(view: &ViewRef) -> Option<&'static mut DesktopSurfaceRef<SC>> {
+    let main_surf = unsafe { SurfaceRef::from_ptr(view.surface().main_surface().as_ptr()) };
+    DesktopSurfaceRef::<SC>::from_surface(main_surf)
+        .filter(|dsurf| dsurf.borrow_user_data().is_some())
+        .map(|dsurf| unsafe { DesktopSurfaceRef::from_ptr_mut(dsurf.as_ptr()) })
+}
+
+type SurfaceListener = ManuallyDrop<Box<WlListener<SurfaceRef>>>;
+
+/// The window a move or resize grab works on, the grab gets cancelled if it's destroyed
+pub(super) struct GrabTarget<SC> {
+    /// Null once the window is destroyed
+    dsurf: Rc<Cell<*mut weston_desktop_surface>>,
+    destroy_listener: Option<SurfaceListener>,
+    phantom: marker::PhantomData<SC>,
+}
+
+impl<SC: ShellSurface> GrabTarget<SC> {
+    /// `destroyed` runs once the window is gone, it should cancel the grab
+    pub(super) fn new<F: FnMut() + 'static>(dsurf: &DesktopSurfaceRef<SC>, mut destroyed: F) -> GrabTarget<SC> {
+        let target = Rc::new(Cell::new(dsurf.as_ptr()));
+        let mut destroy_listener = {
+            let target = target.clone();
+            WlListener::new(Box::new(move |_: &mut SurfaceRef| {
+                target.set(ptr::null_mut());
+                destroyed();
+            }))
+        };
+        destroy_listener.signal_add(dsurf.surface().destroy_signal());
+        GrabTarget {
+            dsurf: target,
+            destroy_listener: Some(destroy_listener),
+            phantom: marker::PhantomData,
+        }
+    }
+
+    pub(super) fn get(&self) -> Option<&'static mut DesktopSurfaceRef<SC>> {
+        let dsurf = self.dsurf.get();
+        if dsurf.is_null() {
+            None
+        } else {
+            Some(unsafe { DesktopSurfaceRef::from_ptr_mut(dsurf) })
+        }
+    }
+
+    /// Stops listening to the window, when the grab ends
+    pub(super) fn release(&mut self) {
+        if let Some(mut listener) = self.destroy_listener.take() {
+            listener.remove();
+            // Unless it's the one running, then it stays around like the grab itself
+            if !self.dsurf.get().is_null() {
+                drop(ManuallyDrop::into_inner(listener));
+            }
+        }
+    }
+}
+
+/// Starts the grab made from the target, cancelling it if the window is destroyed meanwhile
+fn start_grab<SC, T, F>(dsurf: &DesktopSurfaceRef<SC>, pointer: &mut PointerRef, make_grab: F)
+    where SC: ShellSurface, T: PointerGrab, F: FnOnce(GrabTarget<SC>) -> T
+{
+    // Known once started
+    let grab = Rc::new(Cell::new(ptr::null_mut::<weston_pointer_grab>()));
+    let pointer_ptr = pointer.as_ptr();
+    let target = {
+        let grab = grab.clone();
+        GrabTarget::new(dsurf, move || unsafe {
+            // Unless something else took over the pointer
+            let grab = grab.get();
+            if (*pointer_ptr).grab == grab {
+                if let Some(cancel) = (*(*grab).interface).cancel {
+                    cancel(grab);
+                }
+            }
+        })
+    };
+    pointer.start_grab(make_grab(target));
+    grab.set(unsafe { (*pointer_ptr).grab });
+}
+
+/// Mouse handler for moving windows
+struct MoveGrab<SC: 'static> {
+    target: GrabTarget<SC>,
+    dx: f64,
+    dy: f64,
+    snap: Option<Snapper>,
+}
+
+impl<SC: ShellSurface> PointerGrab for MoveGrab<SC> {
+    fn motion(&mut self, pointer: &mut PointerRef, _time: &libc::timespec, event: PointerMotionEvent) {
+        pointer.moove(event);
+        let dsurf = match self.target.get() {
+            Some(dsurf) => dsurf,
+            None => return,
+        };
+        let mut x = (wl_fixed_to_double(pointer.x()) + self.dx) as f32;
+        let mut y = (wl_fixed_to_double(pointer.y()) + self.dy) as f32;
+        if let Some(ref mut snap) = self.snap {
+            let (snapped_x, snapped_y) = snap.moving(dsurf, pointer, x, y);
+            x = snapped_x;
+            y = snapped_y;
+        }
+        floating_of(dsurf).view.set_position(x, y);
+        dsurf.surface().compositor_mut().schedule_repaint();
+    }
+
+    fn button(&mut self, pointer: &mut PointerRef, _time: &libc::timespec, _button: u32, state: ButtonState) {
+        if pointer.button_count() == 0 && state == ButtonState::Released {
+            if let Some(zone) = self.snap.as_mut().and_then(|snap| snap.finish()) {
+                if let Some(dsurf) = self.target.get() {
+                    let geometry = dsurf.get_geometry();
+                    // Tiling an already tiled window keeps the original size
+                    floating_of(dsurf).restore_size.get_or_insert((geometry.width, geometry.height));
+                    place(dsurf, zone);
+                }
+            }
+            self.target.release();
+            pointer.end_grab();
+        }
+    }
+
+    fn cancel(&mut self, pointer: &mut PointerRef) {
+        if let Some(ref mut snap) = self.snap {
+            snap.finish();
+        }
+        self.target.release();
+        pointer.end_grab();
+    }
+}
+
+/// Mouse handler for resizing windows
+struct ResizeGrab<SC: 'static> {
+    target: GrabTarget<SC>,
+    edges: Resize,
+    width: i32,
+    height: i32,
+    snap: Option<Snapper>,
+}
+
+impl<SC: ShellSurface> PointerGrab for ResizeGrab<SC> {
+    fn motion(&mut self, pointer: &mut PointerRef, _time: &libc::timespec, event: PointerMotionEvent) {
+        pointer.moove(event);
+        let dsurf = match self.target.get() {
+            Some(dsurf) => dsurf,
+            None => return,
+        };
+        let floating = floating_of(dsurf);
+        let (from_x, from_y) = floating.view.from_global_fixed(pointer.grab_x(), pointer.grab_y());
+        let (to_x, to_y) = floating.view.from_global_fixed(pointer.x(), pointer.y());
+        let mut width = self.width;
+        if self.edges.contains(Resize::Left) {
+            width += wl_fixed_to_int(from_x - to_x);
+        } else if self.edges.contains(Resize::Right) {
+            width += wl_fixed_to_int(to_x - from_x);
+        }
+        let mut height = self.height;
+        if self.edges.contains(Resize::Top) {
+            height += wl_fixed_to_int(from_y - to_y);
+        } else if self.edges.contains(Resize::Bottom) {
+            height += wl_fixed_to_int(to_y - from_y);
+        }
+        let (width, height) = match self.snap {
+            Some(ref mut snap) => snap.resizing(dsurf, self.edges, width, height),
+            None => clamp_size(dsurf, width, height),
+        };
+        dsurf.set_size(width, height);
+    }
+
+    fn button(&mut self, pointer: &mut PointerRef, _time: &libc::timespec, _button: u32, state: ButtonState) {
+        if pointer.button_count() == 0 && state == ButtonState::Released {
+            self.cancel(pointer);
+        }
+    }
+
+    fn cancel(&mut self, pointer: &mut PointerRef) {
+        if let Some(dsurf) = self.target.get() {
+            dsurf.set_resizing(false);
+            floating_of(dsurf).resize_edges = Resize::None;
+        }
+        self.target.release();
+        pointer.end_grab();
+    }
+}
+
+/// Keeps a size within the surface's min/max size (a max of 0 means no limit)
+pub fn clamp_size<SC>(dsurf: &DesktopSurfaceRef<SC>, width: i32, height: i32) -> (i32, i32) {
+    let min_size = dsurf.get_min_size();
+    let max_size = dsurf.get_max_size();
+    let clamp = |value: i32, min: i32, max: i32| {
+        let value = cmp::max(value, cmp::max(1, min));
+        if max > 0 { cmp::min(value, max) } else { value }
+    };
+    (clamp(width, min_size.width, max_size.width), clamp(height, min_size.height, max_size.height))
+}
+
+/// Sizes the window to the cell (as far as its min/max size allows) and moves it there
+pub fn place<SC: ShellSurface>(dsurf: &mut DesktopSurfaceRef<SC>, cell: Geometry) {
+    let (width, height) = clamp_size(dsurf, cell.width, cell.height);
+    // Windows that can't grow that much are centered in their cell
+    let x = cell.x + cmp::max(0, (cell.width - width) / 2);
+    let y = cell.y + cmp::max(0, (cell.height - height) / 2);
+    // The window geometry doesn't include client-side shadows, the view position does
+    let geometry = dsurf.get_geometry();
+    dsurf.set_size(width, height);
+    let view = floating_of(dsurf).view_mut();
+    view.set_position((x - geometry.x) as f32, (y - geometry.y) as f32);
+    view.geometry_dirty();
+}
+
+/// The work area (or the whole area) of the output the window is on, or of the first one
+fn output_area<SC: ShellSurface>(dsurf: &DesktopSurfaceRef<SC>, work_area: bool) -> Option<Geometry> {
+    let area = |output: &OutputRef| if work_area { output.work_area() } else { output.geometry() };
+    floating_of(dsurf).view.output().map(&area)
+        .or_else(|| dsurf.surface().compositor().iterate_outputs().next().map(|output| area(output)))
+}
+
+/// Fits the window to the area, remembering where it was the first time, or puts it back there
+fn fill_area<SC: ShellSurface>(dsurf: &mut DesktopSurfaceRef<SC>, area: Option<Geometry>) {
+    match area {
+        Some(area) => {
+            {
+                let geometry = dsurf.get_geometry();
+                let floating = floating_of(dsurf);
+                if floating.restore_geometry.is_none() {
+                    let (x, y) = floating.view.get_position();
+                    floating.restore_geometry = Some(Geometry { x: x as i32 + geometry.x, y: y as i32 + geometry.y, ..geometry });
+                }
+            }
+            place(dsurf, area);
+        },
+        None => {
+            let restore = floating_of(dsurf).restore_geometry.take();
+            if let Some(geometry) = restore {
+                place(dsurf, geometry);
+            }
+        },
+    }
+}
+
+/// Whether a `move`/`resize` request comes from a click on the surface that's still held down
+fn pointer_grab_valid<SC>(dsurf: &DesktopSurfaceRef<SC>, pointer: &PointerRef, serial: u32) -> bool {
+    pointer.button_count() > 0 && serial == pointer.grab_serial() &&
+        pointer.focus().map(|focus| focus.surface().main_surface().as_ptr() == dsurf.surface().as_ptr()).unwrap_or(false)
+}
+
+/// Lets the pointer drag the window around until the buttons are released
+pub fn start_move<SC: ShellSurface>(dsurf: &mut DesktopSurfaceRef<SC>, pointer: &mut PointerRef) {
+    start_move_with(dsurf, pointer, None);
+}
+
+pub(super) fn start_move_with<SC: ShellSurface>(dsurf: &mut DesktopSurfaceRef<SC>, pointer: &mut PointerRef, snap: Option<Snapper>) {
+    let (view_x, view_y) = floating_of(dsurf).view.get_position();
+    let mut dx = f64::from(view_x) - wl_fixed_to_double(pointer.grab_x());
+    let mut dy = f64::from(view_y) - wl_fixed_to_double(pointer.grab_y());
+    if snap.is_some() {
+        // Dragging a tiled window out, keeping the pointer at the same relative spot
+        if let Some((width, height)) = floating_of(dsurf).restore_size.take() {
+            let geometry = dsurf.get_geometry();
+            if geometry.width > 0 && geometry.height > 0 {
+                dx *= f64::from(width) / f64::from(geometry.width);
+                dy *= f64::from(height) / f64::from(geometry.height);
+            }
+            dsurf.set_size(width, height);
+        }
+    }
+    start_grab(dsurf, pointer, |target| MoveGrab { target, dx, dy, snap });
+}
+
+/// Lets the pointer resize the window until the buttons are released.
+/// `edges` must be one edge or two adjacent ones.
+pub fn start_resize<SC: ShellSurface>(dsurf: &mut DesktopSurfaceRef<SC>, pointer: &mut PointerRef, edges: Resize) {
+    start_resize_with(dsurf, pointer, edges, None);
+}
+
+pub(super) fn start_resize_with<SC: ShellSurface>(dsurf: &mut DesktopSurfaceRef<SC>, pointer: &mut PointerRef, edges: Resize, snap: Option<Snapper>) {
+    if edges == Resize::None || edges.contains(Resize::Left | Resize::Right) || edges.contains(Resize::Top | Resize::Bottom) {
+        return
+    }
+    let geom = dsurf.get_geometry();
+    dsurf.set_resizing(true);
+    {
+        let floating = floating_of(dsurf);
+        floating.resize_edges = edges;
+        floating.restore_size = None;
+    }
+    start_grab(dsurf, pointer, |target| ResizeGrab { target, edges, width: geom.width, height: geom.height, snap });
+}
+
+/// Whether the view is in a layer that's being shown (not a hidden workspace)
+pub fn is_shown(view: &ViewRef) -> bool {
+    let layer = view.layer_link().layer;
+    !layer.is_null() && unsafe { (*layer).position } != POSITION_HIDDEN
+}
+
+/// Focuses the window of the view on the seat and raises it
+pub fn activate<SC: ShellSurface>(view: &mut ViewRef, seat: &SeatRef, flags: ActivateFlag) {
+    if desktop_surface_of::<SC>(view).is_some() {
+        view.activate(seat, flags);
+        raise::<SC>(view);
+    }
+}
+
+/// Keeps the window above the others (e.g. picture-in-picture video), or puts it back in the
+/// layer it came from. Always on top windows aren't on any workspace, they're always shown.
+///
+/// `menu::default_action` does this for `MenuAction::AlwaysOnTop`.
+pub fn set_always_on_top<SC: ShellSurface>(dsurf: &mut DesktopSurfaceRef<SC>, on_top: bool) {
+    {
+        let floating = floating_of(dsurf);
+        let shell = floating.shell.get();
+        if shell.is_null() || floating.is_always_on_top() == on_top {
+            return;
+        }
+        let target = if on_top {
+            let from = floating.minimized_from.unwrap_or(floating.view.layer_link().layer);
+            if from.is_null() {
+                return;
+            }
+            floating.above_from = Some(from);
+            unsafe { (*shell).above.as_ptr() }
+        } else {
+            let from = floating.above_from.take().expect("above_from");
+            let layer = unsafe { (*shell).layer.as_ptr() };
+            // Unless it was a workspace that's gone since
+            if has_layer(dsurf.surface().compositor().as_ptr(), from) { from } else { layer }
+        };
+        if floating.is_minimized() {
+            // Stays hidden, restoring goes to the new layer
+            floating.minimized_from = Some(target);
+            return;
+        }
+        let view = &mut floating.view;
+        view.damage_below();
+        view.layer_entry_remove();
+        unsafe { LayerRef::from_ptr_mut(target) }.view_list_entry_insert(view);
+        view.geometry_dirty();
+    }
+    dsurf.propagate_layer();
+    dsurf.surface_mut().damage();
+    dsurf.surface_mut().compositor_mut().schedule_repaint();
+}
+
+/// Puts the window of the view on top of the others in its layer, without focusing it
+pub fn raise<SC: ShellSurface>(view: &mut ViewRef) {
+    if let Some(dsurf) = desktop_surface_of::<SC>(view) {
+        // Re-insert into the layer to put on top visually
+        if view.layer_link().layer.is_null() {
+            // Except for newly created surfaces (?)
+            // e.g. w/o this, clicking a GTK menu action that spawns a new window would freeze
+            return
+        }
+        // Within its own layer, which isn't necessarily ours (workspaces, always on top)
+        let layer = unsafe { LayerRef::from_ptr_mut(view.layer_link().layer) };
+        view.geometry_dirty();
+        view.layer_entry_remove();
+        layer.view_list_entry_insert(view);
+        dsurf.propagate_layer();
+        view.geometry_dirty();
+        dsurf.surface_mut().damage();
+    }
+}
+
+unsafe extern "C" fn run_click_activate<SC: ShellSurface>(pointer: *mut weston_pointer, _time: *const libc::timespec, _button: u32, _data: *mut libc::c_void) {
+    click_activate::<SC>(PointerRef::from_ptr_mut(pointer));
+}
+
+fn click_activate<SC: ShellSurface>(p: &mut PointerRef) {
+    if !p.is_default_grab() {
+        return;
+    }
+    // The seat and the view both live in the pointer
+    let seat = unsafe { SeatRef::from_ptr(p.seat().as_ptr()) };
+    if let Some(focus_view) = p.focus_mut() {
+        activate::<SC>(focus_view, seat, ActivateFlag::CONFIGURE | ActivateFlag::CLICKED);
+    }
+}
+
+unsafe extern "C" fn run_modifier_move<SC: ShellSurface>(pointer: *mut weston_pointer, _time: *const libc::timespec, _button: u32, data: *mut libc::c_void) {
+    let state = &*(data as *const FloatingState);
+    modifier_move::<SC>(PointerRef::from_ptr_mut(pointer), state.config.snap.as_ref());
+}
+
+fn modifier_move<SC: ShellSurface>(p: &mut PointerRef, snap_config: Option<&SnapConfig>) {
+    if !p.is_default_grab() {
+        return;
+    }
+    let view = match p.focus() {
+        Some(view) => unsafe { ViewRef::from_ptr_mut(view.as_ptr()) },
+        None => return,
+    };
+    if let Some(dsurf) = desktop_surface_of::<SC>(view) {
+        if dsurf.get_fullscreen() || dsurf.get_maximized() {
+            return;
+        }
+        let seat = unsafe { SeatRef::from_ptr(p.seat().as_ptr()) };
+        activate::<SC>(view, seat, ActivateFlag::CONFIGURE | ActivateFlag::CLICKED);
+        match snap_config {
+            Some(config) => snap::start_move(dsurf, p, config),
+            None => start_move(dsurf, p),
+        }
+    }
+}
+
+pub struct FloatingShell<SC> {
+    focus: FocusManager<SC>,
+    menu: Option<WindowMenu<SC>>,
+    minimize: Option<MinimizeManager<SC>>,
+    /// Given to every minimize manager
+    minimized_hook: Option<MinimizedHook<SC>>,
+    /// Dropped last, the minimize manager puts windows back into its layers
+    state: Box<FloatingState>,
+}
+
+impl<SC: ShellSurface> FloatingShell<SC> {
+    /// Sets up the bindings and the focus tracking.
+    pub fn new(compositor: &mut CompositorRef, config: FloatingConfig) -> FloatingShell<SC> {
+        let mut layer = Layer::new(compositor);
+        layer.set_position(config.layer_position);
+        let mut click_bindings = Vec::new();
+        if config.click_to_activate {
+            for &button in &[ev::BTN_LEFT, ev::BTN_RIGHT] {
+                click_bindings.push(unsafe {
+                    weston_compositor_add_button_binding(compositor.as_ptr(), button, KeyboardModifier::empty().bits(),
+                                                         Some(run_click_activate::<SC>), ptr::null_mut())
+                });
+            }
+        }
+        let mut above = Layer::new(compositor);
+        above.set_position(config.layer_position + 1);
+        let focus = FocusManager::new(compositor, config.focus_policy);
+        let move_modifier = config.move_modifier;
+        let mut state = Box::new(FloatingState {
+            config,
+            layer,
+            above,
+            move_binding: ptr::null_mut(),
+            click_bindings,
+            shared: Rc::new(Cell::new(ptr::null_mut())),
+        });
+        let state_ptr = &mut *state as *mut FloatingState;
+        state.shared.set(state_ptr);
+        if let Some(modifier) = move_modifier {
+            state.move_binding = unsafe {
+                weston_compositor_add_button_binding(compositor.as_ptr(), ev::BTN_LEFT, modifier.bits(),
+                                                     Some(run_modifier_move::<SC>), state_ptr as *mut libc::c_void)
+            };
+        }
+        FloatingShell {
+            focus,
+            menu: None,
+            minimize: None,
+            minimized_hook: None,
+            state,
+        }
+    }
+
+    /// The layer windows live in
+    pub fn layer(&mut self) -> &mut LayerRef {
+        &mut self.state.layer
+    }
+
+    pub fn focus(&mut self) -> &mut FocusManager<SC> {
+        &mut self.focus
+    }
+
+    /// Keeps the window above the others (e.g. picture-in-picture video), see `set_always_on_top`
+    pub fn set_always_on_top(&mut self, dsurf: &mut DesktopSurfaceRef<SC>, on_top: bool) {
+        set_always_on_top(dsurf, on_top);
+    }
+
+    /// Shows the menu when clients ask for the window menu
+    pub fn set_window_menu(&mut self, menu: Option<WindowMenu<SC>>) {
+        self.menu = menu;
+    }
+
+    pub fn window_menu(&mut self) -> Option<&mut WindowMenu<SC>> {
+        self.menu.as_mut()
+    }
+
+    /// Minimizes windows when clients ask for it
+    pub fn set_minimize_manager(&mut self, mut minimize: Option<MinimizeManager<SC>>) {
+        if let Some(ref mut minimize) = minimize {
+            minimize.set_hook(self.minimized_hook.clone());
+        }
+        self.minimize = minimize;
+    }
+
+    pub fn minimize_manager(&mut self) -> Option<&mut MinimizeManager<SC>> {
+        self.minimize.as_mut()
+    }
+
+    /// For shells built on this one, to hear about windows minimized or restored (even on focus)
+    pub(super) fn set_minimized_hook(&mut self, hook: Option<MinimizedHook<SC>>) {
+        if let Some(ref mut minimize) = self.minimize {
+            minimize.set_hook(hook.clone());
+        }
+        self.minimized_hook = hook;
+    }
+}
+
+impl<SC: ShellSurface> DesktopApi<SC> for FloatingShell<SC> {
+    fn as_any(&mut self) -> &mut any::Any { self }
+
+    fn surface_added(&mut self, dsurf: &mut DesktopSurfaceRef<SC>) {
+        let mut view = dsurf.create_view();
+        self.state.layer.view_list_entry_insert(&mut view);
+        dsurf.surface_mut().damage();
+        let compositor = unsafe { CompositorRef::from_ptr_mut((*dsurf.surface().as_ptr()).compositor) };
+        compositor.schedule_repaint();
+        let _ = dsurf.set_user_data(Box::new(SC::from_floating(FloatingSurface {
+            view,
+            resize_edges: Resize::None,
+            last_width: 0.0,
+            last_height: 0.0,
+            restore_size: None,
+            restore_geometry: None,
+            minimized_from: None,
+            above_from: None,
+            shell: self.state.shared.clone(),
+        })));
+        // With the user data set, so that the focus manager knows it's one of ours
+        if self.state.config.activate_new {
+            if let Some(seat) = compositor.first_seat() {
+                floating_of(dsurf).view.activate(seat, ActivateFlag::CONFIGURE);
+            }
+        }
+    }
+
+    fn surface_removed(&mut self, dsurf: &mut DesktopSurfaceRef<SC>) {
+        let mut sctx = dsurf.get_user_data().expect("user_data");
+        dsurf.unlink_view(&mut sctx.floating().view);
+        drop(sctx);
+        self.focus.remove(dsurf);
+    }
+
+    fn committed(&mut self, dsurf: &mut DesktopSurfaceRef<SC>, _sx: i32, _sy: i32) {
+        let floating = floating_of(dsurf);
+        let surface = dsurf.surface();
+        let (from_x, from_y) = floating.view.from_global_float(0.0, 0.0);
+        let (to_x, to_y) = floating.view.from_global_float(
+            if floating.resize_edges.contains(Resize::Left) { floating.last_width - surface.width() as f32 } else { 0.0 },
+            if floating.resize_edges.contains(Resize::Top) { floating.last_height - surface.height() as f32 } else { 0.0 },
+        );
+        let (orig_x, orig_y) = floating.view.get_position();
+        floating.view.set_position(orig_x + to_x - from_x, orig_y + to_y - from_y);
+        floating.last_width = surface.width() as f32;
+        floating.last_height = surface.height() as f32;
+    }
+
+    fn show_window_menu(&mut self, dsurf: &mut DesktopSurfaceRef<SC>, seat: &mut SeatRef, x: i32, y: i32) {
+        if let Some(ref mut menu) = self.menu {
+            menu.show(dsurf, seat, x, y);
+        }
+    }
+
+    fn moove(&mut self, dsurf: &mut DesktopSurfaceRef<SC>, seat: &mut SeatRef, serial: u32) {
+        if let Some(pointer) = seat.pointer_mut() {
+            if pointer_grab_valid(dsurf, pointer, serial) {
+                match self.state.config.snap {
+                    Some(ref config) => snap::start_move(dsurf, pointer, config),
+                    None => start_move(dsurf, pointer),
+                }
+            }
+        }
+    }
+
+    fn resize(&mut self, dsurf: &mut DesktopSurfaceRef<SC>, seat: &mut SeatRef, serial: u32, edges: Resize) {
+        if let Some(pointer) = seat.pointer_mut() {
+            if pointer_grab_valid(dsurf, pointer, serial) {
+                match self.state.config.snap {
+                    Some(ref config) => snap::start_resize(dsurf, pointer, edges, config),
+                    None => start_resize(dsurf, pointer, edges),
+                }
+            }
+        }
+    }
+
+    fn fullscreen_requested(&mut self, dsurf: &mut DesktopSurfaceRef<SC>, fullscreen: bool, output: &mut OutputRef) {
+        let area = if !fullscreen {
+            // Back to maximized if it was before
+            if dsurf.get_maximized() { output_area(dsurf, true) } else { None }
+        } else if output.as_ptr().is_null() {
+            // The client lets the compositor pick
+            output_area(dsurf, false)
+        } else {
+            Some(output.geometry())
+        };
+        if fullscreen && area.is_none() {
+            return;
+        }
+        dsurf.set_fullscreen(fullscreen);
+        fill_area(dsurf, area);
+    }
+
+    fn maximized_requested(&mut self, dsurf: &mut DesktopSurfaceRef<SC>, maximized: bool) {
+        if dsurf.get_fullscreen() {
+            // Applies when leaving fullscreen
+            dsurf.set_maximized(maximized);
+            return;
+        }
+        let area = if maximized { output_area(dsurf, true) } else { None };
+        if maximized && area.is_none() {
+            return;
+        }
+        dsurf.set_maximized(maximized);
+        fill_area(dsurf, area);
+    }
+
+    fn minimized_requested(&mut self, dsurf: &mut DesktopSurfaceRef<SC>) {
+        if let Some(ref mut minimize) = self.minimize {
+            minimize.minimize(dsurf);
+        }
+    }
+}
@@ -0,0 +1,299 @@
+//! Keyboard focus policies, with a per-seat history of focused windows.
+//!
+//! `FocusManager` decides which window gets the keyboard: the clicked one, or the one under the
+//! pointer. It keeps the activated state of windows in sync with the keyboard focus of all seats
+//! (in desktop-shell this is part of seat state) and gives the focus back to the previously
+//! focused window when the focused one goes away.
+
+use std::{mem, ptr, marker};
+use libweston_sys::{weston_seat, weston_surface, weston_desktop_surface};
+use foreign_types::ForeignTypeRef;
+use ::compositor::CompositorRef;
+use ::desktop::DesktopSurfaceRef;
+use ::keyboard::KeyboardRef;
+use ::listener::{WlListener, ListenerList, SeatListeners};
+use ::pointer::PointerRef;
+use ::seat::SeatRef;
+use ::surface::SurfaceRef;
+use ::view::ActivateFlag;
+use super::floating::{ShellSurface, activate, is_shown};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FocusPolicy {
+    /// Clicking a window focuses it
+    Click,
+    /// Entering a window with the pointer focuses it, the focus stays when the pointer leaves
+    /// for the background
+    Sloppy,
+    /// The focus always follows the pointer, nothing is focused over the background
+    Strict,
+}
+
+struct SeatFocus {
+    seat: *mut weston_seat,
+    /// Main surface the keyboard is focused on
+    focused: *mut weston_surface,
+    /// Windows that had the keyboard focus, most recent first
+    history: Vec<*mut weston_desktop_surface>,
+    keyboard_tracked: bool,
+    pointer_tracked: bool,
+    listeners: SeatListeners,
+}
+
+pub(super) struct FocusState<SC> {
+    policy: FocusPolicy,
+    seats: Vec<SeatFocus>,
+    /// On the compositor's seat_created_signal
+    listeners: ListenerList,
+    /// Listeners of destroyed seats, one of them was running when they were removed
+    dead: Vec<ListenerList>,
+    phantom: marker::PhantomData<SC>,
+}
+
+/// The window a surface belongs to, if it's one of ours
+fn window_of<SC: ShellSurface>(surface: *mut weston_surface) -> Option<&'static mut DesktopSurfaceRef<SC>> {
+    if surface.is_null() {
+        return None;
+    }
+    let surface = unsafe { SurfaceRef::from_ptr(surface) };
+    DesktopSurfaceRef::<SC>::from_surface(surface)
+        .filter(|dsurf| dsurf.borrow_user_data().is_some())
+        .map(|dsurf| unsafe { DesktopSurfaceRef::from_ptr_mut(dsurf.as_ptr()) })
+}
+
+impl<SC: ShellSurface> FocusState<SC> {
+    /// Windows the seat has focused, most recent first
+    pub(super) fn history(&self, seat: *mut weston_seat) -> &[*mut weston_desktop_surface] {
+        self.seats.iter()
+            .find(|s| s.seat == seat)
+            .map(|s| &s.history[..])
+            .unwrap_or(&[])
+    }
+
+    fn seat_focus(&mut self, seat: *mut weston_seat) -> Option<&mut SeatFocus> {
+        self.seats.iter_mut().find(|s| s.seat == seat)
+    }
+
+    fn add_seat(&mut self, seat: &mut SeatRef) {
+        let state_ptr = self as *mut FocusState<SC>;
+        let mut listeners = SeatListeners::new(seat.as_ptr());
+        // Keyboards and pointers usually show up after the seat
+        listeners.own.add(WlListener::new(Box::new(move |seat: &mut SeatRef| {
+            unsafe { (*state_ptr).track_devices(seat); }
+        })), seat.updated_caps_signal());
+        listeners.own.add(WlListener::new(Box::new(move |seat: &mut SeatRef| {
+            unsafe { (*state_ptr).seat_destroyed(seat.as_ptr()); }
+        })), seat.destroy_signal());
+        self.seats.push(SeatFocus {
+            seat: seat.as_ptr(),
+            focused: ptr::null_mut(),
+            history: Vec::new(),
+            keyboard_tracked: false,
+            pointer_tracked: false,
+            listeners,
+        });
+        self.track_devices(seat);
+    }
+
+    fn seat_destroyed(&mut self, seat: *mut weston_seat) {
+        self.dead.clear();
+        let index = self.seats.iter().position(|s| s.seat == seat);
+        if let Some(i) = index {
+            let listeners = self.seats.remove(i).listeners.destroyed();
+            self.dead.push(listeners);
+        }
+    }
+
+    fn track_devices(&mut self, seat: &mut SeatRef) {
+        let state_ptr = self as *mut FocusState<SC>;
+        let seat_ptr = seat.as_ptr();
+        let seat_focus = match self.seat_focus(seat_ptr) {
+            Some(seat_focus) => seat_focus,
+            None => return,
+        };
+        if let (false, Some(keyboard)) = (seat_focus.keyboard_tracked, seat.keyboard_mut()) {
+            seat_focus.keyboard_tracked = true;
+            seat_focus.listeners.devices.add(WlListener::new(Box::new(move |k: &mut KeyboardRef| {
+                unsafe { (*state_ptr).keyboard_focused(seat_ptr, k); }
+            })), keyboard.focus_signal());
+        }
+        if let (false, Some(pointer)) = (seat_focus.pointer_tracked, seat.pointer_mut()) {
+            seat_focus.pointer_tracked = true;
+            seat_focus.listeners.devices.add(WlListener::new(Box::new(move |p: &mut PointerRef| {
+                unsafe { (*state_ptr).pointer_focused(p); }
+            })), pointer.focus_signal());
+        }
+    }
+
+    fn keyboard_focused(&mut self, seat: *mut weston_seat, keyboard: &KeyboardRef) {
+        let focused = keyboard.focus().map(|s| s.main_surface().as_ptr()).unwrap_or(ptr::null_mut());
+        let old = {
+            let seat_focus = match self.seat_focus(seat) {
+                Some(seat_focus) => seat_focus,
+                None => return,
+            };
+            if let Some(dsurf) = window_of::<SC>(focused) {
+                seat_focus.history.retain(|&w| w != dsurf.as_ptr());
+                seat_focus.history.insert(0, dsurf.as_ptr());
+            }
+            mem::replace(&mut seat_focus.focused, focused)
+        };
+        if old != focused {
+            self.update_activated(old);
+            self.update_activated(focused);
+        }
+    }
+
+    /// A window is activated while any keyboard is focused on it
+    fn update_activated(&self, surface: *mut weston_surface) {
+        if let Some(dsurf) = window_of::<SC>(surface) {
+            let activated = self.seats.iter().any(|s| s.focused == surface);
+            if dsurf.get_activated() != activated {
+                dsurf.set_activated(activated);
+            }
+        }
+    }
+
+    fn pointer_focused(&mut self, pointer: &mut PointerRef) {
+        // Moves, resizes and popups keep the focus where it is, so do keyboard grabs
+        // (e.g. moving a window with the keyboard, under the pointer)
+        if self.policy == FocusPolicy::Click || !pointer.is_default_grab() ||
+            !pointer.seat().keyboard().map(|k| k.is_default_grab()).unwrap_or(true) {
+            return;
+        }
+        let window = pointer.focus().and_then(|view| window_of::<SC>(view.surface().main_surface().as_ptr()));
+        let seat = pointer.seat_mut();
+        match window {
+            Some(dsurf) => {
+                let focused = seat.keyboard().and_then(|k| k.focus()).map(|s| s.main_surface().as_ptr());
+                if focused != Some(dsurf.surface().as_ptr()) {
+                    // Focusing by pointing doesn't raise
+                    dsurf.borrow_user_data().expect("user_data").floating().view_mut()
+                        .activate(seat, ActivateFlag::CONFIGURE);
+                }
+            },
+            None if self.policy == FocusPolicy::Strict => seat.clear_keyboard_focus(),
+            None => (),
+        }
+    }
+
+    fn refocus(&mut self, seat: *mut weston_seat) {
+        let next = match self.seat_focus(seat) {
+            Some(seat_focus) => seat_focus.history.iter().cloned().find(|&w| {
+                let dsurf = unsafe { DesktopSurfaceRef::<SC>::from_ptr(w) };
+                dsurf.borrow_user_data().map(|sctx| is_shown(sctx.floating().view())).unwrap_or(false)
+            }),
+            None => return,
+        };
+        let seat = unsafe { SeatRef::from_ptr_mut(seat) };
+        match next {
+            Some(surface) => {
+                let dsurf = unsafe { DesktopSurfaceRef::<SC>::from_ptr(surface) };
+                let view = dsurf.borrow_user_data().expect("user_data").floating().view_mut();
+                activate::<SC>(view, seat, ActivateFlag::CONFIGURE);
+            },
+            None => seat.clear_keyboard_focus(),
+        }
+    }
+
+    pub(super) fn refocus_hidden(&mut self) {
+        let seats = self.seats.iter()
+            .filter(|s| s.focused.is_null() || window_of::<SC>(s.focused)
+                    .map(|dsurf| !is_shown(dsurf.borrow_user_data().expect("user_data").floating().view()))
+                    .unwrap_or(false))
+            .map(|s| s.seat)
+            .collect::<Vec<_>>();
+        for seat in seats {
+            self.refocus(seat);
+        }
+    }
+}
+
+pub struct FocusManager<SC> {
+    state: Box<FocusState<SC>>,
+}
+
+impl<SC: ShellSurface> FocusManager<SC> {
+    /// Tracks the focus of all seats, including the ones created later.
+    pub fn new(compositor: &mut CompositorRef, policy: FocusPolicy) -> FocusManager<SC> {
+        let mut state = Box::new(FocusState {
+            policy,
+            seats: Vec::new(),
+            listeners: ListenerList::new(),
+            dead: Vec::new(),
+            phantom: marker::PhantomData,
+        });
+        for seat in compositor.iterate_seats() {
+            state.add_seat(seat);
+        }
+        let state_ptr = &mut *state as *mut FocusState<SC>;
+        state.listeners.add(WlListener::new(Box::new(move |seat: &mut SeatRef| {
+            unsafe { (*state_ptr).add_seat(seat); }
+        })), compositor.seat_created_signal());
+        FocusManager { state }
+    }
+
+    pub fn policy(&self) -> FocusPolicy {
+        self.state.policy
+    }
+
+    /// Takes effect the next time the pointer enters a window
+    pub fn set_policy(&mut self, policy: FocusPolicy) {
+        self.state.policy = policy;
+    }
+
+    /// The window the seat's keyboard is focused on
+    pub fn focused(&mut self, seat: &SeatRef) -> Option<&mut DesktopSurfaceRef<SC>> {
+        self.state.seats.iter()
+            .find(|s| s.seat == seat.as_ptr())
+            .and_then(|s| window_of::<SC>(s.focused))
+    }
+
+    /// Windows the seat has focused, most recent first
+    pub fn history(&mut self, seat: &SeatRef) -> Vec<&mut DesktopSurfaceRef<SC>> {
+        self.state.history(seat.as_ptr()).iter()
+            .map(|&w| unsafe { DesktopSurfaceRef::from_ptr_mut(w) })
+            .collect()
+    }
+
+    /// For grabs that need the history later on. The state is boxed, so this stays valid for as
+    /// long as the manager is around.
+    pub(super) fn state_ptr(&self) -> *const FocusState<SC> {
+        &*self.state
+    }
+
+    pub(super) fn state_ptr_mut(&mut self) -> *mut FocusState<SC> {
+        &mut *self.state
+    }
+
+    /// Forgets a window that's going away. Seats that were focused on it go back to the
+    /// previously focused window, unless the pointer decides (`Strict`).
+    pub fn remove(&mut self, dsurf: &DesktopSurfaceRef<SC>) {
+        let surface = dsurf.surface().as_ptr();
+        let mut orphans = Vec::new();
+        for seat_focus in &mut self.state.seats {
+            // The keyboard might have already let go if the surface was destroyed first
+            let was_focused = seat_focus.focused == surface ||
+                (seat_focus.focused.is_null() && seat_focus.history.first() == Some(&dsurf.as_ptr()));
+            seat_focus.history.retain(|&w| w != dsurf.as_ptr());
+            if was_focused {
+                orphans.push(seat_focus.seat);
+            }
+        }
+        if self.state.policy != FocusPolicy::Strict {
+            for seat in orphans {
+                self.state.refocus(seat);
+            }
+        }
+    }
+
+    /// Focuses the most recently focused window that's still shown, or nothing
+    pub fn refocus(&mut self, seat: &SeatRef) {
+        self.state.refocus(seat.as_ptr());
+    }
+
+    /// Refocuses the seats whose window isn't shown anymore, e.g. after switching workspaces
+    pub fn refocus_hidden(&mut self) {
+        self.state.refocus_hidden();
+    }
+}
@@ -0,0 +1,211 @@
+//! Moving and resizing windows with the keyboard, for when using a pointer is hard (or there's
+//! no pointer at all).
+//!
+//! While the grab is active, the arrow keys move the window (or change its size, the top left
+//! corner staying in place) by a step, or by a fine step with Shift held. Enter keeps the result,
+//! Escape puts the window back the way it was. Like in metacity's Alt+F7/Alt+F8.
+
+use libc;
+use std::ptr;
+use std::cell::Cell;
+use std::rc::Rc;
+use libweston_sys::weston_keyboard_grab;
+use foreign_types::ForeignTypeRef;
+use ::ev;
+use ::compositor::CompositorRef;
+use ::desktop::DesktopSurfaceRef;
+use ::keyboard::{KeyboardRef, KeyboardGrab, KeyboardModifier, KeyState};
+use ::seat::SeatRef;
+use ::view::ActivateFlag;
+use super::floating::{ShellSurface, GrabTarget, activate, clamp_size};
+
+#[derive(Builder, Clone)]
+pub struct StepConfig {
+    /// Pixels per arrow key press
+    #[builder(default = "20")]
+    step: i32,
+    /// Pixels per arrow key press with Shift held
+    #[builder(default = "1")]
+    fine_step: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Move,
+    Resize,
+}
+
+/// Keyboard handler for moving or resizing a window
+struct StepGrab<SC: 'static> {
+    target: GrabTarget<SC>,
+    mode: Mode,
+    config: StepConfig,
+    /// View position and window size to go back to on Escape
+    orig_x: f32,
+    orig_y: f32,
+    orig_width: i32,
+    orig_height: i32,
+    width: i32,
+    height: i32,
+}
+
+impl<SC: ShellSurface> StepGrab<SC> {
+    fn step(&mut self, dsurf: &mut DesktopSurfaceRef<SC>, dx: i32, dy: i32) {
+        match self.mode {
+            Mode::Move => {
+                let view = dsurf.borrow_user_data().expect("user_data").floating().view_mut();
+                let (x, y) = view.get_position();
+                view.set_position(x + dx as f32, y + dy as f32);
+                view.geometry_dirty();
+            },
+            Mode::Resize => {
+                let (width, height) = clamp_size(dsurf, self.width + dx, self.height + dy);
+                self.width = width;
+                self.height = height;
+                dsurf.set_size(width, height);
+            },
+        }
+        dsurf.surface_mut().compositor_mut().schedule_repaint();
+    }
+
+    /// Ends the grab, keeping the result or not (unless the window is gone)
+    fn finish(&mut self, keyboard: &mut KeyboardRef, keep: bool) {
+        if let Some(dsurf) = self.target.get() {
+            if !keep {
+                {
+                    let view = dsurf.borrow_user_data().expect("user_data").floating().view_mut();
+                    view.set_position(self.orig_x, self.orig_y);
+                    view.geometry_dirty();
+                }
+                if self.mode == Mode::Resize {
+                    dsurf.set_size(self.orig_width, self.orig_height);
+                }
+                dsurf.surface_mut().compositor_mut().schedule_repaint();
+            }
+            if self.mode == Mode::Resize {
+                dsurf.set_resizing(false);
+            }
+        }
+        self.target.release();
+        keyboard.end_grab();
+    }
+}
+
+impl<SC: ShellSurface> KeyboardGrab for StepGrab<SC> {
+    fn key(&mut self, keyboard: &mut KeyboardRef, _time: &libc::timespec, key: u32, state: KeyState) {
+        let dsurf = match self.target.get() {
+            Some(dsurf) => dsurf,
+            None => {
+                self.finish(keyboard, false);
+                return;
+            },
+        };
+        if state != KeyState::Pressed {
+            return;
+        }
+        let step = if keyboard.seat().modifier_state().contains(KeyboardModifier::SHIFT) {
+            self.config.fine_step
+        } else {
+            self.config.step
+        };
+        match key {
+            ev::KEY_LEFT => self.step(dsurf, -step, 0),
+            ev::KEY_RIGHT => self.step(dsurf, step, 0),
+            ev::KEY_UP => self.step(dsurf, 0, -step),
+            ev::KEY_DOWN => self.step(dsurf, 0, step),
+            ev::KEY_ENTER | ev::KEY_KPENTER => self.finish(keyboard, true),
+            ev::KEY_ESC => self.finish(keyboard, false),
+            _ => (),
+        }
+    }
+
+    fn modifiers(&mut self, _keyboard: &mut KeyboardRef, _serial: u32,
+                 _mods_depressed: KeyboardModifier, _mods_latched: KeyboardModifier,
+                 _mods_locked: KeyboardModifier, _group: u32) {
+    }
+
+    fn cancel(&mut self, keyboard: &mut KeyboardRef) {
+        self.finish(keyboard, false);
+    }
+}
+
+fn start<SC: ShellSurface>(dsurf: &mut DesktopSurfaceRef<SC>, keyboard: &mut KeyboardRef, mode: Mode, config: &StepConfig) {
+    if !keyboard.is_default_grab() || dsurf.get_fullscreen() || dsurf.get_maximized() {
+        return;
+    }
+    // The window being worked on gets the focus (e.g. when started from a window menu)
+    if keyboard.focus().map(|s| s.main_surface().as_ptr()) != Some(dsurf.surface().as_ptr()) {
+        let seat = unsafe { SeatRef::from_ptr(keyboard.seat().as_ptr()) };
+        activate::<SC>(dsurf.borrow_user_data().expect("user_data").floating().view_mut(), seat, ActivateFlag::CONFIGURE);
+    }
+    let geometry = dsurf.get_geometry();
+    let (orig_x, orig_y) = dsurf.borrow_user_data().expect("user_data").floating().view().get_position();
+    if mode == Mode::Resize {
+        dsurf.set_resizing(true);
+    }
+    // Known once started
+    let grab = Rc::new(Cell::new(ptr::null_mut::<weston_keyboard_grab>()));
+    let keyboard_ptr = keyboard.as_ptr();
+    let target = {
+        let grab = grab.clone();
+        GrabTarget::new(dsurf, move || unsafe {
+            // Unless something else took over the keyboard
+            let grab = grab.get();
+            if (*keyboard_ptr).grab == grab {
+                if let Some(cancel) = (*(*grab).interface).cancel {
+                    cancel(grab);
+                }
+            }
+        })
+    };
+    let step_grab = StepGrab {
+        target,
+        mode,
+        config: config.clone(),
+        orig_x,
+        orig_y,
+        orig_width: geometry.width,
+        orig_height: geometry.height,
+        width: geometry.width,
+        height: geometry.height,
+    };
+    keyboard.start_grab(step_grab);
+    grab.set(unsafe { (*keyboard_ptr).grab });
+}
+
+/// Lets the arrow keys move the window until Enter or Escape is pressed
+pub fn start_move<SC: ShellSurface>(dsurf: &mut DesktopSurfaceRef<SC>, keyboard: &mut KeyboardRef, config: &StepConfig) {
+    start(dsurf, keyboard, Mode::Move, config);
+}
+
+/// Lets the arrow keys resize the window until Enter or Escape is pressed
+pub fn start_resize<SC: ShellSurface>(dsurf: &mut DesktopSurfaceRef<SC>, keyboard: &mut KeyboardRef, config: &StepConfig) {
+    start(dsurf, keyboard, Mode::Resize, config);
+}
+
+/// The window the keyboard is focused on, if it's one of ours
+fn focused_window<SC: ShellSurface>(keyboard: &KeyboardRef) -> Option<&'static mut DesktopSurfaceRef<SC>> {
+    keyboard.focus()
+        .and_then(|surface| DesktopSurfaceRef::<SC>::from_surface(surface.main_surface()))
+        .filter(|dsurf| dsurf.borrow_user_data().is_some())
+        .map(|dsurf| unsafe { DesktopSurfaceRef::from_ptr_mut(dsurf.as_ptr()) })
+}
+
+/// Binds keys (while holding the modifier) to moving and resizing the focused window
+pub fn bind<SC: ShellSurface>(compositor: &mut CompositorRef, move_key: u32, resize_key: u32,
+                              modifier: KeyboardModifier, config: &StepConfig) {
+    let move_config = config.clone();
+    let move_handler: &'static _ = Box::leak(Box::new(move |keyboard: &mut KeyboardRef, _time: &libc::timespec, _key: u32| {
+        if let Some(dsurf) = focused_window::<SC>(keyboard) {
+            start_move(dsurf, keyboard, &move_config);
+        }
+    }));
+    compositor.add_key_binding(move_key, modifier, move_handler);
+    let resize_config = config.clone();
+    let resize_handler: &'static _ = Box::leak(Box::new(move |keyboard: &mut KeyboardRef, _time: &libc::timespec, _key: u32| {
+        if let Some(dsurf) = focused_window::<SC>(keyboard) {
+            start_resize(dsurf, keyboard, &resize_config);
+        }
+    }));
+    compositor.add_key_binding(resize_key, modifier, resize_handler);
+}
@@ -0,0 +1,467 @@
+//! A window menu drawn by the compositor, for `DesktopApi::show_window_menu` (right click on a
+//! client-side titlebar) or `FrameAction::Menu` from server-side decorations.
+//!
+//! The menu is a surface of an `InternalClient` drawn with cairo, placed at the pointer with a
+//! `Positioner` so that it stays on the output. While it's open, the pointer and the keyboard
+//! (Up, Down, Enter, Escape) are grabbed.
+
+use libc;
+use std::{ffi, mem, ptr};
+use std::mem::ManuallyDrop;
+use libweston_sys::{
+    weston_desktop_surface, weston_seat, weston_layer_unset_position,
+    cairo_t, cairo_format_t, cairo_font_slant_t, cairo_font_weight_t, cairo_text_extents_t,
+    cairo_image_surface_create_for_data, cairo_surface_flush, cairo_surface_destroy,
+    cairo_create, cairo_destroy, cairo_set_source_rgba, cairo_rectangle, cairo_fill, cairo_paint,
+    cairo_move_to, cairo_show_text, cairo_select_font_face, cairo_set_font_size, cairo_text_extents,
+};
+use wayland_sys::common::wl_fixed_to_int;
+use wayland_sys::server::wl_list_remove;
+use foreign_types::ForeignTypeRef;
+use ::Geometry;
+use ::compositor::CompositorRef;
+use ::desktop::{DesktopSurfaceRef, Positioner, PositionerAnchor, ConstraintAdjustment};
+use ::ev;
+use ::internal_client::{InternalClient, InternalSurface};
+use ::keyboard::{KeyboardRef, KeyboardGrab, KeyboardModifier, KeyState};
+use ::layer::{Layer, POSITION_TOP_UI};
+use ::listener::WlListener;
+use ::pointer::{PointerGrab, PointerRef, PointerMotionEvent, ButtonState};
+use ::seat::SeatRef;
+use ::surface::SurfaceRef;
+use ::view::View;
+use super::floating::{self, ShellSurface};
+use super::keyboard::{self, StepConfigBuilder};
+
+const CAIRO_FORMAT_ARGB32: cairo_format_t = 0;
+const CAIRO_FONT_SLANT_NORMAL: cairo_font_slant_t = 0;
+const CAIRO_FONT_WEIGHT_NORMAL: cairo_font_weight_t = 0;
+
+const FONT_SIZE: f64 = 13.0;
+const ITEM_HEIGHT: i32 = 26;
+const PADDING: i32 = 4;
+/// Room on the left of the labels for check marks
+const CHECK_WIDTH: i32 = 20;
+const MIN_WIDTH: i32 = 140;
+/// How far the pointer has to go before releasing the button that opened the menu picks an entry
+const DRAG_THRESHOLD: i32 = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MenuAction {
+    /// Toggle
+    Maximize,
+    Minimize,
+    Move,
+    Resize,
+    /// Toggle
+    AlwaysOnTop,
+    Close,
+}
+
+pub trait WindowMenuApi<SC> {
+    /// Runs the chosen entry (`default_action` handles the ones that need nothing from the shell)
+    fn menu_action(&mut self, surface: &mut DesktopSurfaceRef<SC>, seat: &mut SeatRef, action: MenuAction);
+
+    /// Whether the window is kept above the others, for the check mark
+    fn is_always_on_top(&self, surface: &DesktopSurfaceRef<SC>) -> bool where SC: ShellSurface {
+        surface.borrow_user_data().map(|s| s.floating().is_always_on_top()).unwrap_or(false)
+    }
+}
+
+/// Move and resize start the keyboard grabs (the menu might have been opened from the keyboard),
+/// always on top goes through `floating::set_always_on_top`, close asks the client to close.
+/// The rest is up to the shell.
+pub fn default_action<SC: ShellSurface>(surface: &mut DesktopSurfaceRef<SC>, seat: &mut SeatRef, action: MenuAction) {
+    match action {
+        MenuAction::Move | MenuAction::Resize => {
+            if let Some(kbd) = seat.keyboard_mut() {
+                let config = StepConfigBuilder::default().build().expect("default StepConfig");
+                if action == MenuAction::Move {
+                    keyboard::start_move(surface, kbd, &config);
+                } else {
+                    keyboard::start_resize(surface, kbd, &config);
+                }
+            }
+        },
+        MenuAction::AlwaysOnTop => {
+            let on_top = surface.borrow_user_data().map(|s| s.floating().is_always_on_top()).unwrap_or(false);
+            floating::set_always_on_top(surface, !on_top);
+        },
+        MenuAction::Close => surface.close(),
+        MenuAction::Maximize | MenuAction::Minimize => (),
+    }
+}
+
+type SurfaceListener = ManuallyDrop<Box<WlListener<SurfaceRef>>>;
+
+struct MenuState<SC> {
+    api: Box<dyn WindowMenuApi<SC>>,
+    // view first: it must be destroyed before the surface
+    view: Option<View>,
+    internal: InternalSurface,
+    _client: InternalClient,
+    layer: Layer,
+    items: Vec<MenuAction>,
+    /// The window the menu is open for, null when closed
+    window: *mut weston_desktop_surface,
+    seat: *mut weston_seat,
+    geometry: Geometry,
+    selected: Option<usize>,
+    /// The release of the click that opened the menu doesn't pick anything, unless the
+    /// pointer went somewhere in between
+    armed: bool,
+    origin: (i32, i32),
+    destroy_listener: Option<SurfaceListener>,
+    // Listeners can't be freed while they run, these go on the next `open`
+    dead: Vec<SurfaceListener>,
+}
+
+unsafe fn set_font(cr: *mut cairo_t) {
+    let family = ffi::CString::new("sans-serif").expect("CString");
+    cairo_select_font_face(cr, family.as_ptr(), CAIRO_FONT_SLANT_NORMAL, CAIRO_FONT_WEIGHT_NORMAL);
+    cairo_set_font_size(cr, FONT_SIZE);
+}
+
+fn text_width(labels: &[ffi::CString]) -> i32 {
+    let mut pixel = [0u8; 4];
+    let mut widest = 0.0f64;
+    unsafe {
+        let cs = cairo_image_surface_create_for_data(pixel.as_mut_ptr(), CAIRO_FORMAT_ARGB32, 1, 1, 4);
+        let cr = cairo_create(cs);
+        set_font(cr);
+        for label in labels {
+            let mut extents: cairo_text_extents_t = mem::zeroed();
+            cairo_text_extents(cr, label.as_ptr(), &mut extents);
+            widest = widest.max(extents.x_advance);
+        }
+        cairo_destroy(cr);
+        cairo_surface_destroy(cs);
+    }
+    widest.ceil() as i32
+}
+
+impl<SC> MenuState<SC> {
+    fn close(&mut self) {
+        if self.window.is_null() {
+            return;
+        }
+        self.window = ptr::null_mut();
+        if let Some(mut listener) = self.destroy_listener.take() {
+            unsafe { wl_list_remove(&mut listener.wll.link); }
+            self.dead.push(listener);
+        }
+        if let Some(ref mut view) = self.view {
+            if !view.layer_link().layer.is_null() {
+                view.damage_below();
+                view.layer_entry_remove();
+            }
+        }
+        let seat = unsafe { SeatRef::from_ptr_mut(self.seat) };
+        if let Some(pointer) = seat.pointer_mut() {
+            if !pointer.is_default_grab() {
+                pointer.end_grab();
+            }
+        }
+        if let Some(kbd) = seat.keyboard_mut() {
+            if !kbd.is_default_grab() {
+                kbd.end_grab();
+            }
+        }
+    }
+}
+
+impl<SC: ShellSurface> MenuState<SC> {
+    fn entries(&self) -> Vec<(ffi::CString, bool)> {
+        let dsurf = unsafe { DesktopSurfaceRef::<SC>::from_ptr(self.window) };
+        self.items.iter().map(|&item| {
+            let (label, checked) = match item {
+                MenuAction::Maximize if dsurf.get_maximized() => ("Unmaximize", false),
+                MenuAction::Maximize => ("Maximize", false),
+                MenuAction::Minimize => ("Minimize", false),
+                MenuAction::Move => ("Move", false),
+                MenuAction::Resize => ("Resize", false),
+                MenuAction::AlwaysOnTop => ("Always on top", self.api.is_always_on_top(dsurf)),
+                MenuAction::Close => ("Close", false),
+            };
+            (ffi::CString::new(label).expect("CString"), checked)
+        }).collect()
+    }
+
+    fn open(&mut self, dsurf: &mut DesktopSurfaceRef<SC>, seat: &mut SeatRef, x: i32, y: i32) {
+        self.close();
+        for listener in self.dead.drain(..) {
+            drop(ManuallyDrop::into_inner(listener));
+        }
+        // Don't break moves, resizes or popups in progress
+        let busy = seat.pointer().map(|p| !p.is_default_grab()).unwrap_or(false) ||
+            seat.keyboard().map(|k| !k.is_default_grab()).unwrap_or(false);
+        if self.items.is_empty() || busy {
+            return;
+        }
+        self.window = dsurf.as_ptr();
+        self.seat = seat.as_ptr();
+        self.selected = None;
+        self.armed = false;
+        self.origin = (x, y);
+
+        let labels = self.entries().into_iter().map(|(label, _)| label).collect::<Vec<_>>();
+        let width = (text_width(&labels) + CHECK_WIDTH + 4 * PADDING).max(MIN_WIDTH);
+        let height = self.items.len() as i32 * ITEM_HEIGHT + 2 * PADDING;
+        let mut positioner = Positioner::new(width, height, Geometry { x, y, width: 1, height: 1 });
+        positioner.anchor = PositionerAnchor::BottomRight;
+        positioner.gravity = PositionerAnchor::BottomRight;
+        positioner.constraint_adjustment = ConstraintAdjustment::FLIP_X | ConstraintAdjustment::FLIP_Y |
+            ConstraintAdjustment::SLIDE_X | ConstraintAdjustment::SLIDE_Y;
+        let bounds = seat.compositor().iterate_outputs()
+            .map(|output| output.work_area())
+            .find(|area| x >= area.x && x < area.x + area.width && y >= area.y && y < area.y + area.height);
+        self.geometry = match bounds {
+            Some(bounds) => positioner.constrained_geometry(0, 0, &bounds),
+            None => positioner.unconstrained_geometry(),
+        };
+        self.draw();
+
+        if self.view.is_none() {
+            self.view = self.internal.surface().map(|s| View::new(s));
+        }
+        if let Some(ref mut view) = self.view {
+            self.layer.view_list_entry_insert(view);
+            view.set_position(self.geometry.x as f32, self.geometry.y as f32);
+            view.geometry_dirty();
+            view.schedule_repaint();
+        }
+
+        let state_ptr = self as *mut MenuState<SC>;
+        let window = self.window;
+        let mut destroy = WlListener::new(Box::new(move |_: &mut SurfaceRef| unsafe {
+            if (*state_ptr).window == window {
+                (*state_ptr).close();
+            }
+        }));
+        destroy.signal_add(dsurf.surface().destroy_signal());
+        self.destroy_listener = Some(destroy);
+
+        if let Some(pointer) = seat.pointer_mut() {
+            pointer.start_grab(MenuPointerGrab { state: state_ptr });
+        }
+        if let Some(kbd) = seat.keyboard_mut() {
+            kbd.start_grab(MenuKeyboardGrab { state: state_ptr });
+        }
+    }
+
+    /// Closes the menu, then runs the entry
+    fn pick(&mut self, index: usize) {
+        if self.window.is_null() {
+            return;
+        }
+        let action = self.items[index];
+        let dsurf = unsafe { DesktopSurfaceRef::<SC>::from_ptr_mut(self.window) };
+        let seat = unsafe { SeatRef::from_ptr_mut(self.seat) };
+        self.close();
+        self.api.menu_action(dsurf, seat, action);
+    }
+
+    fn item_at(&self, x: i32, y: i32) -> Option<usize> {
+        let g = &self.geometry;
+        if x < g.x || x >= g.x + g.width || y < g.y + PADDING || y >= g.y + g.height - PADDING {
+            return None;
+        }
+        Some(((y - g.y - PADDING) / ITEM_HEIGHT) as usize).filter(|&i| i < self.items.len())
+    }
+
+    fn select(&mut self, selected: Option<usize>) {
+        if selected != self.selected {
+            self.selected = selected;
+            self.draw();
+        }
+    }
+
+    fn draw(&mut self) {
+        let entries = self.entries();
+        let (width, height) = (self.geometry.width, self.geometry.height);
+        let stride = width * 4;
+        let mut pixels = vec![0u8; stride as usize * height as usize];
+        unsafe {
+            let cs = cairo_image_surface_create_for_data(pixels.as_mut_ptr(), CAIRO_FORMAT_ARGB32, width, height, stride);
+            let cr = cairo_create(cs);
+            cairo_set_source_rgba(cr, 0.16, 0.16, 0.18, 0.96);
+            cairo_paint(cr);
+            set_font(cr);
+            for (i, &(ref label, checked)) in entries.iter().enumerate() {
+                let top = f64::from(PADDING + i as i32 * ITEM_HEIGHT);
+                if self.selected == Some(i) {
+                    cairo_set_source_rgba(cr, 0.26, 0.46, 0.80, 1.0);
+                    cairo_rectangle(cr, 0.0, top, f64::from(width), f64::from(ITEM_HEIGHT));
+                    cairo_fill(cr);
+                }
+                cairo_set_source_rgba(cr, 0.95, 0.95, 0.95, 1.0);
+                if checked {
+                    cairo_rectangle(cr, f64::from(2 * PADDING), top + f64::from(ITEM_HEIGHT / 2 - 4), 8.0, 8.0);
+                    cairo_fill(cr);
+                }
+                cairo_move_to(cr, f64::from(CHECK_WIDTH + 2 * PADDING), top + f64::from(ITEM_HEIGHT) / 2.0 + FONT_SIZE / 3.0);
+                cairo_show_text(cr, label.as_ptr());
+            }
+            cairo_destroy(cr);
+            cairo_surface_flush(cs);
+            cairo_surface_destroy(cs);
+        }
+        self.internal.attach_pixels(width, height, &pixels);
+    }
+}
+
+struct MenuPointerGrab<SC: 'static> {
+    state: *mut MenuState<SC>,
+}
+
+impl<SC: ShellSurface> PointerGrab for MenuPointerGrab<SC> {
+    fn motion(&mut self, pointer: &mut PointerRef, _time: &libc::timespec, event: PointerMotionEvent) {
+        pointer.moove(event);
+        let state = unsafe { &mut *self.state };
+        let (x, y) = (wl_fixed_to_int(pointer.x()), wl_fixed_to_int(pointer.y()));
+        if (x - state.origin.0).abs() > DRAG_THRESHOLD || (y - state.origin.1).abs() > DRAG_THRESHOLD {
+            state.armed = true;
+        }
+        let selected = state.item_at(x, y);
+        state.select(selected);
+    }
+
+    fn button(&mut self, pointer: &mut PointerRef, _time: &libc::timespec, _button: u32, button_state: ButtonState) {
+        let state = unsafe { &mut *self.state };
+        let item = state.item_at(wl_fixed_to_int(pointer.x()), wl_fixed_to_int(pointer.y()));
+        let inside = {
+            let g = &state.geometry;
+            let (x, y) = (wl_fixed_to_int(pointer.x()), wl_fixed_to_int(pointer.y()));
+            x >= g.x && x < g.x + g.width && y >= g.y && y < g.y + g.height
+        };
+        match button_state {
+            ButtonState::Pressed if !inside => state.close(),
+            ButtonState::Pressed => state.armed = true,
+            ButtonState::Released if pointer.button_count() == 0 => {
+                match item {
+                    Some(index) if state.armed => state.pick(index),
+                    _ => state.armed = true,
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn cancel(&mut self, _pointer: &mut PointerRef) {
+        unsafe { (*self.state).close(); }
+    }
+}
+
+struct MenuKeyboardGrab<SC: 'static> {
+    state: *mut MenuState<SC>,
+}
+
+impl<SC: ShellSurface> KeyboardGrab for MenuKeyboardGrab<SC> {
+    fn key(&mut self, _keyboard: &mut KeyboardRef, _time: &libc::timespec, key: u32, key_state: KeyState) {
+        if key_state != KeyState::Pressed {
+            return;
+        }
+        let state = unsafe { &mut *self.state };
+        let count = state.items.len();
+        match key {
+            ev::KEY_UP => {
+                let selected = state.selected.map(|i| (i + count - 1) % count).unwrap_or(count - 1);
+                state.select(Some(selected));
+            },
+            ev::KEY_DOWN => {
+                let selected = state.selected.map(|i| (i + 1) % count).unwrap_or(0);
+                state.select(Some(selected));
+            },
+            ev::KEY_ENTER | ev::KEY_KPENTER => {
+                if let Some(index) = state.selected {
+                    state.pick(index);
+                }
+            },
+            ev::KEY_ESC => state.close(),
+            _ => (),
+        }
+    }
+
+    fn modifiers(&mut self, _keyboard: &mut KeyboardRef, _serial: u32,
+                 _mods_depressed: KeyboardModifier, _mods_latched: KeyboardModifier,
+                 _mods_locked: KeyboardModifier, _group: u32) {
+    }
+
+    fn cancel(&mut self, _keyboard: &mut KeyboardRef) {
+        unsafe { (*self.state).close(); }
+    }
+}
+
+/// The menu, shared by all windows (there's only one open at a time).
+pub struct WindowMenu<SC> {
+    state: Box<MenuState<SC>>,
+}
+
+impl<SC: ShellSurface> WindowMenu<SC> {
+    pub fn new(compositor: &mut CompositorRef, api: Box<dyn WindowMenuApi<SC>>) -> Option<WindowMenu<SC>> {
+        let mut client = InternalClient::new(compositor)?;
+        let internal = client.create_surface();
+        let mut layer = Layer::new(compositor);
+        layer.set_position(POSITION_TOP_UI);
+        Some(WindowMenu {
+            state: Box::new(MenuState {
+                api,
+                view: None,
+                internal,
+                _client: client,
+                layer,
+                items: vec![MenuAction::Maximize, MenuAction::Minimize, MenuAction::Move,
+                            MenuAction::Resize, MenuAction::AlwaysOnTop, MenuAction::Close],
+                window: ptr::null_mut(),
+                seat: ptr::null_mut(),
+                geometry: Geometry { x: 0, y: 0, width: 0, height: 0 },
+                selected: None,
+                armed: false,
+                origin: (0, 0),
+                destroy_listener: None,
+                dead: Vec::new(),
+            }),
+        })
+    }
+
+    pub fn api(&mut self) -> &mut dyn WindowMenuApi<SC> {
+        &mut *self.state.api
+    }
+
+    /// Which entries to show, in order (all of them by default)
+    pub fn set_items(&mut self, items: Vec<MenuAction>) {
+        self.state.close();
+        self.state.items = items;
+    }
+
+    /// Opens the menu for the window at a surface-local position, like `show_window_menu` gives
+    pub fn show(&mut self, surface: &mut DesktopSurfaceRef<SC>, seat: &mut SeatRef, x: i32, y: i32) {
+        let (view_x, view_y) = surface.borrow_user_data().expect("user_data").floating().view().get_position();
+        self.show_at(surface, seat, view_x as i32 + x, view_y as i32 + y);
+    }
+
+    /// Opens the menu for the window at a global position, like `FrameAction::Menu` gives
+    pub fn show_at(&mut self, surface: &mut DesktopSurfaceRef<SC>, seat: &mut SeatRef, x: i32, y: i32) {
+        self.state.open(surface, seat, x, y);
+    }
+
+    pub fn hide(&mut self) {
+        self.state.close();
+    }
+
+    pub fn is_shown(&self) -> bool {
+        !self.state.window.is_null()
+    }
+}
+
+impl<SC> Drop for WindowMenu<SC> {
+    fn drop(&mut self) {
+        // Ends the grabs, which point at the state
+        self.state.close();
+        for listener in self.state.dead.drain(..) {
+            drop(ManuallyDrop::into_inner(listener));
+        }
+        // Dropping a layer doesn't take it out of the compositor's list
+        unsafe { weston_layer_unset_position(self.state.layer.as_ptr()); }
+    }
+}
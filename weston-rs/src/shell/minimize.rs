@@ -0,0 +1,310 @@
+//! Minimizing windows: their views go to a hidden layer until they're restored.
+//!
+//! libweston-desktop has no minimized state, clients only ask for it
+//! (`DesktopApi::minimized_requested`). `MinimizeManager` remembers the layer each minimized
+//! window came from (e.g. its workspace) and puts it back there on `restore`. A keyboard focusing
+//! a minimized window restores it too, so `floating::activate` (from the switcher or a taskbar's
+//! `ForeignToplevelApi::activate_requested`) just works.
+//!
+//! Taskbars learn about it through a `ForeignToplevelManager` given to `report_to`, and
+//! `TilingShell` re-arranges its tiles by itself.
+
+use std::ptr;
+use std::cell::RefCell;
+use std::rc::Rc;
+use libweston_sys::{weston_compositor, weston_layer, weston_layer_entry, weston_seat, weston_view, weston_layer_unset_position};
+use wayland_sys::common::wl_list;
+use foreign_types::ForeignTypeRef;
+use ::compositor::CompositorRef;
+use ::desktop::{DesktopSurfaceRef, ForeignToplevelManager};
+use ::desktop::foreign_toplevel::ManagerState as ForeignToplevelState;
+use ::keyboard::KeyboardRef;
+use ::layer::{Layer, LayerRef, POSITION_HIDDEN, POSITION_NORMAL};
+use ::listener::{WlListener, ListenerList, SeatListeners};
+use ::seat::SeatRef;
+use ::view::ViewRef;
+use super::floating::{ShellSurface, desktop_surface_of};
+use super::focus::{FocusManager, FocusState};
+
+pub trait MinimizeApi<SC> {
+    /// A window got minimized or restored (taskbars given to `report_to` already know)
+    fn minimized_changed(&mut self, _surface: &mut DesktopSurfaceRef<SC>, _minimized: bool) {}
+}
+
+/// For the shell that owns the manager, run after `MinimizeApi::minimized_changed`
+/// (see `FloatingShell::set_minimized_hook`)
+pub(super) type MinimizedHook<SC> = Rc<RefCell<FnMut(&mut DesktopSurfaceRef<SC>, bool)>>;
+
+/// Whether the layer is still in the compositor (e.g. workspaces go away with their output)
+pub(super) fn has_layer(compositor: *mut weston_compositor, layer: *mut weston_layer) -> bool {
+    unsafe {
+        let head = &mut (*compositor).layer_list as *mut wl_list;
+        let mut link = (*head).next;
+        while link != head {
+            if wl_container_of!(link, weston_layer, link) == layer {
+                return true;
+            }
+            link = (*link).next;
+        }
+    }
+    false
+}
+
+/// The views in a layer, top to bottom
+pub(super) fn layer_views(layer: *mut weston_layer) -> Vec<*mut weston_view> {
+    let mut result = Vec::new();
+    unsafe {
+        let head = &mut (*layer).view_list.link as *mut wl_list;
+        let mut link = (*head).next;
+        while link != head {
+            let entry = wl_container_of!(link, weston_layer_entry, link);
+            result.push(wl_container_of!(entry, weston_view, layer_link));
+            link = (*link).next;
+        }
+    }
+    result
+}
+
+/// The topmost normal layer, for windows whose layer is gone
+fn fallback_layer(compositor: *mut weston_compositor) -> Option<*mut weston_layer> {
+    unsafe {
+        let head = &mut (*compositor).layer_list as *mut wl_list;
+        let mut link = (*head).next;
+        while link != head {
+            let layer = wl_container_of!(link, weston_layer, link);
+            if (*layer).position == POSITION_NORMAL {
+                return Some(layer);
+            }
+            link = (*link).next;
+        }
+    }
+    None
+}
+
+/// Whether a minimized window would be shown once restored (not on a hidden workspace)
+pub(super) fn restores_shown<SC: ShellSurface>(dsurf: &DesktopSurfaceRef<SC>) -> bool {
+    let from = match dsurf.borrow_user_data().expect("user_data").floating().minimized_from {
+        Some(from) => from,
+        None => return false,
+    };
+    let compositor = dsurf.surface().compositor().as_ptr();
+    !has_layer(compositor, from) || unsafe { (*from).position } != POSITION_HIDDEN
+}
+
+struct MinimizeState<SC> {
+    api: Box<dyn MinimizeApi<SC>>,
+    compositor: *mut weston_compositor,
+    focus: *mut FocusState<SC>,
+    /// Where minimized views are kept
+    layer: Layer,
+    /// `restore_all`, which needs `SC: ShellSurface` (not there in `Drop`)
+    restore_all: fn(&mut MinimizeState<SC>),
+    /// Null unless given to `report_to`
+    foreign: *mut ForeignToplevelState<SC>,
+    hook: Option<MinimizedHook<SC>>,
+    /// Per seat, the keyboard focus listener is in `devices`
+    seats: Vec<SeatListeners>,
+    /// On the compositor's seat_created_signal
+    listeners: ListenerList,
+    /// Listeners of destroyed seats, one of them was running when they were removed
+    dead: Vec<ListenerList>,
+}
+
+impl<SC: ShellSurface> MinimizeState<SC> {
+    fn add_seat(&mut self, seat: &mut SeatRef) {
+        let state_ptr = self as *mut MinimizeState<SC>;
+        let mut listeners = SeatListeners::new(seat.as_ptr());
+        listeners.own.add(WlListener::new(Box::new(move |seat: &mut SeatRef| {
+            unsafe { (*state_ptr).track_keyboard(seat); }
+        })), seat.updated_caps_signal());
+        listeners.own.add(WlListener::new(Box::new(move |seat: &mut SeatRef| {
+            unsafe { (*state_ptr).seat_destroyed(seat.as_ptr()); }
+        })), seat.destroy_signal());
+        self.seats.push(listeners);
+        self.track_keyboard(seat);
+    }
+
+    fn seat_destroyed(&mut self, seat: *mut weston_seat) {
+        self.dead.clear();
+        if let Some(i) = self.seats.iter().position(|s| s.seat == seat) {
+            let listeners = self.seats.remove(i).destroyed();
+            self.dead.push(listeners);
+        }
+    }
+
+    fn track_keyboard(&mut self, seat: &mut SeatRef) {
+        let state_ptr = self as *mut MinimizeState<SC>;
+        let seat_ptr = seat.as_ptr();
+        let listeners = match self.seats.iter_mut().find(|s| s.seat == seat_ptr) {
+            Some(listeners) => listeners,
+            None => return,
+        };
+        if let (true, Some(keyboard)) = (listeners.devices.is_empty(), seat.keyboard_mut()) {
+            listeners.devices.add(WlListener::new(Box::new(move |k: &mut KeyboardRef| {
+                unsafe { (*state_ptr).keyboard_focused(k); }
+            })), keyboard.focus_signal());
+        }
+    }
+
+    fn changed(&mut self, dsurf: &mut DesktopSurfaceRef<SC>, minimized: bool) {
+        if !self.foreign.is_null() {
+            unsafe { (*self.foreign).set_minimized(dsurf.as_ptr(), minimized); }
+        }
+        self.api.minimized_changed(dsurf, minimized);
+        if let Some(ref hook) = self.hook {
+            (&mut *hook.borrow_mut())(dsurf, minimized);
+        }
+    }
+
+    fn keyboard_focused(&mut self, keyboard: &KeyboardRef) {
+        let window = keyboard.focus()
+            .and_then(|surface| DesktopSurfaceRef::<SC>::from_surface(surface.main_surface()))
+            .filter(|dsurf| dsurf.borrow_user_data().map(|sctx| sctx.floating().is_minimized()).unwrap_or(false));
+        if let Some(dsurf) = window {
+            self.restore(dsurf);
+        }
+    }
+
+    fn minimize(&mut self, dsurf: &mut DesktopSurfaceRef<SC>) {
+        {
+            let floating = dsurf.borrow_user_data().expect("user_data").floating();
+            let from = floating.view().layer_link().layer;
+            // Not mapped yet, or already minimized
+            if from.is_null() || floating.is_minimized() {
+                return;
+            }
+            floating.minimized_from = Some(from);
+            let view = floating.view_mut();
+            view.damage_below();
+            view.layer_entry_remove();
+            self.layer.view_list_entry_insert(view);
+            view.geometry_dirty();
+        }
+        dsurf.propagate_layer();
+        unsafe { CompositorRef::from_ptr_mut(self.compositor) }.schedule_repaint();
+        unsafe { (*self.focus).refocus_hidden(); }
+        self.changed(dsurf, true);
+    }
+
+    fn restore(&mut self, dsurf: &mut DesktopSurfaceRef<SC>) {
+        {
+            let floating = dsurf.borrow_user_data().expect("user_data").floating();
+            let from = match floating.minimized_from.take() {
+                Some(from) => from,
+                None => return,
+            };
+            let layer = Some(from).filter(|&l| has_layer(self.compositor, l)).or_else(|| fallback_layer(self.compositor));
+            let view = floating.view_mut();
+            view.layer_entry_remove();
+            if let Some(layer) = layer {
+                // On top, like a newly activated window
+                unsafe { LayerRef::from_ptr_mut(layer) }.view_list_entry_insert(view);
+            }
+            view.geometry_dirty();
+        }
+        dsurf.propagate_layer();
+        dsurf.surface_mut().damage();
+        unsafe { CompositorRef::from_ptr_mut(self.compositor) }.schedule_repaint();
+        self.changed(dsurf, false);
+    }
+
+    /// Puts every minimized window back, before the hidden layer goes away
+    fn restore_all(&mut self) {
+        for view in layer_views(self.layer.as_ptr()) {
+            let view = unsafe { ViewRef::from_ptr_mut(view) };
+            if let Some(dsurf) = desktop_surface_of::<SC>(view) {
+                self.restore(dsurf);
+            }
+            // Not one of ours after all
+            if view.layer_link().layer == self.layer.as_ptr() {
+                view.layer_entry_remove();
+            }
+        }
+    }
+}
+
+pub struct MinimizeManager<SC> {
+    state: Box<MinimizeState<SC>>,
+}
+
+impl<SC: ShellSurface> MinimizeManager<SC> {
+    /// Minimizing a focused window gives the focus to the previous one (see `FocusManager`).
+    ///
+    /// The focus manager must outlive this one (it normally lives as long as the shell).
+    pub fn new(compositor: &mut CompositorRef, focus: &mut FocusManager<SC>, api: Box<dyn MinimizeApi<SC>>) -> MinimizeManager<SC> {
+        let mut layer = Layer::new(compositor);
+        layer.set_position(POSITION_HIDDEN);
+        let mut state = Box::new(MinimizeState {
+            api,
+            compositor: compositor.as_ptr(),
+            focus: focus.state_ptr_mut(),
+            layer,
+            restore_all: MinimizeState::<SC>::restore_all,
+            foreign: ptr::null_mut(),
+            hook: None,
+            seats: Vec::new(),
+            listeners: ListenerList::new(),
+            dead: Vec::new(),
+        });
+        for seat in compositor.iterate_seats() {
+            state.add_seat(seat);
+        }
+        let state_ptr = &mut *state as *mut MinimizeState<SC>;
+        state.listeners.add(WlListener::new(Box::new(move |seat: &mut SeatRef| {
+            unsafe { (*state_ptr).add_seat(seat); }
+        })), compositor.seat_created_signal());
+        MinimizeManager { state }
+    }
+
+    pub fn api(&mut self) -> &mut dyn MinimizeApi<SC> {
+        &mut *self.state.api
+    }
+
+    /// Keeps the taskbars' minimized state (`ForeignToplevelManager::set_minimized`) up to date.
+    ///
+    /// That manager must outlive this one, or be taken back with `None` first.
+    pub fn report_to(&mut self, foreign: Option<&mut ForeignToplevelManager<SC>>) {
+        self.state.foreign = foreign.map(|f| f.state_ptr_mut()).unwrap_or(ptr::null_mut());
+    }
+
+    pub(super) fn set_hook(&mut self, hook: Option<MinimizedHook<SC>>) {
+        self.state.hook = hook;
+    }
+
+    /// Hides the window until it's restored or focused
+    pub fn minimize(&mut self, dsurf: &mut DesktopSurfaceRef<SC>) {
+        self.state.minimize(dsurf);
+    }
+
+    /// Puts the window back on top of the layer it was minimized from, without focusing it
+    pub fn restore(&mut self, dsurf: &mut DesktopSurfaceRef<SC>) {
+        self.state.restore(dsurf);
+    }
+
+    /// For `ForeignToplevelApi::minimize_requested`
+    pub fn set_minimized(&mut self, dsurf: &mut DesktopSurfaceRef<SC>, minimized: bool) {
+        if minimized {
+            self.minimize(dsurf);
+        } else {
+            self.restore(dsurf);
+        }
+    }
+
+    pub fn is_minimized(&self, dsurf: &DesktopSurfaceRef<SC>) -> bool {
+        dsurf.borrow_user_data().map(|sctx| sctx.floating().is_minimized()).unwrap_or(false)
+    }
+}
+
+impl<SC> Drop for MinimizeManager<SC> {
+    fn drop(&mut self) {
+        // Views still in the hidden layer would point into freed memory
+        let restore_all = self.state.restore_all;
+        restore_all(&mut *self.state);
+        // Unlinks the listeners, they point at the state
+        self.state.seats.clear();
+        self.state.listeners.detach();
+        self.state.dead.clear();
+        // Dropping a layer doesn't take it out of the compositor's list
+        unsafe { weston_layer_unset_position(self.state.layer.as_ptr()); }
+    }
+}
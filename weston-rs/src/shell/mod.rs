@@ -0,0 +1,14 @@
+//! Window management building blocks on top of `desktop`.
+//!
+//! These are `DesktopApi` implementations (and the helpers they're made of) that a compositor can
+//! use as is, or delegate to from its own `DesktopApi`.
+
+pub mod floating;
+pub mod focus;
+pub mod keyboard;
+pub mod menu;
+pub mod minimize;
+pub mod snap;
+pub mod switcher;
+pub mod tiling;
+pub mod workspaces;
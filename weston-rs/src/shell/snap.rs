@@ -0,0 +1,319 @@
+//! Moving and resizing that sticks to output and window edges, with drag-to-edge tiling.
+//!
+//! `start_move` and `start_resize` are drop-in replacements for the `floating` ones, to be used
+//! from `DesktopApi::moove`/`resize` (`FloatingShell` does when its config has a `SnapConfig`).
+//! Dragging a window with the pointer against the left or right side of an output tiles it to that
+//! half, corners give quarters and the top edge gives the whole work area. A translucent rectangle
+//! shows where the window will go. Dragging a tiled window away gives it its previous size back.
+
+use libweston_sys::{weston_layer_entry, weston_view};
+use wayland_server::protocol::wl_shell_surface::Resize;
+use foreign_types::ForeignTypeRef;
+use ::Geometry;
+use ::desktop::DesktopSurfaceRef;
+use ::pointer::PointerRef;
+use ::surface::Surface;
+use ::view::{View, ViewRef};
+use super::floating::{self, ShellSurface, is_shown, desktop_surface_of};
+
+#[derive(Builder, Clone)]
+pub struct SnapConfig {
+    /// How close (in pixels) edges have to get to stick together
+    #[builder(default = "16")]
+    threshold: i32,
+    /// Stick to the edges of the outputs' work areas
+    #[builder(default = "true")]
+    to_outputs: bool,
+    /// Stick to the edges of other windows
+    #[builder(default = "true")]
+    to_windows: bool,
+    /// Tile windows dragged against the sides of an output
+    #[builder(default = "true")]
+    edge_tiling: bool,
+    /// RGBA of the tiling preview
+    #[builder(default = "(0.3, 0.5, 0.9, 0.35)")]
+    preview_color: (f32, f32, f32, f32),
+}
+
+/// The rectangle showing where a window will be tiled
+struct Preview {
+    // Declared first to be destroyed before the surface
+    view: View,
+    surface: Surface,
+}
+
+/// Snapping state of a move or resize grab
+pub(super) struct Snapper {
+    config: SnapConfig,
+    zone: Option<Geometry>,
+    preview: Option<Preview>,
+}
+
+/// Where the window is on screen, without client-side shadows
+fn window_rect<SC: ShellSurface>(dsurf: &DesktopSurfaceRef<SC>) -> Geometry {
+    let geometry = dsurf.get_geometry();
+    let (x, y) = dsurf.borrow_user_data().expect("user_data").floating().view().get_position();
+    Geometry { x: x as i32 + geometry.x, y: y as i32 + geometry.y, ..geometry }
+}
+
+/// Other windows shown in the same layer
+fn neighbours<SC: ShellSurface>(dsurf: &DesktopSurfaceRef<SC>) -> Vec<Geometry> {
+    let view = dsurf.borrow_user_data().expect("user_data").floating().view();
+    let mut result = Vec::new();
+    unsafe {
+        let layer = view.layer_link().layer;
+        if layer.is_null() {
+            return result;
+        }
+        let head = &mut (*layer).view_list.link as *mut _;
+        let mut link = (*head).next;
+        while link != head {
+            let entry = wl_container_of!(link, weston_layer_entry, link);
+            let other = ViewRef::from_ptr(wl_container_of!(entry, weston_view, layer_link));
+            if let Some(other_dsurf) = desktop_surface_of::<SC>(other) {
+                if other_dsurf.as_ptr() != dsurf.as_ptr() && is_shown(other) {
+                    result.push(window_rect(other_dsurf));
+                }
+            }
+            link = (*link).next;
+        }
+    }
+    result
+}
+
+/// Whether two spans overlap (or almost)
+fn spans_meet(a: i32, a_len: i32, b: i32, b_len: i32, slack: i32) -> bool {
+    a <= b + b_len + slack && b <= a + a_len + slack
+}
+
+/// The smallest shift (within the threshold) that puts one of the edges on a target
+fn snap_delta(edges: &[i32], targets: &[i32], threshold: i32) -> Option<i32> {
+    edges.iter()
+        .flat_map(|&edge| targets.iter().map(move |&target| target - edge))
+        .filter(|delta| delta.abs() <= threshold)
+        .min_by_key(|delta| delta.abs())
+}
+
+/// The part of the work area for a pointer within `threshold` of the output's edges.
+/// Corners give quarters, the left and right sides halves and the top side the whole area.
+fn edge_zone(px: i32, py: i32, output: Geometry, area: Geometry, threshold: i32) -> Option<Geometry> {
+    let left = px < output.x + threshold;
+    let right = px >= output.x + output.width - threshold;
+    let top = py < output.y + threshold;
+    let bottom = py >= output.y + output.height - threshold;
+    let (half_width, half_height) = (area.width / 2, area.height / 2);
+    let left_half = Geometry { width: half_width, ..area };
+    let right_half = Geometry { x: area.x + half_width, width: area.width - half_width, ..area };
+    let top_of = |half: Geometry| Geometry { height: half_height, ..half };
+    let bottom_of = |half: Geometry| Geometry { y: area.y + half_height, height: area.height - half_height, ..half };
+    match (left, right, top, bottom) {
+        (true, _, true, _) => Some(top_of(left_half)),
+        (true, _, _, true) => Some(bottom_of(left_half)),
+        (true, _, _, _) => Some(left_half),
+        (_, true, true, _) => Some(top_of(right_half)),
+        (_, true, _, true) => Some(bottom_of(right_half)),
+        (_, true, _, _) => Some(right_half),
+        (_, _, true, _) => Some(area),
+        _ => None,
+    }
+}
+
+impl Snapper {
+    pub(super) fn new(config: SnapConfig) -> Snapper {
+        Snapper { config, zone: None, preview: None }
+    }
+
+    /// Edges to stick to, vertical ones then horizontal ones
+    fn targets<SC: ShellSurface>(&self, dsurf: &DesktopSurfaceRef<SC>, rect: Geometry) -> (Vec<i32>, Vec<i32>) {
+        let (mut xs, mut ys) = (Vec::new(), Vec::new());
+        if self.config.to_outputs {
+            for output in dsurf.surface().compositor().iterate_outputs() {
+                let area = output.work_area();
+                xs.extend_from_slice(&[area.x, area.x + area.width]);
+                ys.extend_from_slice(&[area.y, area.y + area.height]);
+            }
+        }
+        if self.config.to_windows {
+            let slack = self.config.threshold;
+            for other in neighbours(dsurf) {
+                // Only windows that are side by side (or stacked) with this one
+                if spans_meet(rect.y, rect.height, other.y, other.height, slack) {
+                    xs.extend_from_slice(&[other.x, other.x + other.width]);
+                }
+                if spans_meet(rect.x, rect.width, other.x, other.width, slack) {
+                    ys.extend_from_slice(&[other.y, other.y + other.height]);
+                }
+            }
+        }
+        (xs, ys)
+    }
+
+    /// Adjusts the view position of a dragged window so that its edges stick to nearby ones,
+    /// and updates the tiling zone under the pointer
+    pub(super) fn moving<SC: ShellSurface>(&mut self, dsurf: &DesktopSurfaceRef<SC>,
+                                           pointer: &PointerRef, x: f32, y: f32) -> (f32, f32) {
+        let geometry = dsurf.get_geometry();
+        let rect = Geometry { x: x as i32 + geometry.x, y: y as i32 + geometry.y, ..geometry };
+        let (xs, ys) = self.targets(dsurf, rect);
+        let threshold = self.config.threshold;
+        let dx = snap_delta(&[rect.x, rect.x + rect.width], &xs, threshold).unwrap_or(0);
+        let dy = snap_delta(&[rect.y, rect.y + rect.height], &ys, threshold).unwrap_or(0);
+        if self.config.edge_tiling {
+            let zone = self.zone_at(dsurf, pointer);
+            self.set_zone(dsurf, zone);
+        }
+        (x + dx as f32, y + dy as f32)
+    }
+
+    /// Adjusts the size of a window being resized so that the moving edges stick to nearby ones
+    pub(super) fn resizing<SC: ShellSurface>(&mut self, dsurf: &DesktopSurfaceRef<SC>,
+                                             edges: Resize, width: i32, height: i32) -> (i32, i32) {
+        let current = window_rect(dsurf);
+        // The edges that don't move stay where they are
+        let x = if edges.contains(Resize::Left) { current.x + current.width - width } else { current.x };
+        let y = if edges.contains(Resize::Top) { current.y + current.height - height } else { current.y };
+        let rect = Geometry { x, y, width, height };
+        let (xs, ys) = self.targets(dsurf, rect);
+        let threshold = self.config.threshold;
+        let mut width = width;
+        if edges.contains(Resize::Left) {
+            width -= snap_delta(&[rect.x], &xs, threshold).unwrap_or(0);
+        } else if edges.contains(Resize::Right) {
+            width += snap_delta(&[rect.x + rect.width], &xs, threshold).unwrap_or(0);
+        }
+        let mut height = height;
+        if edges.contains(Resize::Top) {
+            height -= snap_delta(&[rect.y], &ys, threshold).unwrap_or(0);
+        } else if edges.contains(Resize::Bottom) {
+            height += snap_delta(&[rect.y + rect.height], &ys, threshold).unwrap_or(0);
+        }
+        floating::clamp_size(dsurf, width, height)
+    }
+
+    /// The part of the work area the window would be tiled to if dropped now
+    fn zone_at<SC: ShellSurface>(&self, dsurf: &DesktopSurfaceRef<SC>, pointer: &PointerRef) -> Option<Geometry> {
+        let (px, py) = (::wl_fixed_to_int(pointer.x()), ::wl_fixed_to_int(pointer.y()));
+        let output = dsurf.surface().compositor().iterate_outputs().find(|output| {
+            let geometry = output.geometry();
+            px >= geometry.x && px < geometry.x + geometry.width && py >= geometry.y && py < geometry.y + geometry.height
+        })?;
+        edge_zone(px, py, output.geometry(), output.work_area(), self.config.threshold)
+    }
+
+    fn set_zone<SC: ShellSurface>(&mut self, dsurf: &DesktopSurfaceRef<SC>, zone: Option<Geometry>) {
+        // Geometry isn't PartialEq
+        let key = |zone: Option<Geometry>| zone.map(|z| (z.x, z.y, z.width, z.height));
+        if key(zone) == key(self.zone) {
+            return;
+        }
+        self.zone = zone;
+        let zone = match zone {
+            Some(zone) => zone,
+            None => {
+                self.preview = None;
+                dsurf.surface().compositor_mut().schedule_repaint();
+                return;
+            },
+        };
+        if self.preview.is_none() {
+            let mut surface = Surface::new(dsurf.surface().compositor());
+            let (r, g, b, a) = self.config.preview_color;
+            surface.set_color(r, g, b, a);
+            let mut view = View::new(&surface);
+            // Right under the window being dragged
+            view.place_below(dsurf.borrow_user_data().expect("user_data").floating().view());
+            self.preview = Some(Preview { view, surface });
+        }
+        if let Some(ref mut preview) = self.preview {
+            preview.surface.set_size(zone.width, zone.height);
+            preview.view.set_position(zone.x as f32, zone.y as f32);
+            preview.view.geometry_dirty();
+            preview.surface.damage();
+        }
+    }
+
+    /// The zone to tile the window to, if any. Removes the preview.
+    pub(super) fn finish(&mut self) -> Option<Geometry> {
+        self.preview = None;
+        self.zone.take()
+    }
+}
+
+/// Like `floating::start_move`, with snapping
+pub fn start_move<SC: ShellSurface>(dsurf: &mut DesktopSurfaceRef<SC>, pointer: &mut PointerRef, config: &SnapConfig) {
+    floating::start_move_with(dsurf, pointer, Some(Snapper::new(config.clone())));
+}
+
+/// Like `floating::start_resize`, with snapping
+pub fn start_resize<SC: ShellSurface>(dsurf: &mut DesktopSurfaceRef<SC>, pointer: &mut PointerRef, edges: Resize, config: &SnapConfig) {
+    floating::start_resize_with(dsurf, pointer, edges, Some(Snapper::new(config.clone())));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUTPUT: Geometry = Geometry { x: 0, y: 0, width: 1000, height: 800 };
+    // A 30 pixel panel at the top
+    const AREA: Geometry = Geometry { x: 0, y: 30, width: 1000, height: 770 };
+
+    fn zone(px: i32, py: i32) -> Option<(i32, i32, i32, i32)> {
+        edge_zone(px, py, OUTPUT, AREA, 16).map(|z| (z.x, z.y, z.width, z.height))
+    }
+
+    #[test]
+    fn snap_delta_picks_the_closest_edge() {
+        assert_eq!(snap_delta(&[0, 100], &[110], 16), Some(10));
+        assert_eq!(snap_delta(&[50], &[40], 16), Some(-10));
+        assert_eq!(snap_delta(&[0, 100], &[-5, 103], 16), Some(3));
+        assert_eq!(snap_delta(&[0, 100], &[], 16), None);
+    }
+
+    #[test]
+    fn snap_delta_threshold() {
+        assert_eq!(snap_delta(&[0], &[16], 16), Some(16));
+        assert_eq!(snap_delta(&[0], &[-16], 16), Some(-16));
+        assert_eq!(snap_delta(&[0], &[17], 16), None);
+        assert_eq!(snap_delta(&[0], &[5], 0), None);
+        assert_eq!(snap_delta(&[5], &[5], 0), Some(0));
+    }
+
+    #[test]
+    fn sides() {
+        assert_eq!(zone(0, 400), Some((0, 30, 500, 770)));
+        assert_eq!(zone(999, 400), Some((500, 30, 500, 770)));
+        assert_eq!(zone(500, 0), Some((0, 30, 1000, 770)));
+        // The bottom side alone doesn't tile
+        assert_eq!(zone(500, 799), None);
+        assert_eq!(zone(500, 400), None);
+    }
+
+    #[test]
+    fn side_thresholds() {
+        assert_eq!(zone(15, 400), Some((0, 30, 500, 770)));
+        assert_eq!(zone(16, 400), None);
+        assert_eq!(zone(984, 400), Some((500, 30, 500, 770)));
+        assert_eq!(zone(983, 400), None);
+        assert_eq!(zone(500, 15), Some((0, 30, 1000, 770)));
+        assert_eq!(zone(500, 16), None);
+    }
+
+    #[test]
+    fn corners_win_over_sides() {
+        assert_eq!(zone(5, 5), Some((0, 30, 500, 385)));
+        assert_eq!(zone(5, 790), Some((0, 415, 500, 385)));
+        assert_eq!(zone(995, 5), Some((500, 30, 500, 385)));
+        assert_eq!(zone(995, 790), Some((500, 415, 500, 385)));
+        // Near the bottom, but not near enough for a corner
+        assert_eq!(zone(5, 783), Some((0, 30, 500, 770)));
+    }
+
+    #[test]
+    fn odd_sizes_leave_no_gap() {
+        let area = Geometry { x: 10, y: 0, width: 1001, height: 801 };
+        let left = edge_zone(0, 0, OUTPUT, area, 16).expect("corner");
+        let right = edge_zone(999, 799, OUTPUT, area, 16).expect("corner");
+        assert_eq!((left.x, left.y, left.width, left.height), (10, 0, 500, 400));
+        assert_eq!((right.x, right.y, right.width, right.height), (510, 400, 501, 401));
+    }
+}
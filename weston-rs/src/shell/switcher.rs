@@ -0,0 +1,201 @@
+//! Alt-Tab: cycling through windows in most recently used order.
+//!
+//! The order comes from the seat's focus history (see `focus`), not from the stacking. While the
+//! modifier is held, each press of the key raises the next window as a preview (with Shift, the
+//! previous one). Releasing the modifier focuses the selected window, Escape puts the stacking
+//! back the way it was.
+
+use libc;
+use libweston_sys::{weston_seat, weston_desktop_surface, weston_layer_entry, weston_view};
+use foreign_types::ForeignTypeRef;
+use ::ev;
+use ::compositor::CompositorRef;
+use ::desktop::DesktopSurfaceRef;
+use ::keyboard::{KeyboardRef, KeyboardGrab, KeyboardModifier, KeyState};
+use ::layer::LayerRef;
+use ::seat::SeatRef;
+use ::view::{ViewRef, ActivateFlag};
+use super::floating::{ShellSurface, activate, raise, is_shown, desktop_surface_of};
+use super::focus::{FocusManager, FocusState};
+use super::minimize::restores_shown;
+
+/// Keyboard handler for switching windows
+struct SwitcherGrab<SC: 'static> {
+    focus: *const FocusState<SC>,
+    seat: *mut weston_seat,
+    key: u32,
+    modifier: KeyboardModifier,
+    windows: Vec<*mut weston_desktop_surface>,
+    selected: usize,
+    /// Previewed windows with the window that was right above each (`None` when on top),
+    /// to restore the stacking
+    raised: Vec<(*mut weston_desktop_surface, Option<*mut weston_desktop_surface>)>,
+}
+
+fn view_of<SC: ShellSurface>(window: *mut weston_desktop_surface) -> &'static mut ViewRef {
+    let dsurf = unsafe { DesktopSurfaceRef::<SC>::from_ptr(window) };
+    let view = dsurf.borrow_user_data().expect("user_data").floating().view_mut();
+    unsafe { ViewRef::from_ptr_mut(view.as_ptr()) }
+}
+
+/// The closest window stacked above the view in its layer
+fn window_above<SC: ShellSurface>(view: &ViewRef) -> Option<*mut weston_desktop_surface> {
+    unsafe {
+        let layer = view.layer_link().layer;
+        if layer.is_null() {
+            return None;
+        }
+        let head = &mut (*layer).view_list.link as *mut _;
+        let mut link = view.layer_link().link.prev;
+        while link != head {
+            let entry = wl_container_of!(link, weston_layer_entry, link);
+            let above = ViewRef::from_ptr(wl_container_of!(entry, weston_view, layer_link));
+            if let Some(dsurf) = desktop_surface_of::<SC>(above) {
+                return Some(dsurf.as_ptr());
+            }
+            link = (*link).prev;
+        }
+    }
+    None
+}
+
+impl<SC: ShellSurface> SwitcherGrab<SC> {
+    /// Windows can go away during the switch, the ones still in the history are alive
+    fn is_alive(&self, window: *mut weston_desktop_surface) -> bool {
+        unsafe { (*self.focus).history(self.seat).contains(&window) }
+    }
+
+    fn step(&mut self, backwards: bool) {
+        let count = self.windows.len();
+        for _ in 0..count {
+            self.selected = if backwards { (self.selected + count - 1) % count } else { (self.selected + 1) % count };
+            let window = self.windows[self.selected];
+            if self.is_alive(window) {
+                self.preview(window);
+                return;
+            }
+        }
+    }
+
+    fn preview(&mut self, window: *mut weston_desktop_surface) {
+        let view = view_of::<SC>(window);
+        if !self.raised.iter().any(|&(w, _)| w == window) {
+            self.raised.push((window, window_above::<SC>(view)));
+        }
+        raise::<SC>(view);
+        view.surface_mut().compositor_mut().schedule_repaint();
+    }
+
+    /// Undoes the previews, latest first so that every window goes back between the same neighbours
+    fn restore(&mut self) {
+        while let Some((window, above)) = self.raised.pop() {
+            if !self.is_alive(window) {
+                continue;
+            }
+            let view = view_of::<SC>(window);
+            if view.layer_link().layer.is_null() {
+                continue;
+            }
+            match above {
+                Some(above) if self.is_alive(above) => view.place_below(view_of::<SC>(above)),
+                Some(_) => continue,
+                None => {
+                    let layer = unsafe { LayerRef::from_ptr_mut(view.layer_link().layer) };
+                    view.layer_entry_remove();
+                    layer.view_list_entry_insert(view);
+                },
+            }
+            let dsurf = unsafe { DesktopSurfaceRef::<SC>::from_ptr(window) };
+            dsurf.propagate_layer();
+            view.geometry_dirty();
+            view.surface_mut().damage();
+        }
+    }
+
+    fn finish(&mut self, keyboard: &mut KeyboardRef, commit: bool) {
+        self.restore();
+        let window = self.windows[self.selected];
+        if commit && self.is_alive(window) {
+            let seat = unsafe { SeatRef::from_ptr(self.seat) };
+            activate::<SC>(view_of::<SC>(window), seat, ActivateFlag::CONFIGURE);
+        }
+        keyboard.end_grab();
+    }
+}
+
+impl<SC: ShellSurface> KeyboardGrab for SwitcherGrab<SC> {
+    fn key(&mut self, keyboard: &mut KeyboardRef, _time: &libc::timespec, key: u32, state: KeyState) {
+        if state != KeyState::Pressed {
+            return;
+        }
+        if key == self.key {
+            let backwards = keyboard.seat().modifier_state().contains(KeyboardModifier::SHIFT);
+            self.step(backwards);
+        } else if key == ev::KEY_ESC {
+            self.finish(keyboard, false);
+        }
+    }
+
+    fn modifiers(&mut self, keyboard: &mut KeyboardRef, _serial: u32,
+                 _mods_depressed: KeyboardModifier, _mods_latched: KeyboardModifier,
+                 _mods_locked: KeyboardModifier, _group: u32) {
+        // The arguments are xkb masks, the seat has the weston ones
+        if !keyboard.seat().modifier_state().contains(self.modifier) {
+            self.finish(keyboard, true);
+        }
+    }
+
+    fn cancel(&mut self, keyboard: &mut KeyboardRef) {
+        self.finish(keyboard, false);
+    }
+}
+
+/// Starts switching on the keyboard's seat, selecting the window after the focused one.
+/// Does nothing when there's no other window to switch to.
+pub fn start<SC: ShellSurface>(focus: &FocusManager<SC>, keyboard: &mut KeyboardRef, key: u32, modifier: KeyboardModifier) {
+    start_with(focus.state_ptr(), keyboard, key, modifier);
+}
+
+fn start_with<SC: ShellSurface>(focus: *const FocusState<SC>, keyboard: &mut KeyboardRef, key: u32, modifier: KeyboardModifier) {
+    if !keyboard.is_default_grab() {
+        return;
+    }
+    let seat = keyboard.seat().as_ptr();
+    let windows = unsafe { (*focus).history(seat) }.iter().cloned()
+        // Minimized windows too, switching to one restores it
+        .filter(|&w| is_shown(view_of::<SC>(w)) || restores_shown(unsafe { DesktopSurfaceRef::<SC>::from_ptr(w) }))
+        .collect::<Vec<_>>();
+    let focused = keyboard.focus().map(|s| s.main_surface().as_ptr());
+    let current = match windows.first() {
+        Some(&w) if Some(unsafe { DesktopSurfaceRef::<SC>::from_ptr(w) }.surface().as_ptr()) == focused => 0,
+        // Nothing of ours is focused, the first step goes to the most recent window
+        Some(_) => windows.len() - 1,
+        None => return,
+    };
+    if windows.len() < 2 && current == 0 {
+        return;
+    }
+    let mut grab = SwitcherGrab {
+        focus,
+        seat,
+        key,
+        modifier,
+        windows,
+        selected: current,
+        raised: Vec::new(),
+    };
+    grab.step(keyboard.seat().modifier_state().contains(KeyboardModifier::SHIFT));
+    keyboard.start_grab(grab);
+}
+
+/// Binds the key (with and without Shift) while holding the modifier to `start`.
+///
+/// The focus manager must outlive the bindings (it normally lives as long as the shell).
+pub fn bind<SC: ShellSurface>(compositor: &mut CompositorRef, focus: &FocusManager<SC>, key: u32, modifier: KeyboardModifier) {
+    let focus = focus.state_ptr();
+    let handler: &'static _ = Box::leak(Box::new(move |keyboard: &mut KeyboardRef, _time: &libc::timespec, key: u32| {
+        start_with(focus, keyboard, key, modifier);
+    }));
+    compositor.add_key_binding(key, modifier, handler);
+    compositor.add_key_binding(key, modifier | KeyboardModifier::SHIFT, handler);
+}
@@ -0,0 +1,561 @@
+//! Tiling: windows on an output are laid out next to each other instead of stacked.
+//!
+//! `TilingShell` wraps `FloatingShell`. New toplevels are tiled on their first commit, on the
+//! output under the pointer; dialogs (windows with a parent), fixed-size windows and anything
+//! `set_floating` float as usual. Each output has its own list of windows and current layout,
+//! the first window in the list is the "master" one.
+
+use std::{any, cmp, marker};
+use std::cell::RefCell;
+use std::rc::Rc;
+use libweston_sys::{weston_output, weston_desktop_surface};
+use wayland_server::protocol::wl_shell_surface::Resize;
+use foreign_types::ForeignTypeRef;
+use ::{Geometry, wl_fixed_to_int};
+use ::compositor::CompositorRef;
+use ::desktop::{DesktopApi, DesktopSurfaceRef, DesktopSurfaceRole};
+use ::listener::{WlListener, ListenerList};
+use ::output::OutputRef;
+use ::seat::SeatRef;
+use super::floating::{FloatingShell, FloatingConfig, ShellSurface, place, is_shown};
+use super::minimize::MinimizedHook;
+
+/// A way of dividing an area between windows
+pub trait Layout {
+    fn name(&self) -> &str;
+
+    /// One rectangle per window, in window order. Gaps are added afterwards, so the
+    /// rectangles should fill the area without overlapping (except for stacking layouts).
+    fn arrange(&self, area: Geometry, count: usize) -> Vec<Geometry>;
+}
+
+/// Splits `length` into `count` parts that differ by at most a pixel, as (offset, length) pairs
+fn split(start: i32, length: i32, count: usize) -> Vec<(i32, i32)> {
+    let count = cmp::max(count, 1) as i32;
+    (0..count).map(|i| {
+        let from = start + length * i / count;
+        let to = start + length * (i + 1) / count;
+        (from, to - from)
+    }).collect()
+}
+
+fn rows(area: Geometry, count: usize) -> Vec<Geometry> {
+    split(area.y, area.height, count).into_iter()
+        .map(|(y, height)| Geometry { x: area.x, y, width: area.width, height })
+        .take(count).collect()
+}
+
+/// One or more master windows on the left, the rest stacked on the right
+#[derive(Debug, Clone)]
+pub struct MasterStack {
+    pub masters: usize,
+    /// Fraction of the width taken by the master column
+    pub ratio: f32,
+}
+
+impl Default for MasterStack {
+    fn default() -> MasterStack {
+        MasterStack { masters: 1, ratio: 0.55 }
+    }
+}
+
+impl Layout for MasterStack {
+    fn name(&self) -> &str {
+        "master-stack"
+    }
+
+    fn arrange(&self, area: Geometry, count: usize) -> Vec<Geometry> {
+        let masters = cmp::min(self.masters, count);
+        if masters == 0 || masters == count {
+            return rows(area, count);
+        }
+        // Both columns keep at least a pixel
+        let master_width = (area.width as f32 * self.ratio.max(0.0).min(1.0)) as i32;
+        let master_width = cmp::max(1, cmp::min(area.width - 1, master_width));
+        let mut result = rows(Geometry { width: master_width, ..area }, masters);
+        result.extend(rows(Geometry { x: area.x + master_width, width: area.width - master_width, ..area }, count - masters));
+        result
+    }
+}
+
+/// Equal width columns
+#[derive(Debug, Clone, Default)]
+pub struct Columns;
+
+impl Layout for Columns {
+    fn name(&self) -> &str {
+        "columns"
+    }
+
+    fn arrange(&self, area: Geometry, count: usize) -> Vec<Geometry> {
+        split(area.x, area.width, count).into_iter()
+            .map(|(x, width)| Geometry { x, y: area.y, width, height: area.height })
+            .take(count).collect()
+    }
+}
+
+/// Every window takes the whole area, the one on top is the one you see
+#[derive(Debug, Clone, Default)]
+pub struct Monocle;
+
+impl Layout for Monocle {
+    fn name(&self) -> &str {
+        "monocle"
+    }
+
+    fn arrange(&self, area: Geometry, count: usize) -> Vec<Geometry> {
+        vec![area; count]
+    }
+}
+
+#[derive(Builder)]
+pub struct TilingConfig {
+    /// Between windows and the edges of the work area
+    #[builder(default)]
+    outer_gap: i32,
+    /// Between neighbouring windows
+    #[builder(default)]
+    inner_gap: i32,
+    /// Whether new toplevels get tiled at all (`set_floating` works either way)
+    #[builder(default = "true")]
+    tile_new_windows: bool,
+}
+
+fn inset(area: Geometry, by: i32) -> Geometry {
+    Geometry {
+        x: area.x + by,
+        y: area.y + by,
+        width: cmp::max(1, area.width - 2 * by),
+        height: cmp::max(1, area.height - 2 * by),
+    }
+}
+
+/// Half a gap on every side that's not on the border of the area
+fn apply_gaps(rect: Geometry, area: Geometry, gap: i32) -> Geometry {
+    let half = gap / 2;
+    let left = if rect.x > area.x { half } else { 0 };
+    let top = if rect.y > area.y { half } else { 0 };
+    let right = if rect.x + rect.width < area.x + area.width { gap - half } else { 0 };
+    let bottom = if rect.y + rect.height < area.y + area.height { gap - half } else { 0 };
+    Geometry {
+        x: rect.x + left,
+        y: rect.y + top,
+        width: cmp::max(1, rect.width - left - right),
+        height: cmp::max(1, rect.height - top - bottom),
+    }
+}
+
+struct OutputTiles {
+    output: *mut weston_output,
+    windows: Vec<*mut weston_desktop_surface>,
+    layout: usize,
+    /// On the output's destroy and work area signals
+    listeners: ListenerList,
+}
+
+struct TilingState {
+    config: TilingConfig,
+    layouts: Vec<Box<dyn Layout>>,
+    outputs: Vec<OutputTiles>,
+    /// Added but not committed yet, so there's nothing to decide on
+    pending: Vec<*mut weston_desktop_surface>,
+    /// Listeners of unplugged outputs, one of them was running when they were removed
+    dead: Vec<ListenerList>,
+}
+
+impl TilingState {
+    fn output_of(&self, surface: *mut weston_desktop_surface) -> Option<*mut weston_output> {
+        self.outputs.iter().find(|t| t.windows.contains(&surface)).map(|t| t.output)
+    }
+
+    fn tiles<SC: ShellSurface>(&mut self, output: *mut weston_output) -> &mut OutputTiles {
+        if let Some(i) = self.outputs.iter().position(|t| t.output == output) {
+            return &mut self.outputs[i];
+        }
+        let listeners = self.watch_output::<SC>(output);
+        self.outputs.push(OutputTiles { output, windows: Vec::new(), layout: 0, listeners });
+        self.outputs.last_mut().expect("just pushed")
+    }
+
+    fn watch_output<SC: ShellSurface>(&mut self, output: *mut weston_output) -> ListenerList {
+        let state = self as *mut TilingState;
+        let output = unsafe { OutputRef::from_ptr_mut(output) };
+        let mut listeners = ListenerList::new();
+        listeners.add(WlListener::new(Box::new(move |output: &mut OutputRef| {
+            unsafe { (*state).arrange::<SC>(output.as_ptr()); }
+        })), output.work_area_changed_signal());
+        listeners.add(WlListener::new(Box::new(move |output: &mut OutputRef| {
+            unsafe { (*state).output_destroyed::<SC>(output.as_ptr()); }
+        })), output.destroy_signal());
+        listeners
+    }
+
+    /// The windows of an unplugged output go to another one
+    fn output_destroyed<SC: ShellSurface>(&mut self, output: *mut weston_output) {
+        let i = match self.outputs.iter().position(|t| t.output == output) {
+            Some(i) => i,
+            None => return,
+        };
+        let mut tiles = self.outputs.remove(i);
+        self.dead.push(tiles.listeners.detach());
+        let orphans = tiles.windows;
+        let compositor = unsafe { CompositorRef::from_ptr((*output).compositor) };
+        let other = compositor.iterate_outputs().map(|o| o.as_ptr()).find(|&o| o != output);
+        if let Some(other) = other {
+            self.tiles::<SC>(other).windows.extend(orphans);
+            self.arrange::<SC>(other);
+        }
+    }
+
+    fn tile<SC: ShellSurface>(&mut self, surface: *mut weston_desktop_surface, output: *mut weston_output) {
+        self.untile::<SC>(surface);
+        self.tiles::<SC>(output).windows.push(surface);
+        self.arrange::<SC>(output);
+    }
+
+    fn untile<SC: ShellSurface>(&mut self, surface: *mut weston_desktop_surface) -> bool {
+        if let Some(output) = self.output_of(surface) {
+            self.tiles::<SC>(output).windows.retain(|&w| w != surface);
+            self.arrange::<SC>(output);
+            return true;
+        }
+        false
+    }
+
+    fn arrange<SC: ShellSurface>(&mut self, output: *mut weston_output) {
+        let tiles = match self.outputs.iter().find(|t| t.output == output) {
+            Some(tiles) => tiles,
+            None => return,
+        };
+        let output = unsafe { OutputRef::from_ptr(output) };
+        let area = inset(output.work_area(), self.config.outer_gap);
+        // Windows on hidden workspaces don't take up space
+        let windows = tiles.windows.iter().cloned().filter(|&w| is_tile_shown::<SC>(w)).collect::<Vec<_>>();
+        let layout = &self.layouts[tiles.layout % self.layouts.len()];
+        let rects = layout.arrange(area, windows.len());
+        for (surface, rect) in windows.into_iter().zip(rects) {
+            let dsurf = unsafe { DesktopSurfaceRef::<SC>::from_ptr_mut(surface) };
+            // They get their slot back when they leave fullscreen
+            if dsurf.get_fullscreen() {
+                continue;
+            }
+            place(dsurf, apply_gaps(rect, area, self.config.inner_gap));
+        }
+        unsafe { CompositorRef::from_ptr_mut((*output.as_ptr()).compositor) }.schedule_repaint();
+    }
+}
+
+fn is_tile_shown<SC: ShellSurface>(surface: *mut weston_desktop_surface) -> bool {
+    let dsurf = unsafe { DesktopSurfaceRef::<SC>::from_ptr(surface) };
+    is_shown(dsurf.borrow_user_data().expect("user_data").floating().view())
+}
+
+/// Dialogs and windows that can't be resized aren't worth tiling
+fn wants_tiling<SC>(dsurf: &DesktopSurfaceRef<SC>) -> bool {
+    let (min, max) = (dsurf.get_min_size(), dsurf.get_max_size());
+    let fixed_size = max.width > 0 && max.height > 0 && min.width == max.width && min.height == max.height;
+    match dsurf.role() {
+        DesktopSurfaceRole::Toplevel | DesktopSurfaceRole::WlShell | DesktopSurfaceRole::Xwayland =>
+            dsurf.parent().is_none() && !fixed_size,
+        _ => false,
+    }
+}
+
+/// The output under the first seat's pointer, the first output otherwise
+fn output_for_new(compositor: &CompositorRef) -> Option<*mut weston_output> {
+    let pointer_pos = compositor.first_seat()
+        .and_then(|seat| seat.pointer())
+        .map(|p| (wl_fixed_to_int(p.x()), wl_fixed_to_int(p.y())));
+    let contains = |o: &OutputRef, (x, y): (i32, i32)| {
+        x >= o.x() && y >= o.y() && x < o.x() + o.width() && y < o.y() + o.height()
+    };
+    pointer_pos.and_then(|pos| compositor.iterate_outputs().find(|o| contains(o, pos)))
+        .or_else(|| compositor.iterate_outputs().next())
+        .map(|o| o.as_ptr())
+}
+
+pub struct TilingShell<SC> {
+    floating: FloatingShell<SC>,
+    state: Box<TilingState>,
+    phantom: marker::PhantomData<SC>,
+}
+
+impl<SC: ShellSurface> TilingShell<SC> {
+    /// Starts with the master-stack, columns and monocle layouts
+    pub fn new(compositor: &mut CompositorRef, config: TilingConfig, floating: FloatingConfig) -> TilingShell<SC> {
+        let mut shell = TilingShell {
+            floating: FloatingShell::new(compositor, floating),
+            state: Box::new(TilingState {
+                config,
+                layouts: vec![Box::new(MasterStack::default()), Box::new(Columns), Box::new(Monocle)],
+                outputs: Vec::new(),
+                pending: Vec::new(),
+                dead: Vec::new(),
+            }),
+            phantom: marker::PhantomData,
+        };
+        // Minimized tiles give their space to the others until they're restored
+        let state = &mut *shell.state as *mut TilingState;
+        let hook: MinimizedHook<SC> = Rc::new(RefCell::new(move |dsurf: &mut DesktopSurfaceRef<SC>, _minimized: bool| unsafe {
+            if let Some(output) = (*state).output_of(dsurf.as_ptr()) {
+                (*state).arrange::<SC>(output);
+            }
+        }));
+        shell.floating.set_minimized_hook(Some(hook));
+        shell
+    }
+
+    /// The window manager behind the floating windows
+    pub fn floating(&mut self) -> &mut FloatingShell<SC> {
+        &mut self.floating
+    }
+
+    /// Replaces the layouts, outputs go back to the first one. Does nothing with an empty list.
+    pub fn set_layouts(&mut self, layouts: Vec<Box<dyn Layout>>) {
+        if layouts.is_empty() {
+            return;
+        }
+        self.state.layouts = layouts;
+        for tiles in &mut self.state.outputs {
+            tiles.layout = 0;
+        }
+        self.arrange_all();
+    }
+
+    pub fn layout(&mut self, output: &OutputRef) -> &dyn Layout {
+        let index = self.state.tiles::<SC>(output.as_ptr()).layout;
+        &*self.state.layouts[index % self.state.layouts.len()]
+    }
+
+    /// Switches to the layout at that index in the list
+    pub fn set_layout(&mut self, output: &OutputRef, index: usize) {
+        let count = self.state.layouts.len();
+        self.state.tiles::<SC>(output.as_ptr()).layout = index % count;
+        self.state.arrange::<SC>(output.as_ptr());
+    }
+
+    /// Switches to the next layout in the list
+    pub fn next_layout(&mut self, output: &OutputRef) {
+        let index = self.state.tiles::<SC>(output.as_ptr()).layout + 1;
+        self.set_layout(output, index);
+    }
+
+    pub fn arrange(&mut self, output: &OutputRef) {
+        self.state.arrange::<SC>(output.as_ptr());
+    }
+
+    pub fn arrange_all(&mut self) {
+        let outputs = self.state.outputs.iter().map(|t| t.output).collect::<Vec<_>>();
+        for output in outputs {
+            self.state.arrange::<SC>(output);
+        }
+    }
+
+    pub fn is_tiled(&self, dsurf: &DesktopSurfaceRef<SC>) -> bool {
+        self.state.output_of(dsurf.as_ptr()).is_some()
+    }
+
+    /// Takes a window out of the layout, or puts it back on the output it's on (or the one under the pointer)
+    pub fn set_floating(&mut self, dsurf: &mut DesktopSurfaceRef<SC>, floating: bool) {
+        if floating {
+            self.state.untile::<SC>(dsurf.as_ptr());
+            return;
+        }
+        if self.is_tiled(dsurf) {
+            return;
+        }
+        let compositor = unsafe { CompositorRef::from_ptr((*dsurf.surface().as_ptr()).compositor) };
+        let output = dsurf.borrow_user_data().expect("user_data").floating().view().output().map(|o| o.as_ptr())
+            .or_else(|| output_for_new(compositor));
+        if let Some(output) = output {
+            self.state.tile::<SC>(dsurf.as_ptr(), output);
+        }
+    }
+
+    /// Moves a tiled window to the end of another output's list
+    pub fn move_to_output(&mut self, dsurf: &mut DesktopSurfaceRef<SC>, output: &OutputRef) {
+        if self.is_tiled(dsurf) {
+            self.state.tile::<SC>(dsurf.as_ptr(), output.as_ptr());
+        }
+    }
+
+    /// Makes a tiled window the first one of its output
+    pub fn promote(&mut self, dsurf: &mut DesktopSurfaceRef<SC>) {
+        let surface = dsurf.as_ptr();
+        if let Some(output) = self.state.output_of(surface) {
+            let windows = &mut self.state.tiles::<SC>(output).windows;
+            windows.retain(|&w| w != surface);
+            windows.insert(0, surface);
+            self.state.arrange::<SC>(output);
+        }
+    }
+
+    /// Swaps the places of two tiled windows, even across outputs
+    pub fn swap(&mut self, a: &mut DesktopSurfaceRef<SC>, b: &mut DesktopSurfaceRef<SC>) {
+        let (a, b) = (a.as_ptr(), b.as_ptr());
+        let (output_a, output_b) = match (self.state.output_of(a), self.state.output_of(b)) {
+            (Some(output_a), Some(output_b)) => (output_a, output_b),
+            _ => return,
+        };
+        for tiles in &mut self.state.outputs {
+            for w in &mut tiles.windows {
+                if *w == a {
+                    *w = b;
+                } else if *w == b {
+                    *w = a;
+                }
+            }
+        }
+        self.state.arrange::<SC>(output_a);
+        if output_b != output_a {
+            self.state.arrange::<SC>(output_b);
+        }
+    }
+
+    /// The tiled windows of an output, in layout order
+    pub fn windows(&mut self, output: &OutputRef) -> Vec<&mut DesktopSurfaceRef<SC>> {
+        self.state.outputs.iter().find(|t| t.output == output.as_ptr())
+            .map(|t| t.windows.iter().map(|&w| unsafe { DesktopSurfaceRef::from_ptr_mut(w) }).collect())
+            .unwrap_or_else(Vec::new)
+    }
+}
+
+impl<SC: ShellSurface> DesktopApi<SC> for TilingShell<SC> {
+    fn as_any(&mut self) -> &mut any::Any { self }
+
+    fn surface_added(&mut self, dsurf: &mut DesktopSurfaceRef<SC>) {
+        self.floating.surface_added(dsurf);
+        if self.state.config.tile_new_windows {
+            self.state.pending.push(dsurf.as_ptr());
+        }
+    }
+
+    fn surface_removed(&mut self, dsurf: &mut DesktopSurfaceRef<SC>) {
+        let surface = dsurf.as_ptr();
+        self.state.pending.retain(|&p| p != surface);
+        self.state.untile::<SC>(surface);
+        self.floating.surface_removed(dsurf);
+    }
+
+    fn committed(&mut self, dsurf: &mut DesktopSurfaceRef<SC>, sx: i32, sy: i32) {
+        self.state.dead.clear();
+        self.floating.committed(dsurf, sx, sy);
+        let surface = dsurf.as_ptr();
+        // The first commit with a buffer: the title, parent and size limits are known by now
+        if dsurf.surface().width() > 0 && self.state.pending.contains(&surface) {
+            self.state.pending.retain(|&p| p != surface);
+            if wants_tiling(dsurf) {
+                self.set_floating(dsurf, false);
+            }
+        }
+    }
+
+    fn show_window_menu(&mut self, dsurf: &mut DesktopSurfaceRef<SC>, seat: &mut SeatRef, x: i32, y: i32) {
+        self.floating.show_window_menu(dsurf, seat, x, y);
+    }
+
+    fn moove(&mut self, dsurf: &mut DesktopSurfaceRef<SC>, seat: &mut SeatRef, serial: u32) {
+        if !self.is_tiled(dsurf) {
+            self.floating.moove(dsurf, seat, serial);
+        }
+    }
+
+    fn resize(&mut self, dsurf: &mut DesktopSurfaceRef<SC>, seat: &mut SeatRef, serial: u32, edges: Resize) {
+        if !self.is_tiled(dsurf) {
+            self.floating.resize(dsurf, seat, serial, edges);
+        }
+    }
+
+    fn minimized_requested(&mut self, dsurf: &mut DesktopSurfaceRef<SC>) {
+        // Re-arranged by the minimized hook
+        self.floating.minimized_requested(dsurf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, width: i32, height: i32) -> Geometry {
+        Geometry { x, y, width, height }
+    }
+
+    fn tuples(rects: &[Geometry]) -> Vec<(i32, i32, i32, i32)> {
+        rects.iter().map(|r| (r.x, r.y, r.width, r.height)).collect()
+    }
+
+    #[test]
+    fn split_spreads_the_remainder() {
+        assert_eq!(split(0, 10, 3), vec![(0, 3), (3, 3), (6, 4)]);
+        assert_eq!(split(5, 4, 4), vec![(5, 1), (6, 1), (7, 1), (8, 1)]);
+        // Nothing to split between counts as one part
+        assert_eq!(split(5, 10, 0), vec![(5, 10)]);
+    }
+
+    #[test]
+    fn master_stack() {
+        let layout = MasterStack { masters: 1, ratio: 0.5 };
+        assert_eq!(tuples(&layout.arrange(rect(0, 0, 100, 60), 3)),
+                   vec![(0, 0, 50, 60), (50, 0, 50, 30), (50, 30, 50, 30)]);
+        let layout = MasterStack { masters: 2, ratio: 0.25 };
+        assert_eq!(tuples(&layout.arrange(rect(10, 20, 100, 60), 3)),
+                   vec![(10, 20, 25, 30), (10, 50, 25, 30), (35, 20, 75, 60)]);
+    }
+
+    #[test]
+    fn master_stack_without_a_stack() {
+        for &masters in &[0, 2, 5] {
+            let layout = MasterStack { masters, ratio: 0.5 };
+            assert_eq!(tuples(&layout.arrange(rect(0, 0, 100, 60), 2)), vec![(0, 0, 100, 30), (0, 30, 100, 30)]);
+        }
+        assert!(MasterStack::default().arrange(rect(0, 0, 100, 60), 0).is_empty());
+    }
+
+    #[test]
+    fn master_stack_extreme_ratios() {
+        let layout = MasterStack { masters: 1, ratio: 0.0 };
+        assert_eq!(tuples(&layout.arrange(rect(0, 0, 100, 60), 2)), vec![(0, 0, 1, 60), (1, 0, 99, 60)]);
+        let layout = MasterStack { masters: 1, ratio: 1.0 };
+        assert_eq!(tuples(&layout.arrange(rect(0, 0, 100, 60), 2)), vec![(0, 0, 99, 60), (99, 0, 1, 60)]);
+        let layout = MasterStack { masters: 1, ratio: 7.0 };
+        assert_eq!(tuples(&layout.arrange(rect(0, 0, 100, 60), 2)), vec![(0, 0, 99, 60), (99, 0, 1, 60)]);
+    }
+
+    #[test]
+    fn columns() {
+        assert_eq!(tuples(&Columns.arrange(rect(10, 5, 100, 50), 3)),
+                   vec![(10, 5, 33, 50), (43, 5, 33, 50), (76, 5, 34, 50)]);
+        assert_eq!(tuples(&Columns.arrange(rect(10, 5, 100, 50), 1)), vec![(10, 5, 100, 50)]);
+        assert!(Columns.arrange(rect(10, 5, 100, 50), 0).is_empty());
+    }
+
+    #[test]
+    fn gaps_only_between_windows() {
+        let area = rect(0, 0, 100, 100);
+        assert_eq!(tuples(&[apply_gaps(rect(0, 0, 50, 50), area, 10)]), vec![(0, 0, 45, 45)]);
+        assert_eq!(tuples(&[apply_gaps(rect(50, 50, 50, 50), area, 10)]), vec![(55, 55, 45, 45)]);
+        assert_eq!(tuples(&[apply_gaps(area, area, 10)]), vec![(0, 0, 100, 100)]);
+    }
+
+    #[test]
+    fn odd_gaps_add_up() {
+        let area = rect(0, 0, 100, 100);
+        let left = apply_gaps(rect(0, 0, 50, 100), area, 5);
+        let right = apply_gaps(rect(50, 0, 50, 100), area, 5);
+        assert_eq!(tuples(&[left, right]), vec![(0, 0, 47, 100), (52, 0, 48, 100)]);
+        assert_eq!(right.x - (left.x + left.width), 5);
+    }
+
+    #[test]
+    fn gaps_keep_a_pixel() {
+        let area = rect(0, 0, 100, 100);
+        assert_eq!(tuples(&[apply_gaps(rect(0, 0, 3, 100), area, 10)]), vec![(0, 0, 1, 100)]);
+    }
+
+    #[test]
+    fn inset_area() {
+        assert_eq!(tuples(&[inset(rect(0, 0, 100, 50), 10)]), vec![(10, 10, 80, 30)]);
+        assert_eq!(tuples(&[inset(rect(0, 0, 100, 50), 0)]), vec![(0, 0, 100, 50)]);
+        assert_eq!(tuples(&[inset(rect(0, 0, 100, 50), 30)]), vec![(30, 30, 40, 1)]);
+    }
+}
@@ -0,0 +1,632 @@
+//! Workspaces (virtual desktops): every output has its own list of them, one shown at a time.
+//!
+//! Each workspace is a layer, the shown one at `POSITION_NORMAL` and the others at `POSITION_HIDDEN`.
+//! A window is on the workspace whose layer its view is in, so the shell puts new windows on one
+//! with `Workspaces::add` instead of inserting them into a layer of its own (`floating::activate`
+//! raises windows within whatever layer they're in). Switching doesn't touch the keyboard focus or
+//! the tiling, that's for `WorkspacesApi::switched`.
+//!
+//! Panels list and switch workspaces through `ext_workspace_manager_v1`, with one workspace group per output.
+//!
+//! Sticky windows are on all workspaces of their output: switching moves them to the shown one.
+
+use libc;
+use std::{ffi, mem, ptr};
+use std::mem::ManuallyDrop;
+use libweston_sys::{
+    weston_compositor, weston_output, weston_view, weston_layer_entry,
+    weston_layer_unset_position, weston_output_damage,
+    weston_surface_is_desktop_surface, weston_surface_get_desktop_surface, weston_desktop_surface_propagate_layer,
+    ext_workspace_manager_v1_interface, ext_workspace_group_handle_v1_interface, ext_workspace_handle_v1_interface,
+};
+use wayland_sys::common::wl_array;
+use wayland_sys::server::{
+    wl_client, wl_resource, wl_global,
+    wl_global_create, wl_global_destroy, wl_client_post_no_memory,
+    wl_resource_create, wl_resource_destroy, wl_resource_set_implementation,
+    wl_resource_get_user_data, wl_resource_set_user_data, wl_resource_get_version,
+    wl_resource_get_client, wl_resource_find_for_client, wl_resource_post_event,
+    wl_list_remove,
+};
+use foreign_types::ForeignTypeRef;
+use ::compositor::CompositorRef;
+use ::desktop::DesktopSurfaceRef;
+use ::layer::{Layer, LayerRef, POSITION_NORMAL, POSITION_HIDDEN};
+use ::listener::{WlListener, ListenerList};
+use ::output::OutputRef;
+use ::view::ViewRef;
+
+const EXT_WORKSPACE_MANAGER_V1_WORKSPACE_GROUP: u32 = 0;
+const EXT_WORKSPACE_MANAGER_V1_WORKSPACE: u32 = 1;
+const EXT_WORKSPACE_MANAGER_V1_DONE: u32 = 2;
+const EXT_WORKSPACE_MANAGER_V1_FINISHED: u32 = 3;
+const EXT_WORKSPACE_GROUP_HANDLE_V1_CAPABILITIES: u32 = 0;
+const EXT_WORKSPACE_GROUP_HANDLE_V1_OUTPUT_ENTER: u32 = 1;
+const EXT_WORKSPACE_GROUP_HANDLE_V1_WORKSPACE_ENTER: u32 = 3;
+const EXT_WORKSPACE_GROUP_HANDLE_V1_REMOVED: u32 = 5;
+const EXT_WORKSPACE_HANDLE_V1_ID: u32 = 0;
+const EXT_WORKSPACE_HANDLE_V1_NAME: u32 = 1;
+const EXT_WORKSPACE_HANDLE_V1_COORDINATES: u32 = 2;
+const EXT_WORKSPACE_HANDLE_V1_STATE: u32 = 3;
+const EXT_WORKSPACE_HANDLE_V1_CAPABILITIES: u32 = 4;
+const EXT_WORKSPACE_HANDLE_V1_REMOVED: u32 = 5;
+const EXT_WORKSPACE_HANDLE_V1_STATE_ACTIVE: u32 = 1;
+const EXT_WORKSPACE_HANDLE_V1_STATE_URGENT: u32 = 2;
+const EXT_WORKSPACE_HANDLE_V1_WORKSPACE_CAPABILITIES_ACTIVATE: u32 = 1;
+
+#[derive(Builder)]
+pub struct WorkspacesConfig {
+    /// One workspace per name on every output
+    #[builder(default = "(1..5).map(|i| i.to_string()).collect()")]
+    names: Vec<String>,
+}
+
+pub trait WorkspacesApi {
+    /// Another workspace is shown on the output, by `Workspaces::activate` or at a panel's request.
+    /// Time to move the keyboard focus to a window that's still visible (`FocusManager::refocus_hidden`).
+    fn switched(&mut self, _output: &mut OutputRef, _from: usize, _to: usize) {}
+}
+
+struct Workspace {
+    group: *mut Group,
+    index: usize,
+    id: ffi::CString,
+    name: ffi::CString,
+    layer: Layer,
+    urgent: bool,
+    /// ext_workspace_handle_v1 objects
+    handles: Vec<*mut wl_resource>,
+}
+
+impl Workspace {
+    fn active(&self) -> bool {
+        unsafe { (*self.group).active == self.index }
+    }
+
+    fn state(&self) -> u32 {
+        let mut state = 0;
+        if self.active() {
+            state |= EXT_WORKSPACE_HANDLE_V1_STATE_ACTIVE;
+        }
+        if self.urgent {
+            state |= EXT_WORKSPACE_HANDLE_V1_STATE_URGENT;
+        }
+        state
+    }
+
+    fn send_state(&self) {
+        for &handle in &self.handles {
+            unsafe { wl_resource_post_event(handle, EXT_WORKSPACE_HANDLE_V1_STATE, self.state()); }
+        }
+    }
+
+    fn create_handle(&mut self, manager: *mut wl_resource) -> *mut wl_resource {
+        unsafe {
+            let client = wl_resource_get_client(manager);
+            let handle = wl_resource_create(client, &ext_workspace_handle_v1_interface, wl_resource_get_version(manager), 0);
+            if handle.is_null() {
+                wl_client_post_no_memory(client);
+                return handle;
+            }
+            wl_resource_set_implementation(handle, &WORKSPACE_IMPL as *const _ as *const libc::c_void,
+                                           self as *mut Workspace as *mut libc::c_void, Some(run_workspace_handle_destroyed));
+            self.handles.push(handle);
+            wl_resource_post_event(manager, EXT_WORKSPACE_MANAGER_V1_WORKSPACE, handle);
+            wl_resource_post_event(handle, EXT_WORKSPACE_HANDLE_V1_ID, self.id.as_ptr());
+            wl_resource_post_event(handle, EXT_WORKSPACE_HANDLE_V1_NAME, self.name.as_ptr());
+            let mut coordinates = [self.index as u32];
+            let mut array = wl_array {
+                size: mem::size_of::<u32>(),
+                alloc: mem::size_of::<u32>(),
+                data: coordinates.as_mut_ptr() as *mut libc::c_void,
+            };
+            wl_resource_post_event(handle, EXT_WORKSPACE_HANDLE_V1_COORDINATES, &mut array as *mut wl_array);
+            wl_resource_post_event(handle, EXT_WORKSPACE_HANDLE_V1_STATE, self.state());
+            wl_resource_post_event(handle, EXT_WORKSPACE_HANDLE_V1_CAPABILITIES, EXT_WORKSPACE_HANDLE_V1_WORKSPACE_CAPABILITIES_ACTIVATE);
+            handle
+        }
+    }
+
+    /// The handles stay around (inert) until their clients destroy them
+    fn remove_handles(&mut self) {
+        for handle in self.handles.drain(..) {
+            unsafe {
+                wl_resource_post_event(handle, EXT_WORKSPACE_HANDLE_V1_REMOVED);
+                wl_resource_set_user_data(handle, ptr::null_mut());
+            }
+        }
+    }
+
+    fn views(&self) -> Vec<*mut weston_view> {
+        let mut result = Vec::new();
+        unsafe {
+            let head = &mut (*self.layer.as_ptr()).view_list.link as *mut _;
+            let mut link = (*head).next;
+            while link != head {
+                let entry = wl_container_of!(link, weston_layer_entry, link);
+                result.push(wl_container_of!(entry, weston_view, layer_link));
+                link = (*link).next;
+            }
+        }
+        result
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        // Windows still in here would point into the freed layer
+        for view in self.views() {
+            unsafe { ViewRef::from_ptr_mut(view) }.layer_entry_remove();
+        }
+        // Dropping a layer doesn't take it out of the compositor's list
+        unsafe { weston_layer_unset_position(self.layer.as_ptr()); }
+    }
+}
+
+struct Group {
+    state: *mut WorkspacesState,
+    output: *mut weston_output,
+    workspaces: Vec<Box<Workspace>>,
+    active: usize,
+    /// ext_workspace_group_handle_v1 objects
+    handles: Vec<*mut wl_resource>,
+}
+
+impl Group {
+    fn announce(&mut self, manager: *mut wl_resource) {
+        unsafe {
+            let client = wl_resource_get_client(manager);
+            let handle = wl_resource_create(client, &ext_workspace_group_handle_v1_interface, wl_resource_get_version(manager), 0);
+            if handle.is_null() {
+                wl_client_post_no_memory(client);
+                return;
+            }
+            wl_resource_set_implementation(handle, &GROUP_IMPL as *const _ as *const libc::c_void,
+                                           self as *mut Group as *mut libc::c_void, Some(run_group_handle_destroyed));
+            self.handles.push(handle);
+            wl_resource_post_event(manager, EXT_WORKSPACE_MANAGER_V1_WORKSPACE_GROUP, handle);
+            wl_resource_post_event(handle, EXT_WORKSPACE_GROUP_HANDLE_V1_CAPABILITIES, 0u32);
+            for head in OutputRef::from_ptr_mut(self.output).iterate_heads() {
+                let output = wl_resource_find_for_client(head.resource_list(), client);
+                if !output.is_null() {
+                    wl_resource_post_event(handle, EXT_WORKSPACE_GROUP_HANDLE_V1_OUTPUT_ENTER, output);
+                }
+            }
+            for workspace in self.workspaces.iter_mut() {
+                let workspace_handle = workspace.create_handle(manager);
+                if !workspace_handle.is_null() {
+                    wl_resource_post_event(handle, EXT_WORKSPACE_GROUP_HANDLE_V1_WORKSPACE_ENTER, workspace_handle);
+                }
+            }
+        }
+    }
+
+    fn remove_handles(&mut self) {
+        for workspace in self.workspaces.iter_mut() {
+            workspace.remove_handles();
+        }
+        for handle in self.handles.drain(..) {
+            unsafe {
+                wl_resource_post_event(handle, EXT_WORKSPACE_GROUP_HANDLE_V1_REMOVED);
+                wl_resource_set_user_data(handle, ptr::null_mut());
+            }
+        }
+    }
+}
+
+#[repr(C)]
+struct ManagerInterface {
+    commit: unsafe extern "C" fn(*mut wl_client, *mut wl_resource),
+    stop: unsafe extern "C" fn(*mut wl_client, *mut wl_resource),
+}
+
+#[repr(C)]
+struct GroupInterface {
+    create_workspace: unsafe extern "C" fn(*mut wl_client, *mut wl_resource, *const libc::c_char),
+    destroy: unsafe extern "C" fn(*mut wl_client, *mut wl_resource),
+}
+
+#[repr(C)]
+struct WorkspaceInterface {
+    destroy: unsafe extern "C" fn(*mut wl_client, *mut wl_resource),
+    activate: unsafe extern "C" fn(*mut wl_client, *mut wl_resource),
+    deactivate: unsafe extern "C" fn(*mut wl_client, *mut wl_resource),
+    assign: unsafe extern "C" fn(*mut wl_client, *mut wl_resource, *mut wl_resource),
+    remove: unsafe extern "C" fn(*mut wl_client, *mut wl_resource),
+}
+
+static MANAGER_IMPL: ManagerInterface = ManagerInterface {
+    commit: run_commit,
+    stop: run_stop,
+};
+
+static GROUP_IMPL: GroupInterface = GroupInterface {
+    create_workspace: run_ignored_create_workspace,
+    destroy: run_destroy,
+};
+
+static WORKSPACE_IMPL: WorkspaceInterface = WorkspaceInterface {
+    destroy: run_destroy,
+    activate: run_activate,
+    deactivate: run_ignored,
+    assign: run_ignored_assign,
+    remove: run_ignored,
+};
+
+type ViewListener = ManuallyDrop<Box<WlListener<ViewRef>>>;
+
+struct StickyView {
+    view: *mut weston_view,
+    destroy_listener: ViewListener,
+}
+
+/// The view's popups and subsurfaces follow it to its new layer
+fn propagate_layer(view: *mut weston_view) {
+    unsafe {
+        let surface = (*view).surface;
+        if weston_surface_is_desktop_surface(surface) {
+            weston_desktop_surface_propagate_layer(weston_surface_get_desktop_surface(surface));
+        }
+    }
+}
+
+struct WorkspacesState {
+    compositor: *mut weston_compositor,
+    config: WorkspacesConfig,
+    api: Box<dyn WorkspacesApi>,
+    global: *mut wl_global,
+    managers: Vec<*mut wl_resource>,
+    groups: Vec<Box<Group>>,
+    next_id: u32,
+    /// Workspaces panels asked to activate, applied on commit
+    pending: Vec<*mut Workspace>,
+    sticky: Vec<StickyView>,
+    // Listeners can't be freed while they run, these go on the next `set_sticky`
+    dead: Vec<ViewListener>,
+    /// On the compositor's output signals
+    listeners: ListenerList,
+}
+
+impl WorkspacesState {
+    fn group(&mut self, output: *mut weston_output) -> Option<&mut Group> {
+        self.groups.iter_mut().find(|g| g.output == output).map(|g| &mut **g)
+    }
+
+    fn send_done(&self) {
+        for &manager in &self.managers {
+            unsafe { wl_resource_post_event(manager, EXT_WORKSPACE_MANAGER_V1_DONE); }
+        }
+    }
+
+    fn add_output(&mut self, output: *mut weston_output) {
+        if self.group(output).is_some() {
+            return;
+        }
+        let compositor = unsafe { CompositorRef::from_ptr(self.compositor) };
+        let mut group = Box::new(Group {
+            state: self,
+            output,
+            workspaces: Vec::new(),
+            active: 0,
+            handles: Vec::new(),
+        });
+        let group_ptr = &mut *group as *mut Group;
+        for (index, name) in self.config.names.iter().enumerate() {
+            let mut layer = Layer::new(compositor);
+            layer.set_position(if index == 0 { POSITION_NORMAL } else { POSITION_HIDDEN });
+            self.next_id += 1;
+            group.workspaces.push(Box::new(Workspace {
+                group: group_ptr,
+                index,
+                id: ffi::CString::new(format!("weston-rs-{}", self.next_id)).expect("CString"),
+                name: ffi::CString::new(name.replace('\0', "")).expect("CString"),
+                layer,
+                urgent: false,
+                handles: Vec::new(),
+            }));
+        }
+        for &manager in &self.managers {
+            group.announce(manager);
+        }
+        self.groups.push(group);
+        self.send_done();
+    }
+
+    /// The windows of an unplugged output go to the shown workspace of another one.
+    /// Without another output, dropping the group just unlinks them.
+    fn remove_output(&mut self, output: *mut weston_output) {
+        let i = match self.groups.iter().position(|g| g.output == output) {
+            Some(i) => i,
+            None => return,
+        };
+        let mut group = self.groups.remove(i);
+        group.remove_handles();
+        let group_ptr = &mut *group as *mut Group;
+        self.pending.retain(|&w| unsafe { (*w).group } != group_ptr);
+        if let Some(other) = self.groups.first_mut() {
+            let active = other.active;
+            let target = &mut other.workspaces[active].layer;
+            for workspace in &group.workspaces {
+                for view in workspace.views() {
+                    let view = unsafe { ViewRef::from_ptr_mut(view) };
+                    view.layer_entry_remove();
+                    target.view_list_entry_insert(view);
+                    view.geometry_dirty();
+                }
+            }
+        }
+        self.send_done();
+    }
+
+    fn forget_sticky(&mut self, view: *mut weston_view) {
+        if let Some(pos) = self.sticky.iter().position(|s| s.view == view) {
+            let mut listener = self.sticky.remove(pos).destroy_listener;
+            unsafe { wl_list_remove(&mut listener.wll.link); }
+            self.dead.push(listener);
+        }
+    }
+
+    fn activate(&mut self, output: *mut weston_output, index: usize) {
+        let sticky = self.sticky.iter().map(|s| s.view).collect::<Vec<_>>();
+        let from = {
+            let group = match self.group(output) {
+                Some(group) => group,
+                None => return,
+            };
+            if index >= group.workspaces.len() || index == group.active {
+                return;
+            }
+            let from = group.active;
+            group.active = index;
+            // Bottom first, so that they keep their stacking order on top of the new workspace
+            for view in group.workspaces[from].views().into_iter().rev().filter(|v| sticky.contains(v)) {
+                let view = unsafe { ViewRef::from_ptr_mut(view) };
+                view.layer_entry_remove();
+                group.workspaces[index].layer.view_list_entry_insert(view);
+                propagate_layer(view.as_ptr());
+                view.geometry_dirty();
+            }
+            group.workspaces[from].layer.set_position(POSITION_HIDDEN);
+            group.workspaces[index].layer.set_position(POSITION_NORMAL);
+            group.workspaces[from].send_state();
+            group.workspaces[index].send_state();
+            from
+        };
+        self.send_done();
+        unsafe { weston_output_damage(output); }
+        let output = unsafe { OutputRef::from_ptr_mut(output) };
+        self.api.switched(output, from, index);
+    }
+}
+
+unsafe extern "C" fn bind_manager(client: *mut wl_client, data: *mut libc::c_void, version: u32, id: u32) {
+    let state = &mut *(data as *mut WorkspacesState);
+    let resource = wl_resource_create(client, &ext_workspace_manager_v1_interface, version as libc::c_int, id);
+    if resource.is_null() {
+        wl_client_post_no_memory(client);
+        return;
+    }
+    wl_resource_set_implementation(resource, &MANAGER_IMPL as *const _ as *const libc::c_void, data, Some(run_manager_destroyed));
+    state.managers.push(resource);
+    for group in state.groups.iter_mut() {
+        group.announce(resource);
+    }
+    wl_resource_post_event(resource, EXT_WORKSPACE_MANAGER_V1_DONE);
+}
+
+unsafe extern "C" fn run_manager_destroyed(resource: *mut wl_resource) {
+    let state = wl_resource_get_user_data(resource) as *mut WorkspacesState;
+    if !state.is_null() {
+        (*state).managers.retain(|&r| r != resource);
+    }
+}
+
+unsafe extern "C" fn run_commit(_client: *mut wl_client, resource: *mut wl_resource) {
+    let state = wl_resource_get_user_data(resource) as *mut WorkspacesState;
+    if state.is_null() {
+        return;
+    }
+    let state = &mut *state;
+    for workspace in mem::replace(&mut state.pending, Vec::new()) {
+        let (output, index) = ((*(*workspace).group).output, (*workspace).index);
+        state.activate(output, index);
+    }
+}
+
+unsafe extern "C" fn run_stop(_client: *mut wl_client, resource: *mut wl_resource) {
+    wl_resource_post_event(resource, EXT_WORKSPACE_MANAGER_V1_FINISHED);
+    wl_resource_destroy(resource);
+}
+
+unsafe extern "C" fn run_destroy(_client: *mut wl_client, resource: *mut wl_resource) {
+    wl_resource_destroy(resource);
+}
+
+unsafe extern "C" fn run_group_handle_destroyed(handle: *mut wl_resource) {
+    let group = wl_resource_get_user_data(handle) as *mut Group;
+    if !group.is_null() {
+        (*group).handles.retain(|&h| h != handle);
+    }
+}
+
+unsafe extern "C" fn run_workspace_handle_destroyed(handle: *mut wl_resource) {
+    let workspace = wl_resource_get_user_data(handle) as *mut Workspace;
+    if !workspace.is_null() {
+        (*workspace).handles.retain(|&h| h != handle);
+    }
+}
+
+unsafe extern "C" fn run_activate(_client: *mut wl_client, handle: *mut wl_resource) {
+    let workspace = wl_resource_get_user_data(handle) as *mut Workspace;
+    if !workspace.is_null() {
+        (*(*(*workspace).group).state).pending.push(workspace);
+    }
+}
+
+// Not advertised in the capabilities, clients shouldn't send these
+
+unsafe extern "C" fn run_ignored(_client: *mut wl_client, _resource: *mut wl_resource) {}
+
+unsafe extern "C" fn run_ignored_assign(_client: *mut wl_client, _resource: *mut wl_resource, _group: *mut wl_resource) {}
+
+unsafe extern "C" fn run_ignored_create_workspace(_client: *mut wl_client, _resource: *mut wl_resource, _name: *const libc::c_char) {}
+
+/// The workspaces of all outputs and the `ext_workspace_manager_v1` global.
+pub struct Workspaces {
+    state: Box<WorkspacesState>,
+}
+
+impl Workspaces {
+    /// Outputs get their workspaces when they're created (or right away if they exist already)
+    pub fn new(compositor: &mut CompositorRef, config: WorkspacesConfig, api: Box<dyn WorkspacesApi>) -> Workspaces {
+        let mut state = Box::new(WorkspacesState {
+            compositor: compositor.as_ptr(),
+            config,
+            api,
+            global: ptr::null_mut(),
+            managers: Vec::new(),
+            groups: Vec::new(),
+            next_id: 0,
+            pending: Vec::new(),
+            sticky: Vec::new(),
+            dead: Vec::new(),
+            listeners: ListenerList::new(),
+        });
+        state.global = unsafe {
+            wl_global_create((*compositor.as_ptr()).wl_display, &ext_workspace_manager_v1_interface, 1,
+                             &mut *state as *mut _ as *mut libc::c_void, bind_manager)
+        };
+        let state_ptr = &mut *state as *mut WorkspacesState;
+        for output in compositor.iterate_outputs() {
+            state.add_output(output.as_ptr());
+        }
+        state.listeners.add(WlListener::new(Box::new(move |output: &mut OutputRef| {
+            unsafe { (*state_ptr).add_output(output.as_ptr()); }
+        })), compositor.output_created_signal());
+        state.listeners.add(WlListener::new(Box::new(move |output: &mut OutputRef| {
+            unsafe { (*state_ptr).remove_output(output.as_ptr()); }
+        })), compositor.output_destroyed_signal());
+        Workspaces { state }
+    }
+
+    pub fn api(&mut self) -> &mut dyn WorkspacesApi {
+        &mut *self.state.api
+    }
+
+    /// How many workspaces the output has (0 for unknown outputs)
+    pub fn count(&self, output: &OutputRef) -> usize {
+        self.state.groups.iter().find(|g| g.output == output.as_ptr()).map(|g| g.workspaces.len()).unwrap_or(0)
+    }
+
+    /// The index of the shown workspace
+    pub fn active(&self, output: &OutputRef) -> Option<usize> {
+        self.state.groups.iter().find(|g| g.output == output.as_ptr()).map(|g| g.active)
+    }
+
+    /// Shows another workspace on the output
+    pub fn activate(&mut self, output: &OutputRef, index: usize) {
+        self.state.activate(output.as_ptr(), index);
+    }
+
+    pub fn layer(&mut self, output: &OutputRef, index: usize) -> Option<&mut LayerRef> {
+        self.state.group(output.as_ptr())
+            .and_then(|g| g.workspaces.get_mut(index))
+            .map(|w| &mut *w.layer)
+    }
+
+    /// Puts a window on the shown workspace of the output (e.g. a new one, in `surface_added`)
+    pub fn add<SC>(&mut self, surface: &DesktopSurfaceRef<SC>, view: &mut ViewRef, output: &OutputRef) {
+        if let Some(index) = self.active(output) {
+            self.move_to(surface, view, output, index);
+        }
+    }
+
+    /// Moves a window to a workspace, possibly of another output
+    pub fn move_to<SC>(&mut self, surface: &DesktopSurfaceRef<SC>, view: &mut ViewRef, output: &OutputRef, index: usize) {
+        let layer = match self.layer(output, index) {
+            Some(layer) => layer,
+            None => return,
+        };
+        view.damage_below();
+        view.geometry_dirty();
+        view.layer_entry_remove();
+        layer.view_list_entry_insert(view);
+        surface.propagate_layer();
+        view.geometry_dirty();
+        unsafe { CompositorRef::from_ptr_mut(self.state.compositor) }.schedule_repaint();
+    }
+
+    /// The output and the index of the workspace the window is on
+    pub fn workspace_of(&self, view: &ViewRef) -> Option<(&OutputRef, usize)> {
+        let layer = view.layer_link().layer;
+        self.state.groups.iter()
+            .filter_map(|g| g.workspaces.iter().position(|w| w.layer.as_ptr() == layer).map(|i| (g.output, i)))
+            .next()
+            .map(|(output, i)| (unsafe { OutputRef::from_ptr(output) }, i))
+    }
+
+    /// Keeps the window on the shown workspace of its output, whichever it is.
+    /// Sticky windows are moved when switching, there's nothing to do when they go away.
+    pub fn set_sticky(&mut self, view: &ViewRef, sticky: bool) {
+        for listener in self.state.dead.drain(..) {
+            drop(ManuallyDrop::into_inner(listener));
+        }
+        if sticky == self.is_sticky(view) {
+            return;
+        }
+        if !sticky {
+            self.state.forget_sticky(view.as_ptr());
+            return;
+        }
+        let state_ptr = &mut *self.state as *mut WorkspacesState;
+        let view_ptr = view.as_ptr();
+        let mut destroy_listener = WlListener::new(Box::new(move |_: &mut ViewRef| {
+            unsafe { (*state_ptr).forget_sticky(view_ptr); }
+        }));
+        destroy_listener.signal_add(view.destroy_signal());
+        self.state.sticky.push(StickyView { view: view_ptr, destroy_listener });
+    }
+
+    pub fn is_sticky(&self, view: &ViewRef) -> bool {
+        self.state.sticky.iter().any(|s| s.view == view.as_ptr())
+    }
+
+    /// Shows the workspace as wanting attention in panels
+    pub fn set_urgent(&mut self, output: &OutputRef, index: usize, urgent: bool) {
+        let changed = match self.state.group(output.as_ptr()).and_then(|g| g.workspaces.get_mut(index)) {
+            Some(workspace) if workspace.urgent != urgent => {
+                workspace.urgent = urgent;
+                workspace.send_state();
+                true
+            },
+            _ => false,
+        };
+        if changed {
+            self.state.send_done();
+        }
+    }
+}
+
+impl Drop for Workspaces {
+    fn drop(&mut self) {
+        for group in self.state.groups.iter_mut() {
+            group.remove_handles();
+        }
+        for sticky in self.state.sticky.drain(..) {
+            let mut listener = sticky.destroy_listener;
+            unsafe { wl_list_remove(&mut listener.wll.link); }
+            drop(ManuallyDrop::into_inner(listener));
+        }
+        // (already unlinked)
+        for listener in self.state.dead.drain(..) {
+            drop(ManuallyDrop::into_inner(listener));
+        }
+        unsafe {
+            // The managers' resources stay with their clients
+            for &manager in &self.state.managers {
+                wl_resource_set_user_data(manager, ptr::null_mut());
+            }
+            wl_global_destroy(self.state.global);
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use libc;
 use std::ffi;
 use libweston_sys::{
-    weston_surface, weston_surface_create, weston_surface_destroy,
+    weston_surface, weston_view, weston_surface_create, weston_surface_destroy,
     weston_surface_set_size, weston_surface_set_color,
     weston_surface_get_role, weston_surface_set_role,
     weston_surface_damage, weston_surface_schedule_repaint,
@@ -9,12 +9,33 @@ use libweston_sys::{
     weston_surface_to_buffer_float, weston_surface_get_content_size,
     weston_surface_get_main_surface, weston_surface_copy_content,
 };
+use wayland_sys::common::wl_list;
 use wayland_sys::server::wl_signal;
 use wayland_server::Resource;
 use wayland_server::commons::Interface;
 use foreign_types::{ForeignType, ForeignTypeRef};
 use ::compositor::CompositorRef;
 use ::output::OutputRef;
+use ::pointer_constraints::PointerConstraintIterator;
+use ::view::ViewRef;
+
+pub struct SurfaceViewIterator<'a> {
+    surface: &'a SurfaceRef,
+    link: *mut wl_list,
+}
+
+impl<'a> Iterator for SurfaceViewIterator<'a> {
+    type Item = &'a mut ViewRef;
+
+    fn next(&mut self) -> Option<&'a mut ViewRef> {
+        self.link = unsafe { (*self.link).next };
+        if self.link == unsafe { &mut (*self.surface.as_ptr()).views as *mut _ } {
+            None
+        } else {
+            Some(unsafe { ViewRef::from_ptr_mut(wl_container_of!(self.link, weston_view, surface_link)) })
+        }
+    }
+}
 
 foreign_type! {
     type CType = weston_surface;
@@ -50,6 +71,16 @@ impl SurfaceRef {
         unsafe { ffi::CStr::from_ptr(weston_surface_get_role(self.as_ptr())) }
     }
 
+    /// Like `get_role`, but for surfaces that might not have a role yet
+    pub fn role_name(&self) -> Option<&ffi::CStr> {
+        let role = unsafe { weston_surface_get_role(self.as_ptr()) };
+        if role.is_null() {
+            None
+        } else {
+            Some(unsafe { ffi::CStr::from_ptr(role) })
+        }
+    }
+
     pub fn set_role<S: AsRef<ffi::CStr>, I: Interface>(&mut self, role_name: S, error_resource: &Resource<I>, error_code: u32) -> bool {
         unsafe { weston_surface_set_role(self.as_ptr(), role_name.as_ref().as_ptr(), error_resource.c_ptr(), error_code) == 0 }
     }
@@ -101,6 +132,17 @@ impl SurfaceRef {
         self.copy_content(0, 0, width, height)
     }
 
+    pub fn iterate_views(&self) -> SurfaceViewIterator {
+        SurfaceViewIterator {
+            surface: self,
+            link: unsafe { &mut (*self.as_ptr()).views },
+        }
+    }
+
+    pub fn pointer_constraints(&self) -> PointerConstraintIterator {
+        PointerConstraintIterator::new(self)
+    }
+
     pub fn set_committed<D, T: FnMut(&mut SurfaceRef, i32, i32, &mut D) + 'static>(&mut self, cb: T, user_data: D) {
         unsafe {
             (*self.as_ptr()).committed_private = Box::into_raw(Box::new((cb, user_data))) as *mut libc::c_void;
@@ -0,0 +1,47 @@
+use libc;
+use wayland_sys::server::{
+    wl_event_source,
+    wl_display_get_event_loop, wl_event_loop_add_timer,
+    wl_event_source_timer_update, wl_event_source_remove,
+};
+use foreign_types::ForeignTypeRef;
+use ::compositor::CompositorRef;
+
+/// A one-shot timer on the compositor's event loop. Removed from the loop when dropped.
+pub struct Timer {
+    source: *mut wl_event_source,
+    // the outer box is the stable pointer passed to the event loop
+    _cb: Box<Box<FnMut()>>,
+}
+
+impl Timer {
+    pub fn new(compositor: &CompositorRef, cb: Box<FnMut()>) -> Timer {
+        let mut cb = Box::new(cb);
+        let source = unsafe {
+            let event_loop = wl_display_get_event_loop((*compositor.as_ptr()).wl_display);
+            wl_event_loop_add_timer(event_loop, run_timer, &mut *cb as *mut _ as *mut libc::c_void)
+        };
+        Timer { source, _cb: cb }
+    }
+
+    /// (Re)starts the countdown.
+    pub fn arm(&mut self, ms: u32) {
+        unsafe { wl_event_source_timer_update(self.source, ms as libc::c_int); }
+    }
+
+    pub fn disarm(&mut self) {
+        unsafe { wl_event_source_timer_update(self.source, 0); }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        unsafe { wl_event_source_remove(self.source); }
+    }
+}
+
+unsafe extern "C" fn run_timer(data: *mut libc::c_void) -> libc::c_int {
+    let cb = &mut *(data as *mut Box<FnMut()>);
+    cb();
+    0
+}
@@ -13,7 +13,7 @@ use libweston_sys::{
     weston_view_to_global_fixed, weston_view_to_global_float,
     weston_view_from_global_float, weston_view_from_global, weston_view_from_global_fixed,
     weston_view_activate,
-    weston_layer_entry, weston_layer_entry_remove
+    weston_layer_entry, weston_layer_entry_insert, weston_layer_entry_remove
 };
 use wayland_sys::common::wl_fixed_t;
 use wayland_sys::server::wl_signal;
@@ -46,6 +46,7 @@ impl View {
 
 impl ViewRef {
     obj_accessors!(opt ViewRef | parent_view parent_view_mut = |&this| { (*this.as_ptr()).parent_view });
+    obj_accessors!(opt ViewRef | transform_parent transform_parent_mut = |&this| { (*this.as_ptr()).geometry.parent });
     obj_accessors!(SurfaceRef | surface surface_mut = |&this| { (*this.as_ptr()).surface });
     obj_accessors!(opt OutputRef | output output_mut = |&this| { (*this.as_ptr()).output });
     prop_accessors!(ptr weston_layer_entry | layer_link);
@@ -105,6 +106,14 @@ impl ViewRef {
         unsafe { weston_layer_entry_remove(self.layer_link()); }
     }
 
+    /// Moves this view right below `above` (into its layer).
+    pub fn place_below(&mut self, above: &ViewRef) {
+        if !self.layer_link().layer.is_null() {
+            self.layer_entry_remove();
+        }
+        unsafe { weston_layer_entry_insert(above.layer_link(), self.layer_link()); }
+    }
+
     pub fn to_global_fixed(&self, sx: wl_fixed_t, sy: wl_fixed_t) -> (wl_fixed_t, wl_fixed_t) {
         let mut x = 0;
         let mut y = 0;
@@ -0,0 +1,338 @@
+//! Running X11 applications through Xwayland.
+//!
+//! The X display sockets are opened right away, the Xwayland process is only started when the
+//! first X client connects (and again on the next connection after it exits). X windows show up
+//! as `DesktopSurfaceRef`s with the `Xwayland` role, through the same `DesktopApi` as Wayland
+//! clients (plus `DesktopApi::set_xwayland_position`).
+//!
+//! After a crash the next server is forked right away (the X module wants its pid) but held back
+//! until a timer in the compositor opens its gate, later after each crash in a row.
+
+use libc;
+use std::{ffi, mem, ptr};
+use std::time::{Duration, Instant};
+use libweston_sys::{
+    weston_compositor, weston_xwayland, weston_xwayland_api,
+    weston_plugin_api_get, weston_xwayland_module_init, weston_log,
+};
+use wayland_sys::server::{
+    wl_client, wl_event_source,
+    wl_client_create, wl_display_get_event_loop, wl_event_loop_add_signal, wl_event_source_remove,
+};
+use foreign_types::ForeignTypeRef;
+use ::compositor::CompositorRef;
+use ::timer::Timer;
+
+const_cstr!{
+    XWAYLAND_API_NAME = "weston_xwayland_v1";
+    WAYLAND_SOCKET = "WAYLAND_SOCKET";
+    ROOTLESS = "-rootless";
+    LISTEN = "-listen";
+    WM = "-wm";
+    TERMINATE = "-terminate";
+    GAVE_UP_MSG = "Xwayland keeps crashing, not starting it again\n";
+}
+
+/// A server that ran at least this long before dying doesn't count as crashing in a loop
+const STABLE_AFTER: Duration = Duration::from_secs(30);
+const FIRST_BACKOFF_MS: u32 = 500;
+const MAX_BACKOFF_MS: u32 = 30_000;
+
+#[derive(Builder)]
+pub struct XwaylandConfig {
+    /// Looked up in `PATH` unless it contains a slash
+    #[builder(default = "\"Xwayland\".into()")]
+    xserver_path: String,
+    /// Crashes in a row before X support is given up on. Restarts are delayed more after each one.
+    #[builder(default = "5")]
+    max_restarts: u32,
+}
+
+struct XwaylandState {
+    config: XwaylandConfig,
+    compositor: *mut weston_compositor,
+    api: *const weston_xwayland_api,
+    xwayland: *mut weston_xwayland,
+    client: *mut wl_client,
+    wm_fd: libc::c_int,
+    pid: libc::pid_t,
+    loaded: bool,
+    started_at: Option<Instant>,
+    crashes: u32,
+    sigusr1_source: *mut wl_event_source,
+    sigchld_source: *mut wl_event_source,
+    /// Write end of the pipe a delayed server waits on, closed to let it start
+    gate_fd: libc::c_int,
+    gate_timer: Option<Timer>,
+    /// Set when the `Xwayland` is dropped, the X module still calls `spawn_xserver`
+    defunct: bool,
+}
+
+impl XwaylandState {
+    fn backoff_ms(&self) -> u32 {
+        if self.crashes == 0 {
+            0
+        } else {
+            FIRST_BACKOFF_MS.saturating_mul(1 << (self.crashes - 1).min(16)).min(MAX_BACKOFF_MS)
+        }
+    }
+
+    fn gave_up(&self) -> bool {
+        self.defunct || self.crashes > self.config.max_restarts
+    }
+
+    fn open_gate(&mut self) {
+        if self.gate_fd < 0 {
+            return;
+        }
+        unsafe { libc::close(self.gate_fd); }
+        self.gate_fd = -1;
+        if self.pid > 0 {
+            self.started_at = Some(Instant::now());
+        }
+    }
+}
+
+unsafe fn close_pair(fds: &[libc::c_int; 2]) {
+    libc::close(fds[0]);
+    libc::close(fds[1]);
+}
+
+/// Takes connections off a listening socket without serving them
+unsafe fn reject_pending(fd: libc::c_int) {
+    let mut pfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+    while libc::poll(&mut pfd, 1, 0) > 0 && pfd.revents & libc::POLLIN != 0 {
+        let conn = libc::accept(fd, ptr::null_mut(), ptr::null_mut());
+        if conn < 0 {
+            break;
+        }
+        libc::close(conn);
+    }
+}
+
+unsafe extern "C" fn spawn_xserver(user_data: *mut libc::c_void, display: *const libc::c_char,
+                                   abstract_fd: libc::c_int, unix_fd: libc::c_int) -> libc::pid_t {
+    let state = &mut *(user_data as *mut XwaylandState);
+    if state.gave_up() {
+        // Returning -1 keeps the sockets watched, they'd wake us up forever
+        reject_pending(abstract_fd);
+        reject_pending(unix_fd);
+        return -1;
+    }
+    let mut sv = [0; 2];
+    let mut wm = [0; 2];
+    if libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0, sv.as_mut_ptr()) < 0 {
+        return -1;
+    }
+    if libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0, wm.as_mut_ptr()) < 0 {
+        close_pair(&sv);
+        return -1;
+    }
+    // Everything the child needs is allocated before forking
+    let path = match ffi::CString::new(state.config.xserver_path.clone()) {
+        Ok(path) => path,
+        Err(_) => {
+            close_pair(&sv);
+            close_pair(&wm);
+            return -1;
+        },
+    };
+    let fd_arg = |fd: libc::c_int| ffi::CString::new(fd.to_string()).expect("fd string");
+    let (wayland_socket, abstract_arg, unix_arg, wm_arg) = (fd_arg(sv[1]), fd_arg(abstract_fd), fd_arg(unix_fd), fd_arg(wm[1]));
+    let argv = [
+        path.as_ptr(), display, ROOTLESS.as_ptr(),
+        LISTEN.as_ptr(), abstract_arg.as_ptr(), LISTEN.as_ptr(), unix_arg.as_ptr(),
+        WM.as_ptr(), wm_arg.as_ptr(), TERMINATE.as_ptr(), ptr::null(),
+    ];
+    let backoff_ms = state.backoff_ms();
+    let mut gate = [-1; 2];
+    if backoff_ms > 0 && libc::pipe2(gate.as_mut_ptr(), libc::O_CLOEXEC) < 0 {
+        close_pair(&sv);
+        close_pair(&wm);
+        return -1;
+    }
+    let pid = libc::fork();
+    match pid {
+        0 => {
+            // The descriptors handed to the server have to survive exec
+            for &fd in &[sv[1], abstract_fd, unix_fd, wm[1]] {
+                libc::fcntl(fd, libc::F_SETFD, 0);
+            }
+            let mut all: libc::sigset_t = mem::zeroed();
+            libc::sigfillset(&mut all);
+            libc::sigprocmask(libc::SIG_UNBLOCK, &all, ptr::null_mut());
+            // An ignored SIGUSR1 makes the X server send one to its parent when it's ready
+            libc::signal(libc::SIGUSR1, libc::SIG_IGN);
+            libc::setenv(WAYLAND_SOCKET.as_ptr(), wayland_socket.as_ptr(), 1);
+            if backoff_ms > 0 {
+                // The X client that triggered the restart just waits a bit longer:
+                // until the compositor closes its end of the gate
+                libc::close(gate[1]);
+                let mut byte = 0u8;
+                while libc::read(gate[0], &mut byte as *mut u8 as *mut libc::c_void, 1) < 0 {}
+            }
+            libc::execvp(path.as_ptr(), argv.as_ptr());
+            libc::_exit(libc::EXIT_FAILURE);
+        },
+        -1 => {
+            close_pair(&sv);
+            close_pair(&wm);
+            if backoff_ms > 0 {
+                close_pair(&gate);
+            }
+            -1
+        },
+        _ => {
+            libc::close(sv[1]);
+            state.client = wl_client_create((*state.compositor).wl_display, sv[0]);
+            libc::close(wm[1]);
+            state.wm_fd = wm[0];
+            state.pid = pid;
+            state.loaded = false;
+            if backoff_ms > 0 {
+                libc::close(gate[0]);
+                state.gate_fd = gate[1];
+                state.started_at = None;
+                if let Some(timer) = state.gate_timer.as_mut() {
+                    timer.arm(backoff_ms);
+                }
+            } else {
+                state.started_at = Some(Instant::now());
+            }
+            pid
+        },
+    }
+}
+
+unsafe extern "C" fn handle_sigusr1(_signal: libc::c_int, data: *mut libc::c_void) -> libc::c_int {
+    let state = &mut *(data as *mut XwaylandState);
+    if state.pid > 0 && !state.loaded && !state.client.is_null() {
+        state.loaded = true;
+        if let Some(xserver_loaded) = (*state.api).xserver_loaded {
+            xserver_loaded(state.xwayland, state.client, state.wm_fd);
+        }
+    }
+    1
+}
+
+unsafe extern "C" fn handle_sigchld(_signal: libc::c_int, data: *mut libc::c_void) -> libc::c_int {
+    let state = &mut *(data as *mut XwaylandState);
+    let mut status = 0;
+    // Other children are someone else's business
+    if state.pid <= 0 || libc::waitpid(state.pid, &mut status, libc::WNOHANG) != state.pid {
+        return 1;
+    }
+    state.pid = 0;
+    state.client = ptr::null_mut();
+    state.loaded = false;
+    // Killed before its gate opened
+    if state.gate_fd >= 0 {
+        libc::close(state.gate_fd);
+        state.gate_fd = -1;
+        if let Some(timer) = state.gate_timer.as_mut() {
+            timer.disarm();
+        }
+    }
+    let clean_exit = libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0;
+    let stable = state.started_at.take().map(|t| t.elapsed() >= STABLE_AFTER).unwrap_or(false);
+    if clean_exit || stable {
+        state.crashes = 0;
+    } else {
+        state.crashes += 1;
+        if state.gave_up() {
+            weston_log(GAVE_UP_MSG.as_ptr());
+        }
+    }
+    // Tears down the window manager and listens for the next X client
+    if let Some(xserver_exited) = (*state.api).xserver_exited {
+        xserver_exited(state.xwayland, status);
+    }
+    1
+}
+
+/// The X11 window manager and the Xwayland process behind an X display.
+pub struct Xwayland {
+    // The X module can't forget `spawn_xserver` and its user data, so the state is never freed
+    state: mem::ManuallyDrop<Box<XwaylandState>>,
+}
+
+impl Xwayland {
+    /// Sets `DISPLAY` and starts listening for X clients. `None` if the X display couldn't be set up.
+    pub fn new(compositor: &mut CompositorRef, config: XwaylandConfig) -> Option<Xwayland> {
+        unsafe {
+            if weston_xwayland_module_init(compositor.as_ptr()) < 0 {
+                return None;
+            }
+            let api = weston_plugin_api_get(compositor.as_ptr(), XWAYLAND_API_NAME.as_ptr(),
+                                            mem::size_of::<weston_xwayland_api>()) as *const weston_xwayland_api;
+            if api.is_null() {
+                return None;
+            }
+            let xwayland = ((*api).get?)(compositor.as_ptr());
+            if xwayland.is_null() {
+                return None;
+            }
+            let mut state = Box::new(XwaylandState {
+                config,
+                compositor: compositor.as_ptr(),
+                api,
+                xwayland,
+                client: ptr::null_mut(),
+                wm_fd: -1,
+                pid: 0,
+                loaded: false,
+                started_at: None,
+                crashes: 0,
+                sigusr1_source: ptr::null_mut(),
+                sigchld_source: ptr::null_mut(),
+                gate_fd: -1,
+                gate_timer: None,
+                defunct: false,
+            });
+            let state_ptr = &mut *state as *mut XwaylandState;
+            state.gate_timer = Some(Timer::new(compositor, Box::new(move || (*state_ptr).open_gate())));
+            let data = state_ptr as *mut libc::c_void;
+            if ((*api).listen?)(xwayland, data, Some(spawn_xserver)) < 0 {
+                return None;
+            }
+            let event_loop = wl_display_get_event_loop((*compositor.as_ptr()).wl_display);
+            state.sigusr1_source = wl_event_loop_add_signal(event_loop, libc::SIGUSR1, handle_sigusr1, data);
+            state.sigchld_source = wl_event_loop_add_signal(event_loop, libc::SIGCHLD, handle_sigchld, data);
+            Some(Xwayland { state: mem::ManuallyDrop::new(state) })
+        }
+    }
+
+    /// Whether an Xwayland process is currently running
+    pub fn is_running(&self) -> bool {
+        self.state.pid > 0
+    }
+
+    /// The running server's Wayland client, e.g. to recognize its surfaces
+    pub fn client(&self) -> Option<*mut wl_client> {
+        if self.state.client.is_null() { None } else { Some(self.state.client) }
+    }
+
+    /// Crashes in a row so far
+    pub fn crash_count(&self) -> u32 {
+        self.state.crashes
+    }
+}
+
+impl Drop for Xwayland {
+    fn drop(&mut self) {
+        // What's left of the state only makes spawn_xserver turn X clients away
+        self.state.defunct = true;
+        self.state.gate_timer = None;
+        unsafe {
+            wl_event_source_remove(self.state.sigusr1_source);
+            wl_event_source_remove(self.state.sigchld_source);
+            if self.state.pid > 0 {
+                libc::kill(self.state.pid, libc::SIGTERM);
+            }
+            if self.state.gate_fd >= 0 {
+                libc::close(self.state.gate_fd);
+                self.state.gate_fd = -1;
+            }
+        }
+    }
+}